@@ -0,0 +1,237 @@
+use super::cpu::Quirks;
+use super::dap::Transport;
+use super::display::{self, Palette};
+use super::filter::Filter;
+use super::gpu::Present;
+use super::postfx::PostFx;
+
+const USAGE: &str = "<rom> [options]
+
+options:
+  --trace <file>           log every executed instruction to <file>
+  --trace-format <fmt>     line format of the text trace (see trace.rs)
+  --trace-binary           write a compact binary trace instead of text
+  --profile <file>         write a profile report to <file> on exit
+  --profile-folded <file>  write folded call stacks (flamegraph input) on exit
+  --cdl                    record code/data accesses in <rom>.cdl
+  --disasm                 print the disassembly of the ROM using <rom>.cdl
+  --symbols <file>         load symbol names (`<addr> <name>` or Octo labels)
+  --quirks <list>          comma separated list of enabled quirks
+  --seed <n>               seed for the RND instruction
+  --diff <list>            run a second cpu with the quirks <list> next to the
+                           first one and report the first divergence
+  --diff-cycles <n>        number of instructions to compare (default 100000)
+  --diff-traces <a> <b>    report the first divergence of two trace files
+  --fg <rrggbb>            color of set pixels (default ff0000)
+  --bg <rrggbb>            color of cleared pixels (default 000000)
+  --scale <n>              initial integer pixel scale of the game without the
+                           debug panel (default 4)
+  --window <w>x<h>         initial window size
+  --no-panel               start with the debug panel hidden ('Tab' toggles)
+  --filter <name>[:<n>]    anti-flicker filter `blend[:<frames>]`,
+                           `decay[:<kept>]`, `max2[:<prev>]` or `off`
+  --postfx <list>          comma separated post-processing effects: epx,
+                           scanlines, grid, crt
+  --present <mode>         when the shown frame is latched: `vblank`
+                           (default), `draw`, `clear` or `immediate`
+  --capture-scale <n>      pixel scale of screenshots ('F12') and recordings
+                           ('F9'), default 1
+  --record <file>          record from the start to a .gif or .y4m file, the
+                           sound of a .y4m is written to a .wav next to it
+  --tui                    run in the terminal instead of a window
+  --headless <frames>      run <frames> 60Hz frames without display and exit
+  --input <file>           keys pressed in headless mode, lines
+                           `<frame> <hex keys>`
+  --dap                    serve the Debug Adapter Protocol on stdin/stdout,
+                           <rom> is optional and used for `attach`
+  --dap-port <port>        serve the Debug Adapter Protocol on 127.0.0.1:<port>
+  --listing <file>         assembler listing map (`<addr> <file>:<line>`) for
+                           source breakpoints";
+
+pub struct Args {
+    // only optional for `--diff-traces`
+    pub rom: Option<String>,
+    pub trace: Option<String>,
+    pub trace_format: Option<String>,
+    pub trace_binary: bool,
+    pub profile: Option<String>,
+    pub profile_folded: Option<String>,
+    pub cdl: bool,
+    pub disasm: bool,
+    pub symbols: Option<String>,
+    pub quirks: Quirks,
+    pub seed: Option<u64>,
+    pub diff: Option<Quirks>,
+    pub diff_cycles: u64,
+    pub diff_traces: Option<(String, String)>,
+    pub dap: Option<Transport>,
+    pub listing: Option<String>,
+    pub palette: Palette,
+    pub scale: usize,
+    pub window: Option<(usize, usize)>,
+    pub panel: bool,
+    pub filter: Filter,
+    pub postfx: PostFx,
+    pub present: Present,
+    pub capture_scale: usize,
+    pub record: Option<String>,
+    pub tui: bool,
+    pub headless: Option<u64>,
+    pub input: Option<String>,
+}
+
+pub fn usage() -> String {
+    format!(
+        "Use as {} {}",
+        std::env::args()
+            .next()
+            .unwrap_or_else(|| "chip8-remu".to_string()),
+        USAGE
+    )
+}
+
+pub fn parse() -> Result<Args, String> {
+    parse_from(std::env::args().skip(1))
+}
+
+pub fn parse_from<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut rom = None;
+    let mut trace = None;
+    let mut trace_format = None;
+    let mut trace_binary = false;
+    let mut profile = None;
+    let mut profile_folded = None;
+    let mut cdl = false;
+    let mut disasm = false;
+    let mut symbols = None;
+    let mut quirks = Quirks::default();
+    let mut seed = None;
+    let mut diff = None;
+    let mut diff_cycles = 100_000;
+    let mut diff_traces = None;
+    let mut dap = None;
+    let mut listing = None;
+    let mut palette = Palette::default();
+    let mut scale = 4;
+    let mut window = None;
+    let mut panel = true;
+    let mut filter = Filter::new();
+    let mut postfx = PostFx::default();
+    let mut present = Present::default();
+    let mut capture_scale = 1;
+    let mut record = None;
+    let mut tui = false;
+    let mut headless = None;
+    let mut input = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = Some(value(&arg, args.next())?),
+            "--trace-format" => trace_format = Some(value(&arg, args.next())?),
+            "--trace-binary" => trace_binary = true,
+            "--profile" => profile = Some(value(&arg, args.next())?),
+            "--profile-folded" => profile_folded = Some(value(&arg, args.next())?),
+            "--cdl" => cdl = true,
+            "--disasm" => disasm = true,
+            "--symbols" => symbols = Some(value(&arg, args.next())?),
+            "--quirks" => quirks = Quirks::parse(&value(&arg, args.next())?)?,
+            "--seed" => seed = Some(number(&arg, args.next())?),
+            "--diff" => diff = Some(Quirks::parse(&value(&arg, args.next())?)?),
+            "--diff-cycles" => diff_cycles = number(&arg, args.next())?,
+            "--diff-traces" => {
+                let a = value(&arg, args.next())?;
+                diff_traces = Some((a, value(&arg, args.next())?));
+            }
+            "--dap" => dap = Some(Transport::Stdio),
+            "--dap-port" => {
+                let port = number(&arg, args.next())?;
+                if port > u16::MAX as u64 {
+                    return Err(format!("Invalid port {}", port));
+                }
+                dap = Some(Transport::Tcp(port as u16));
+            }
+            "--listing" => listing = Some(value(&arg, args.next())?),
+            "--fg" => palette.fg = display::parse_color(&value(&arg, args.next())?)?,
+            "--bg" => palette.bg = display::parse_color(&value(&arg, args.next())?)?,
+            "--scale" => match number(&arg, args.next())? {
+                n @ 1..=32 => scale = n as usize,
+                n => return Err(format!("Invalid scale {}, expected 1 to 32", n)),
+            },
+            "--window" => window = Some(size(&value(&arg, args.next())?)?),
+            "--no-panel" => panel = false,
+            "--filter" => filter = Filter::parse(&value(&arg, args.next())?)?,
+            "--postfx" => postfx = PostFx::parse(&value(&arg, args.next())?)?,
+            "--present" => present = Present::parse(&value(&arg, args.next())?)?,
+            "--capture-scale" => match number(&arg, args.next())? {
+                n @ 1..=32 => capture_scale = n as usize,
+                n => return Err(format!("Invalid scale {}, expected 1 to 32", n)),
+            },
+            "--record" => record = Some(value(&arg, args.next())?),
+            "--tui" => tui = true,
+            "--headless" => headless = Some(number(&arg, args.next())?),
+            "--input" => input = Some(value(&arg, args.next())?),
+            "-h" | "--help" => return Err(usage()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    if rom.is_none() && diff_traces.is_none() && dap.is_none() {
+        return Err(usage());
+    }
+
+    Ok(Args {
+        rom,
+        trace,
+        trace_format,
+        trace_binary,
+        profile,
+        profile_folded,
+        cdl,
+        disasm,
+        symbols,
+        quirks,
+        seed,
+        diff,
+        diff_cycles,
+        diff_traces,
+        dap,
+        listing,
+        palette,
+        scale,
+        window,
+        panel,
+        filter,
+        postfx,
+        present,
+        capture_scale,
+        record,
+        tui,
+        headless,
+        input,
+    })
+}
+
+fn value(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("Option '{}' requires a value", option))
+}
+
+// parse `<w>x<h>`
+fn size(val: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("Invalid window size '{}', expected <w>x<h>", val);
+    let x = val.find('x').ok_or_else(invalid)?;
+    match (val[..x].parse::<usize>(), val[x + 1..].parse::<usize>()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(invalid()),
+    }
+}
+
+fn number(option: &str, val: Option<String>) -> Result<u64, String> {
+    let val = value(option, val)?;
+    let res = match val.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => val.parse::<u64>(),
+    };
+    res.map_err(|_| format!("Option '{}' expects a number, got '{}'", option, val))
+}
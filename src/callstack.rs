@@ -0,0 +1,94 @@
+// the original interpreter provides 16 stack entries
+pub const MAX_DEPTH: usize = 16;
+
+#[derive(PartialEq, Debug)]
+pub struct Frame {
+    // address of the CALL instruction
+    pub caller: u16,
+    // called routine, None if there is no CALL at `caller`
+    pub callee: Option<u16>,
+    // return address as stored on the stack
+    pub ret: u16,
+    pub warning: Option<&'static str>,
+}
+
+// reconstruct the call frames from the return addresses on the stack, the
+// outermost frame comes first
+pub fn frames(stack: &[u16], mem: &[u8]) -> Vec<Frame> {
+    stack
+        .iter()
+        .enumerate()
+        .map(|(depth, &ret)| {
+            let caller = ret.wrapping_sub(2);
+            // decode CALL (2NNN) directly, any other opcode is suspicious
+            let callee = match mem.get(caller as usize..caller as usize + 2) {
+                Some(&[hi, lo]) if hi & 0xf0 == 0x20 => Some(u16::from_be_bytes([hi, lo]) & 0x0fff),
+                _ => None,
+            };
+
+            let warning = if callee.is_none() {
+                Some("no CALL at caller")
+            } else if depth >= MAX_DEPTH {
+                Some("depth > 16")
+            } else if ret < 0x200 {
+                Some("return below 0x200")
+            } else {
+                None
+            };
+
+            Frame {
+                caller,
+                callee,
+                ret,
+                warning,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frames_valid() {
+        let mut mem = vec![0u8; 4096];
+        // 0200: CALL 0300, 0300: CALL 0400
+        mem[0x200..0x202].copy_from_slice(&[0x23, 0x00]);
+        mem[0x300..0x302].copy_from_slice(&[0x24, 0x00]);
+
+        assert_eq!(
+            frames(&[0x202, 0x302], &mem),
+            vec![
+                Frame {
+                    caller: 0x200,
+                    callee: Some(0x300),
+                    ret: 0x202,
+                    warning: None,
+                },
+                Frame {
+                    caller: 0x300,
+                    callee: Some(0x400),
+                    ret: 0x302,
+                    warning: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_suspicious() {
+        let mut mem = vec![0u8; 4096];
+        mem[0x200..0x202].copy_from_slice(&[0x23, 0x00]);
+        // LD V0, 00 is no CALL
+        mem[0x300..0x302].copy_from_slice(&[0x60, 0x00]);
+
+        let f = frames(&[0x302], &mem);
+        assert_eq!(f[0].callee, None);
+        assert_eq!(f[0].warning, Some("no CALL at caller"));
+
+        let f = frames(&[0x202; 17], &mem);
+        assert_eq!(f[15].warning, None);
+        assert_eq!(f[16].warning, Some("depth > 16"));
+    }
+}
@@ -0,0 +1,527 @@
+use super::cpu::TIMER_HZ;
+use super::display::{Image, Palette};
+use super::gpu::Plane;
+use super::tone::{self, SAMPLE_RATE};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// frames per second of the emulated timeline, one frame per timer tick
+const FPS: u64 = TIMER_HZ;
+
+// framebuffer in the palette colors, scaled by `scale`
+pub fn image(fb: &Plane, palette: Palette, scale: usize) -> Image {
+    let levels: Vec<f32> = fb.pixels().map(|on| if on { 1.0 } else { 0.0 }).collect();
+    super::display::render(&levels, palette, scale)
+}
+
+pub fn screenshot<P: AsRef<Path>>(
+    path: P,
+    fb: &Plane,
+    palette: Palette,
+    scale: usize,
+) -> Result<(), String> {
+    let png = encode_png(&image(fb, palette, scale));
+    std::fs::write(&path, png)
+        .map_err(|e| format!("Failed to write {}: {}", path.as_ref().display(), e))
+}
+
+// first `<prefix>-<n>.<ext>` which does not exist yet
+pub fn free_path(prefix: &str, ext: &str) -> String {
+    (1..)
+        .map(|n| format!("{}-{}.{}", prefix, n, ext))
+        .find(|p| !Path::new(p).exists())
+        .unwrap()
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &d in data {
+        a = (a + d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// 8 bit RGB png, the image data is stored without compression (deflate
+// stored blocks) which keeps the encoder small
+pub fn encode_png(img: &Image) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((img.width * 3 + 1) * img.height);
+    for row in img.pixels.chunks(img.width) {
+        // filter type none
+        raw.push(0);
+        for &p in row {
+            raw.extend_from_slice(&[(p >> 16) as u8, (p >> 8) as u8, p as u8]);
+        }
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&(img.width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(img.height as u32).to_be_bytes());
+    // bit depth 8, color type RGB, deflate, no filter, no interlace
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut out = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    png_chunk(&mut out, b"IHDR", &ihdr);
+    png_chunk(&mut out, b"IDAT", &zlib);
+    png_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+// variable width LZW of the GIF format, codes packed LSB first
+fn lzw(indices: &[u8], min_size: u32) -> Vec<u8> {
+    let clear = 1u16 << min_size;
+    let mut out = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0u32);
+    let mut size = min_size + 1;
+    let mut emit = |code: u16, size: u32| {
+        acc |= (code as u32) << bits;
+        bits += size;
+        while bits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            bits -= 8;
+        }
+    };
+
+    let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = clear + 2;
+    emit(clear, size);
+    let mut codes = indices.iter();
+    if let Some(&first) = codes.next() {
+        let mut prefix = first as u16;
+        for &k in codes {
+            if let Some(&code) = dict.get(&(prefix, k)) {
+                prefix = code;
+                continue;
+            }
+            emit(prefix, size);
+            if next < 4096 {
+                dict.insert((prefix, k), next);
+                next += 1;
+                if next > 1 << size && size < 12 {
+                    size += 1;
+                }
+            } else {
+                emit(clear, size);
+                dict.clear();
+                next = clear + 2;
+                size = min_size + 1;
+            }
+            prefix = k as u16;
+        }
+        emit(prefix, size);
+    }
+    emit(clear + 1, size);
+    if bits > 0 {
+        out.push(acc as u8);
+    }
+    out
+}
+
+// GIF delays are in 1/100s, rounding the start and end of every frame keeps
+// the total length exact
+fn centis(frame: u64) -> u64 {
+    (frame * 100 + FPS / 2) / FPS
+}
+
+// animated two color GIF, identical frames are merged into one with a longer
+// delay
+pub struct Gif<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    // frame waiting for its delay and the frame number it started at
+    pending: Option<(Vec<u8>, u64)>,
+    frames: u64,
+}
+
+impl<W: Write> Gif<W> {
+    pub fn new(
+        mut out: W,
+        width: usize,
+        height: usize,
+        palette: Palette,
+    ) -> Result<Gif<W>, String> {
+        let mut header = b"GIF89a".to_vec();
+        header.extend_from_slice(&(width as u16).to_le_bytes());
+        header.extend_from_slice(&(height as u16).to_le_bytes());
+        // global color table with 2 entries
+        header.extend_from_slice(&[0x80, 0, 0]);
+        for &c in &[palette.bg, palette.fg] {
+            header.extend_from_slice(&[(c >> 16) as u8, (c >> 8) as u8, c as u8]);
+        }
+        // loop forever
+        header.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+        out.write_all(&header).map_err(|e| e.to_string())?;
+        Ok(Gif {
+            out,
+            width,
+            height,
+            pending: None,
+            frames: 0,
+        })
+    }
+
+    // `indices` holds 0 (bg) or 1 (fg) per pixel
+    pub fn frame(&mut self, indices: Vec<u8>) -> Result<(), String> {
+        self.frames += 1;
+        match self.pending {
+            Some((ref pixels, _)) if *pixels == indices => Ok(()),
+            // browsers slow down delays below 2/100s, such a short frame is
+            // replaced by the next one to keep the timeline exact
+            Some((_, start)) if centis(self.frames - 1) - centis(start) < 2 => {
+                self.pending = Some((indices, start));
+                Ok(())
+            }
+            _ => {
+                self.flush(self.frames - 1)?;
+                self.pending = Some((indices, self.frames - 1));
+                Ok(())
+            }
+        }
+    }
+
+    // write the pending frame which is shown until frame number `end`
+    fn flush(&mut self, end: u64) -> Result<(), String> {
+        let (pixels, start) = match self.pending.take() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let delay = centis(end) - centis(start);
+
+        let mut data = vec![0x21, 0xf9, 4, 0];
+        data.extend_from_slice(&(delay as u16).to_le_bytes());
+        data.extend_from_slice(&[0, 0, 0x2c, 0, 0, 0, 0]);
+        data.extend_from_slice(&(self.width as u16).to_le_bytes());
+        data.extend_from_slice(&(self.height as u16).to_le_bytes());
+        data.extend_from_slice(&[0, 2]);
+        for block in lzw(&pixels, 2).chunks(255) {
+            data.push(block.len() as u8);
+            data.extend_from_slice(block);
+        }
+        data.push(0);
+        self.out.write_all(&data).map_err(|e| e.to_string())
+    }
+
+    pub fn finish(mut self) -> Result<W, String> {
+        self.flush(self.frames)?;
+        self.out.write_all(&[0x3b]).map_err(|e| e.to_string())?;
+        self.out.flush().map_err(|e| e.to_string())?;
+        Ok(self.out)
+    }
+}
+
+// 16 bit mono PCM, the sound timer plays a square wave
+pub struct Wav<W: Write + Seek> {
+    out: W,
+    samples: u64,
+}
+
+impl<W: Write + Seek> Wav<W> {
+    pub fn new(mut out: W) -> Result<Wav<W>, String> {
+        let mut header = Vec::new();
+        header.extend_from_slice(b"RIFF\0\0\0\0WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, mono
+        header.extend_from_slice(&[1, 0, 1, 0]);
+        header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        header.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+        // block align, bits per sample
+        header.extend_from_slice(&[2, 0, 16, 0]);
+        header.extend_from_slice(b"data\0\0\0\0");
+        out.write_all(&header).map_err(|e| e.to_string())?;
+        Ok(Wav { out, samples: 0 })
+    }
+
+    // samples of one frame, a tone if the sound timer is active
+    pub fn frame(&mut self, sound: bool) -> Result<(), String> {
+        let n = SAMPLE_RATE as u64 / FPS;
+        let mut data = Vec::with_capacity(n as usize * 2);
+        for s in self.samples..self.samples + n {
+            data.extend_from_slice(&tone::sample(s, sound).to_le_bytes());
+        }
+        self.samples += n;
+        self.out.write_all(&data).map_err(|e| e.to_string())
+    }
+
+    pub fn finish(mut self) -> Result<W, String> {
+        let bytes = (self.samples * 2) as u32;
+        let mut patch = |pos: u64, val: u32| {
+            self.out.seek(SeekFrom::Start(pos))?;
+            self.out.write_all(&val.to_le_bytes())
+        };
+        patch(4, 36 + bytes).map_err(|e| e.to_string())?;
+        patch(40, bytes).map_err(|e| e.to_string())?;
+        self.out.flush().map_err(|e| e.to_string())?;
+        Ok(self.out)
+    }
+}
+
+// YUV 4:4:4 frame of a Y4M stream (BT.601, limited range)
+fn y4m_frame(img: &Image) -> Vec<u8> {
+    let n = img.pixels.len();
+    let mut data = b"FRAME\n".to_vec();
+    data.resize(6 + 3 * n, 0);
+    for (i, &p) in img.pixels.iter().enumerate() {
+        let (r, g, b) = (
+            (p >> 16 & 0xff) as i32,
+            (p >> 8 & 0xff) as i32,
+            (p & 0xff) as i32,
+        );
+        data[6 + i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        data[6 + n + i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        data[6 + 2 * n + i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+    data
+}
+
+enum Sink {
+    Gif(Gif<BufWriter<File>>),
+    // raw video with the sound in a wav file next to it
+    Y4m(BufWriter<File>, Wav<BufWriter<File>>),
+}
+
+// records one frame per timer tick, the format is chosen by the extension of
+// the path: `.gif` or `.y4m` (plus `.wav`)
+pub struct Recorder {
+    sink: Sink,
+    path: String,
+    palette: Palette,
+    scale: usize,
+    frames: u64,
+}
+
+impl Recorder {
+    pub fn start(path: &str, palette: Palette, scale: usize) -> Result<Recorder, String> {
+        let create = |p: &Path| {
+            File::create(p)
+                .map(BufWriter::new)
+                .map_err(|e| format!("Failed to create {}: {}", p.display(), e))
+        };
+        let (width, height) = (super::gpu::WIDTH * scale, super::gpu::HEIGHT * scale);
+        let sink = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("gif") => Sink::Gif(Gif::new(create(Path::new(path))?, width, height, palette)?),
+            Some("y4m") => {
+                let mut video = create(Path::new(path))?;
+                writeln!(
+                    video,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                    width, height, FPS
+                )
+                .map_err(|e| e.to_string())?;
+                let wav = Wav::new(create(&Path::new(path).with_extension("wav"))?)?;
+                Sink::Y4m(video, wav)
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown recording format '{}', use .gif or .y4m",
+                    path
+                ))
+            }
+        };
+        Ok(Recorder {
+            sink,
+            path: path.to_string(),
+            palette,
+            scale,
+            frames: 0,
+        })
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn frame(&mut self, fb: &Plane, sound: bool) -> Result<(), String> {
+        self.frames += 1;
+        match self.sink {
+            Sink::Gif(ref mut gif) => {
+                let img = image(fb, Palette { fg: 1, bg: 0 }, self.scale);
+                gif.frame(img.pixels.iter().map(|&p| p as u8).collect())
+            }
+            Sink::Y4m(ref mut video, ref mut wav) => {
+                let frame = y4m_frame(&image(fb, self.palette, self.scale));
+                video.write_all(&frame).map_err(|e| e.to_string())?;
+                wav.frame(sound)
+            }
+        }
+    }
+
+    // close the files, returns the number of recorded frames
+    pub fn finish(self) -> Result<u64, String> {
+        let Recorder {
+            sink, path, frames, ..
+        } = self;
+        match sink {
+            Sink::Gif(gif) => gif.finish().map(|_| ()),
+            Sink::Y4m(mut video, wav) => {
+                video.flush().map_err(|e| e.to_string())?;
+                wav.finish().map(|_| ())
+            }
+        }
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    // GIF LZW decoder to check the encoder
+    fn unlzw(data: &[u8], min_size: u32) -> Vec<u8> {
+        let clear = 1u16 << min_size;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..clear + 2).map(|i| vec![i as u8]).collect();
+        };
+        reset(&mut table);
+        let (mut size, mut pos) = (min_size + 1, 0usize);
+        let mut out = Vec::new();
+        let mut prev: Option<Vec<u8>> = None;
+        loop {
+            let mut code = 0usize;
+            for i in 0..size as usize {
+                let bit = data[(pos + i) / 8] >> ((pos + i) % 8) & 1;
+                code |= (bit as usize) << i;
+            }
+            pos += size as usize;
+            if code == clear as usize {
+                reset(&mut table);
+                size = min_size + 1;
+                prev = None;
+                continue;
+            } else if code == clear as usize + 1 {
+                return out;
+            }
+            let entry = match table.get(code) {
+                Some(e) => e.clone(),
+                None => {
+                    let p = prev.clone().unwrap();
+                    let mut e = p.clone();
+                    e.push(p[0]);
+                    e
+                }
+            };
+            out.extend_from_slice(&entry);
+            if let Some(p) = prev {
+                if table.len() < 4096 {
+                    let mut e = p;
+                    e.push(entry[0]);
+                    table.push(e);
+                    if table.len() == 1 << size && size < 12 {
+                        size += 1;
+                    }
+                }
+            }
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_roundtrip() {
+        let mut seed = 7u32;
+        let noise: Vec<u8> = (0..20000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16 & 3 == 0) as u8
+            })
+            .collect();
+        for data in &[vec![], vec![1], vec![0; 5000], noise] {
+            assert_eq!(&unlzw(&lzw(data, 2), 2), data);
+        }
+    }
+
+    #[test]
+    fn png_format() {
+        let img = Image::new(3, 2, 0x123456);
+        let png = encode_png(&img);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 3, 0, 0, 0, 2]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    // delays of the graphic control extensions
+    fn delays_of(gif: &[u8]) -> Vec<u16> {
+        gif.windows(4)
+            .enumerate()
+            .filter(|(_, w)| w[..3] == [0x21, 0xf9, 4])
+            .map(|(i, _)| u16::from_le_bytes([gif[i + 4], gif[i + 5]]))
+            .collect()
+    }
+
+    #[test]
+    fn frame_timing() {
+        // one second at 60Hz is 100/100s, split into three equal frames
+        let mut gif = Gif::new(Vec::new(), 1, 1, Palette::default()).unwrap();
+        for f in 0..60 {
+            gif.frame(vec![(f / 20) as u8 % 2]).unwrap();
+        }
+        let data = gif.finish().unwrap();
+        assert_eq!(delays_of(&data), vec![33, 34, 33]);
+
+        // flicker at 60Hz is shown at 30Hz
+        let mut gif = Gif::new(Vec::new(), 1, 1, Palette::default()).unwrap();
+        for f in 0..60 {
+            gif.frame(vec![f as u8 % 2]).unwrap();
+        }
+        let data = gif.finish().unwrap();
+        let delays = delays_of(&data);
+        assert_eq!(delays.iter().sum::<u16>(), 100);
+        assert!(delays.iter().all(|&d| d >= 2));
+
+        let mut wav = Wav::new(Cursor::new(Vec::new())).unwrap();
+        wav.frame(false).unwrap();
+        wav.frame(true).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+        assert_eq!(data.len(), 44 + 2 * 800 * 2);
+        assert_eq!(&data[40..44], &3200u32.to_le_bytes());
+        assert_eq!(&data[44..46], &[0, 0]);
+        assert_eq!(&data[44 + 1600..46 + 1600], &tone::AMPLITUDE.to_le_bytes());
+    }
+}
@@ -0,0 +1,170 @@
+use super::cpu::{AccessKind, MemAccess};
+use super::decoder;
+use super::memory::MEM_SIZE;
+use super::symbols::Symbols;
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+
+// flags per memory address
+pub const CODE: u8 = 0x01;
+pub const DATA: u8 = 0x02;
+pub const WRITTEN: u8 = 0x04;
+
+// code/data log, records per address how it was accessed
+//
+// the log is stored as one flag byte per address and accumulates over
+// multiple sessions
+pub struct CodeDataLog {
+    flags: Vec<u8>,
+}
+
+// `<rom>.cdl` next to the ROM file
+pub fn sidecar_path(rom: &str) -> String {
+    format!("{}.cdl", rom)
+}
+
+impl CodeDataLog {
+    pub fn new() -> CodeDataLog {
+        CodeDataLog {
+            flags: vec![0; MEM_SIZE],
+        }
+    }
+
+    // load a previously saved log, a missing file results in an empty log
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CodeDataLog, String> {
+        let mut data = Vec::new();
+        match File::open(&path).and_then(|mut f| f.read_to_end(&mut data)) {
+            Ok(_) if data.len() == MEM_SIZE => Ok(CodeDataLog { flags: data }),
+            Ok(_) => Err(format!(
+                "{} is no code/data log ({} bytes)",
+                path.as_ref().display(),
+                data.len()
+            )),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(CodeDataLog::new()),
+            Err(e) => Err(format!("Failed to read {}: {}", path.as_ref().display(), e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        File::create(&path)
+            .and_then(|mut f| f.write_all(&self.flags))
+            .map_err(|e| format!("Failed to write {}: {}", path.as_ref().display(), e))
+    }
+
+    // account the memory accesses of one executed instruction
+    pub fn record(&mut self, accesses: &[MemAccess]) {
+        for a in accesses {
+            self.flags[a.addr as usize % MEM_SIZE] |= match a.kind {
+                AccessKind::Fetch => CODE,
+                AccessKind::Read => DATA,
+                AccessKind::Write => WRITTEN,
+            };
+        }
+    }
+
+    pub fn get(&self, addr: u16) -> u8 {
+        self.flags[addr as usize % MEM_SIZE]
+    }
+
+    // address only accessed as data so far
+    pub fn is_data(&self, addr: u16) -> bool {
+        let flags = self.get(addr);
+        flags & CODE == 0 && flags & (DATA | WRITTEN) != 0
+    }
+
+    // disassembly of `mem[start..end]`, addresses fetched as opcode are shown
+    // as instructions, data is shown byte wise as bitmap and bytes never
+    // accessed are disassembled as instructions marked with `?`, symbols are
+    // shown as labels
+    pub fn listing(&self, mem: &[u8], start: u16, end: u16, symbols: &Symbols) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut addr = start;
+
+        while addr < end {
+            if let Some(name) = symbols.get(addr) {
+                lines.push(format!("{}:", name));
+            }
+            let byte = mem[addr as usize % MEM_SIZE];
+            let flags = self.get(addr);
+
+            if flags & CODE == 0 && (flags != 0 || self.get(addr + 1) & CODE != 0) {
+                let bitmap: String = (0..8)
+                    .map(|col| if byte & (0x80 >> col) != 0 { '#' } else { '.' })
+                    .collect();
+                lines.push(format!(
+                    "{:04x}: {:02x}    {} DB {:02x}  ; {}",
+                    addr,
+                    byte,
+                    if flags & WRITTEN != 0 { "w" } else { " " },
+                    byte,
+                    bitmap
+                ));
+                addr += 1;
+            } else {
+                let opcode = u16::from_be_bytes([byte, mem[(addr as usize + 1) % MEM_SIZE]]);
+                lines.push(format!(
+                    "{:04x}: {:04x}  {} {}",
+                    addr,
+                    opcode,
+                    if flags & CODE != 0 { " " } else { "?" },
+                    decoder::disassemble_with(opcode, |a| symbols.format_addr(a))
+                ));
+                addr += 2;
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn access(addr: u16, kind: AccessKind) -> MemAccess {
+        MemAccess {
+            addr,
+            kind,
+            value: 0,
+            old: 0,
+        }
+    }
+
+    #[test]
+    fn record() {
+        let mut cdl = CodeDataLog::new();
+        cdl.record(&[
+            access(0x200, AccessKind::Fetch),
+            access(0x201, AccessKind::Fetch),
+            access(0x300, AccessKind::Read),
+        ]);
+        cdl.record(&[access(0x300, AccessKind::Write)]);
+
+        assert_eq!(cdl.get(0x200), CODE);
+        assert_eq!(cdl.get(0x300), DATA | WRITTEN);
+        assert!(cdl.is_data(0x300));
+        assert!(!cdl.is_data(0x200));
+        assert!(!cdl.is_data(0x202));
+    }
+
+    #[test]
+    fn listing() {
+        let mut cdl = CodeDataLog::new();
+        cdl.record(&[
+            access(0x0, AccessKind::Fetch),
+            access(0x1, AccessKind::Fetch),
+            access(0x2, AccessKind::Read),
+        ]);
+        let mem = [0x00, 0xe0, 0xf0, 0x12, 0x00];
+
+        assert_eq!(
+            cdl.listing(&mem, 0, 5, &Symbols::new()),
+            vec![
+                "0000: 00e0    CLS",
+                "0002: f0      DB f0  ; ####....",
+                "0003: 1200  ? JP 0200",
+            ]
+        );
+    }
+}
@@ -1,8 +1,13 @@
 use super::decoder;
 use super::gpu;
 use super::memory;
+use super::symbols::Symbols;
 
-const PROGRAM_START: u16 = 0x200;
+pub const PROGRAM_START: u16 = 0x200;
+// the timers tick at 60Hz, the frontends show a frame per tick
+pub const TIMER_HZ: u64 = 60;
+// instructions per timer tick
+pub const INSTR_PER_TICK: u64 = 8;
 
 #[derive(PartialEq)]
 enum PCOp {
@@ -12,7 +17,165 @@ enum PCOp {
     JumpAddr(u16),
 }
 
+// plain copy of the register file, used to compare state before and after
+// executing an instruction
 #[allow(non_snake_case)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Registers {
+    pub V: [u8; 16],
+    pub I: u16,
+    pub DT: u8,
+    pub ST: u8,
+    pub PC: u16,
+    // stack depth
+    pub SP: usize,
+}
+
+// snapshot of the complete cpu state for frontends, debuggers and tests
+#[allow(non_snake_case)]
+#[derive(Clone, PartialEq, Debug)]
+pub struct CpuState {
+    pub V: [u8; 16],
+    pub I: u16,
+    pub DT: u8,
+    pub ST: u8,
+    pub PC: u16,
+    // return addresses, the most recent call last
+    pub stack: Vec<u16>,
+    // number of executed instructions
+    pub cycles: u64,
+}
+
+impl CpuState {
+    // text lines shown in the debug panel, `reg_view` relies on the fixed
+    // position of every register
+    pub fn format(&self, symbols: &Symbols) -> Vec<String> {
+        // append the symbolic name of addresses that have one nearby
+        let sym = |addr: u16| match symbols.describe(addr) {
+            Some(name) => format!(" {}", name),
+            None => String::new(),
+        };
+        let mut state = Vec::new();
+        state.push(format!("---- CPU STATE ----"));
+        for i in 0..4 {
+            let i = 4 * i;
+            state.push(format!(
+                "V{:X}: {:02X}   V{:X}: {:02X}  V{:X}: {:02X}  V{:X}: {:02X}",
+                i,
+                self.V[i],
+                i + 1,
+                self.V[i + 1],
+                i + 2,
+                self.V[i + 2],
+                i + 3,
+                self.V[i + 3]
+            ));
+        }
+        state.push(format!("DT: {:02X}   ST: {:02X}", self.DT, self.ST));
+        state.push(format!("I : {:04X}{}", self.I, sym(self.I)));
+        state.push(format!("PC: {:04X}{}", self.PC, sym(self.PC)));
+        for (i, &val) in self.stack.iter().rev().enumerate() {
+            if i == 0 {
+                state.push(format!("SP: {:04X}{}", val, sym(val)));
+            } else {
+                state.push(format!("    {:04X}{}", val, sym(val)));
+            }
+        }
+        state
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AccessKind {
+    // opcode fetch
+    Fetch,
+    // data read, e.g. FX65 or sprite data of DXYN
+    Read,
+    Write,
+}
+
+// memory access of an executed instruction
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MemAccess {
+    pub addr: u16,
+    pub kind: AccessKind,
+    // value read or written
+    pub value: u8,
+    // value before a write, equal to `value` for reads
+    pub old: u8,
+}
+
+// behaviour which differs between CHIP-8 interpreters, the default matches
+// this emulator's original behaviour
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY and store the result in VX
+    pub shift_vy: bool,
+    // FX55/FX65 increment I by X + 1
+    pub load_store_inc_i: bool,
+    // BXNN jumps to XNN + VX instead of NNN + V0
+    pub jump_vx: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    // DXYN clips sprites at the right/bottom edge instead of wrapping them
+    // around, the start position wraps either way
+    pub clip_x: bool,
+    pub clip_y: bool,
+}
+
+impl Quirks {
+    pub const NAMES: [&'static str; 7] = [
+        "shift-vy",
+        "load-store-inc-i",
+        "jump-vx",
+        "vf-reset",
+        "clip-x",
+        "clip-y",
+        "clip",
+    ];
+
+    // parse a comma separated list of quirk names, `none` enables no quirk
+    pub fn parse(list: &str) -> Result<Quirks, String> {
+        let mut quirks = Quirks::default();
+        for name in list.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+            match name {
+                "none" => {}
+                "shift-vy" => quirks.shift_vy = true,
+                "load-store-inc-i" => quirks.load_store_inc_i = true,
+                "jump-vx" => quirks.jump_vx = true,
+                "vf-reset" => quirks.vf_reset = true,
+                "clip-x" => quirks.clip_x = true,
+                "clip-y" => quirks.clip_y = true,
+                "clip" => {
+                    quirks.clip_x = true;
+                    quirks.clip_y = true;
+                }
+                _ => {
+                    return Err(format!(
+                        "Unknown quirk '{}', available: {}",
+                        name,
+                        Quirks::NAMES.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+// register state restored when stepping backwards, memory and framebuffer
+// changes are undone separately
+#[derive(Clone)]
+pub struct Checkpoint {
+    pub regs: Registers,
+    stack: Vec<u16>,
+    prev_pc: u16,
+    // executed instructions before this state
+    pub cycles: u64,
+}
+
+#[allow(non_snake_case)]
+#[derive(Clone)]
 pub struct Cpu {
     V: [u8; 16],
     I: u16,
@@ -22,6 +185,15 @@ pub struct Cpu {
     // stack is only used to push/pop PC on call/ret
     SP: Vec<u16>,
     prev_PC: u16,
+    // sound timer before the last executed instruction
+    prev_ST: u8,
+    // number of executed instructions
+    cycles: u64,
+    quirks: Quirks,
+    // splitmix64 state of RND, small enough to be saved with the machine
+    rng: u64,
+    // memory accesses of the last executed instruction
+    accesses: Vec<MemAccess>,
 
     ram: memory::Memory,
     gpu: gpu::Gpu,
@@ -37,13 +209,178 @@ impl Cpu {
             PC: PROGRAM_START,
             SP: Vec::with_capacity(16),
             prev_PC: 0,
+            prev_ST: 0,
+            cycles: 0,
+            quirks: Quirks::default(),
+            rng: rand::random(),
+            accesses: Vec::with_capacity(16),
             ram: ram,
             gpu: gpu,
         }
     }
 
-    pub fn get_fb(&self) -> &[bool] {
-        self.gpu.as_ref()
+    pub fn get_fb(&self) -> &gpu::Plane {
+        self.gpu.get_fb()
+    }
+
+    // last complete frame, see `gpu::Present`
+    pub fn get_frame(&self) -> &gpu::Plane {
+        self.gpu.get_frame()
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.gpu.set_clipping(quirks.clip_x, quirks.clip_y);
+    }
+
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // make RND reproducible, the seed is the state of the generator
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = seed;
+    }
+
+    // state of the RND generator, `seed_rng` with it continues the sequence
+    pub fn get_rng_state(&self) -> u64 {
+        self.rng
+    }
+
+    // next number of the splitmix64 sequence
+    fn random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn get_mem(&self) -> &[u8] {
+        self.ram.as_ref()
+    }
+
+    // write memory from outside of the program, e.g. a debugger
+    pub fn write_mem(&mut self, addr: u16, value: u8) {
+        self.ram.write_byte(addr, value);
+    }
+
+    // return addresses, the most recent call last
+    pub fn get_stack(&self) -> &[u16] {
+        &self.SP
+    }
+
+    pub fn get_accesses(&self) -> &[MemAccess] {
+        &self.accesses
+    }
+
+    pub fn get_prev_st(&self) -> u8 {
+        self.prev_ST
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn get_registers(&self) -> Registers {
+        Registers {
+            V: self.V,
+            I: self.I,
+            DT: self.DT,
+            ST: self.ST,
+            PC: self.PC,
+            SP: self.SP.len(),
+        }
+    }
+
+    pub fn get_state(&self) -> CpuState {
+        CpuState {
+            V: self.V,
+            I: self.I,
+            DT: self.DT,
+            ST: self.ST,
+            PC: self.PC,
+            stack: self.SP.clone(),
+            cycles: self.cycles,
+        }
+    }
+
+    pub fn set_state(&mut self, state: &CpuState) {
+        self.V = state.V;
+        self.I = state.I;
+        self.DT = state.DT;
+        self.ST = state.ST;
+        self.set_pc(state.PC);
+        self.SP = state.stack.clone();
+        self.cycles = state.cycles;
+    }
+
+    pub fn set_v(&mut self, x: usize, value: u8) {
+        self.V[x] = value;
+    }
+
+    pub fn set_i(&mut self, value: u16) {
+        self.I = value;
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.PC = value;
+        // the stuck detection compares against the previous PC
+        self.prev_PC = value.wrapping_sub(2);
+    }
+
+    pub fn set_dt(&mut self, value: u8) {
+        self.DT = value;
+    }
+
+    pub fn set_st(&mut self, value: u8) {
+        self.ST = value;
+    }
+
+    pub fn set_stack(&mut self, stack: &[u16]) {
+        self.SP = stack.to_vec();
+    }
+
+    pub fn get_ram(&self) -> &memory::Memory {
+        &self.ram
+    }
+
+    pub fn get_ram_mut(&mut self) -> &mut memory::Memory {
+        &mut self.ram
+    }
+
+    pub fn get_gpu(&self) -> &gpu::Gpu {
+        &self.gpu
+    }
+
+    pub fn get_gpu_mut(&mut self) -> &mut gpu::Gpu {
+        &mut self.gpu
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            regs: self.get_registers(),
+            stack: self.SP.clone(),
+            prev_pc: self.prev_PC,
+            cycles: self.cycles,
+        }
+    }
+
+    pub fn rollback(&mut self, state: &Checkpoint) {
+        self.V = state.regs.V;
+        self.I = state.regs.I;
+        self.DT = state.regs.DT;
+        self.ST = state.regs.ST;
+        self.PC = state.regs.PC;
+        self.SP = state.stack.clone();
+        self.prev_PC = state.prev_pc;
+        self.cycles = state.cycles;
+        self.prev_ST = self.ST;
+        self.accesses.clear();
+    }
+
+    pub fn toggle_pixel(&mut self, idx: usize) {
+        self.gpu.toggle_pixel(idx);
     }
 
     pub fn load_rom(&mut self, data: &[u8]) {
@@ -51,6 +388,7 @@ impl Cpu {
     }
 
     pub fn timer_tick(&mut self) {
+        self.gpu.vblank();
         if self.DT > 0 {
             self.DT -= 1;
         }
@@ -72,11 +410,36 @@ impl Cpu {
         instrs
     }
 
+    fn access(&mut self, addr: u16, kind: AccessKind) -> u8 {
+        let value = self.ram.read_byte(addr);
+        self.accesses.push(MemAccess {
+            addr,
+            kind,
+            value,
+            old: value,
+        });
+        value
+    }
+
+    fn store(&mut self, addr: u16, value: u8) {
+        self.accesses.push(MemAccess {
+            addr,
+            kind: AccessKind::Write,
+            value,
+            old: self.ram.read_byte(addr),
+        });
+        self.ram.write_byte(addr, value);
+    }
+
     pub fn execute(&mut self, keys: Vec<u8>) {
         use decoder::Instruction::*;
 
-        let instr_raw =
-            u16::from_be_bytes([self.ram.read_byte(self.PC), self.ram.read_byte(self.PC + 1)]);
+        self.accesses.clear();
+        self.prev_ST = self.ST;
+        let instr_raw = u16::from_be_bytes([
+            self.access(self.PC, AccessKind::Fetch),
+            self.access(self.PC + 1, AccessKind::Fetch),
+        ]);
         let instr = match decoder::decode(instr_raw) {
             Some(instr) => instr,
             None => panic!("UNKNOWN INSTRUCTION"),
@@ -96,7 +459,12 @@ impl Cpu {
                 pc_op = PCOp::JumpAddr(addr);
             }
             JumpV0Addr(addr) => {
-                pc_op = PCOp::JumpAddr(self.V[0] as u16 + addr);
+                let v = if self.quirks.jump_vx {
+                    (addr >> 8) as usize
+                } else {
+                    0
+                };
+                pc_op = PCOp::JumpAddr(self.V[v] as u16 + addr);
             }
             Call(addr) => {
                 self.SP.push(self.PC + 2); // push addr of next instr
@@ -135,19 +503,25 @@ impl Cpu {
             }
             StoreRegsVx(v) => {
                 for vi in 0..v + 1 {
-                    self.ram.write_byte(self.I + vi as u16, self.V[vi]);
+                    self.store(self.I + vi as u16, self.V[vi]);
+                }
+                if self.quirks.load_store_inc_i {
+                    self.I += v as u16 + 1;
                 }
             }
             LoadRegsVx(v) => {
                 for vi in 0..v + 1 {
-                    self.V[vi] = self.ram.read_byte(self.I + vi as u16);
+                    self.V[vi] = self.access(self.I + vi as u16, AccessKind::Read);
+                }
+                if self.quirks.load_store_inc_i {
+                    self.I += v as u16 + 1;
                 }
             }
             LoadBVx(v) => {
                 let v = self.V[v];
-                self.ram.write_byte(self.I, v / 100);
-                self.ram.write_byte(self.I + 1, (v / 10) % 10);
-                self.ram.write_byte(self.I + 2, v % 10);
+                self.store(self.I, v / 100);
+                self.store(self.I + 1, (v / 10) % 10);
+                self.store(self.I + 2, v % 10);
             }
             LoadSpriteAddrVx(v) => {
                 self.I = self.V[v] as u16 * 5;
@@ -190,27 +564,42 @@ impl Cpu {
             // ---- Bit Operations ---- ///
             AndVxVy(vx, vy) => {
                 self.V[vx] &= self.V[vy];
+                if self.quirks.vf_reset {
+                    self.V[15] = 0;
+                }
             }
             ShlVxby1(v) => {
+                if self.quirks.shift_vy {
+                    self.V[v] = self.V[(instr_raw as usize & 0x00f0) >> 4];
+                }
                 // VF = Vx[7]
                 self.V[15] = (self.V[v] & 0x80) as u8;
                 self.V[v] <<= 1;
             }
             ShrVxby1(v) => {
+                if self.quirks.shift_vy {
+                    self.V[v] = self.V[(instr_raw as usize & 0x00f0) >> 4];
+                }
                 // VF = Vx[0]
                 self.V[15] = (self.V[v] & 0x01) as u8;
                 self.V[v] >>= 1;
             }
             XorVxVy(vx, vy) => {
                 self.V[vx] ^= self.V[vy];
+                if self.quirks.vf_reset {
+                    self.V[15] = 0;
+                }
             }
             OrVxVy(vx, vy) => {
                 self.V[vx] |= self.V[vy];
+                if self.quirks.vf_reset {
+                    self.V[15] = 0;
+                }
             }
 
             // ---- Rand ----//
             RandVxAndByte(v, byte) => {
-                self.V[v] = rand::random::<u8>() & byte;
+                self.V[v] = self.random() as u8 & byte;
             }
 
             // ---- Display ---- //
@@ -222,7 +611,7 @@ impl Cpu {
                 // TODO: get rid of copy (slice into memory)
                 let mut sprite = [0u8; 16];
                 for line in 0..lines {
-                    sprite[line] = self.ram.read_byte(self.I + line as u16);
+                    sprite[line] = self.access(self.I + line as u16, AccessKind::Read);
                 }
                 self.V[15] = (self.gpu.write_sprite(
                     self.V[vx] as usize,
@@ -258,6 +647,8 @@ impl Cpu {
             self.prev_PC = self.PC;
         }
 
+        self.cycles += 1;
+
         match pc_op {
             PCOp::Inc => {
                 self.PC += 2;
@@ -273,53 +664,59 @@ impl Cpu {
     }
 
     pub fn dump(&self) {
-        println!("---- CPU state ----");
-        for l in 0..4 {
-            for (i, v) in self.V[l..l + 4].iter().enumerate() {
-                print!("V{:X}: {:02x}    ", i + 4 * l, v);
-            }
-            println!();
-        }
-        println!("DT: {:02x}    ST: {:02x}", self.DT, self.ST);
-        println!("I : {:04x}", self.I);
-        println!("PC: {:04x}", self.PC);
-        for (i, val) in self.SP.iter().rev().enumerate() {
-            if i == 0 {
-                println!("SP: {:04x}", val);
-            } else {
-                println!("    {:04x}", val);
-            }
+        for line in self.dump_to_vec_str(&Symbols::new()) {
+            println!("{}", line);
         }
-        println!("-------------------");
     }
 
-    pub fn dump_to_vec_str(&self) -> std::vec::Vec<String> {
-        let mut state = Vec::new();
-        state.push(format!("---- CPU STATE ----"));
-        for i in 0..4 {
-            let i = 4 * i;
-            state.push(format!(
-                "V{:X}: {:02X}   V{:X}: {:02X}  V{:X}: {:02X}  V{:X}: {:02X}",
-                i,
-                self.V[i],
-                i + 1,
-                self.V[i + 1],
-                i + 2,
-                self.V[i + 2],
-                i + 3,
-                self.V[i + 3]
-            ));
-        }
-        state.push(format!("DT: {:02X}   ST: {:02X}", self.DT, self.ST));
-        state.push(format!("I : {:04X}", self.I));
-        state.push(format!("PC: {:04X}", self.PC));
-        for (i, val) in self.SP.iter().rev().enumerate() {
-            if i == 0 {
-                state.push(format!("SP: {:04X}", val));
-            } else {
-                state.push(format!("    {:04X}", val));
-            }
-        }
-        state
+    pub fn dump_to_vec_str(&self, symbols: &Symbols) -> std::vec::Vec<String> {
+        self.get_state().format(symbols)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn state() {
+        let mut cpu = Cpu::new(memory::Memory::new(), gpu::Gpu::new());
+        // LD V0, 01 | CALL 0300
+        cpu.load_rom(&[0x60, 0x01, 0x23, 0x00]);
+        cpu.execute(vec![]);
+        cpu.execute(vec![]);
+
+        let mut state = cpu.get_state();
+        assert_eq!(state.V[0], 1);
+        assert_eq!(state.PC, 0x300);
+        assert_eq!(state.stack, vec![0x204]);
+        assert_eq!(state.cycles, 2);
+
+        state.V[0xa] = 0x42;
+        state.I = 0x123;
+        state.PC = 0x200;
+        state.stack.clear();
+        cpu.set_state(&state);
+        assert_eq!(cpu.get_state(), state);
+
+        cpu.set_v(1, 2);
+        cpu.set_i(0x300);
+        cpu.set_dt(3);
+        cpu.set_st(4);
+        cpu.set_stack(&[0x202, 0x302]);
+        let regs = cpu.get_registers();
+        assert_eq!(
+            (regs.V[1], regs.I, regs.DT, regs.ST, regs.SP),
+            (2, 0x300, 3, 4, 2)
+        );
+
+        // executing at the PC set from outside must not trip the stuck check
+        cpu.set_pc(0x200);
+        cpu.execute(vec![]);
+        assert_eq!(cpu.get_registers().PC, 0x202);
+
+        let lines = state.format(&Symbols::new());
+        assert_eq!(lines[3], "V8: 00   V9: 00  VA: 42  VB: 00");
+        assert_eq!(lines[6], "I : 0123");
     }
 }
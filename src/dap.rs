@@ -0,0 +1,1182 @@
+use super::args::Args;
+use super::callstack;
+use super::cpu::{Cpu, Quirks, INSTR_PER_TICK, PROGRAM_START};
+use super::debugger::Debugger;
+use super::decoder;
+use super::diff;
+use super::expr::{self, Expr};
+use super::gpu::{self, Gpu};
+use super::json::{object, Json};
+use super::linemap::LineMap;
+use super::memory::{Memory, MEM_SIZE};
+use super::symbols::Symbols;
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+// instructions executed between checks for new requests while running
+const BATCH: usize = 1000;
+// the cpu is the only thread
+const THREAD_ID: usize = 1;
+// variablesReference of the scopes
+const REGISTERS: i64 = 1;
+const MEMORY: i64 = 2;
+const DISPLAY: i64 = 3;
+// bytes per variable of the memory scope
+const ROW: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+    Stdio,
+    // localhost port
+    Tcp(u16),
+}
+
+// read one `Content-Length` framed message, None at the end of the stream
+fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<Json>, String> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if reader
+            .read_line(&mut line)
+            .map_err(|e| format!("Failed to read message: {}", e))?
+            == 0
+        {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            if len.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            len = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid header '{}'", line))?,
+            );
+        }
+    }
+
+    let mut body = vec![0; len.unwrap()];
+    reader
+        .read_exact(&mut body)
+        .map_err(|e| format!("Failed to read message: {}", e))?;
+    let text = String::from_utf8(body).map_err(|_| "message is not UTF-8".to_string())?;
+    Json::parse(&text).map(Some)
+}
+
+fn write_message<W: Write>(out: &mut W, msg: &Json) -> std::io::Result<()> {
+    let text = msg.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", text.len(), text)?;
+    out.flush()
+}
+
+fn load_program(path: &str, quirks: Quirks, seed: Option<u64>) -> Result<Cpu, String> {
+    let rom = std::fs::read(path).map_err(|e| format!("Failed to read ROM {}: {}", path, e))?;
+    if rom.len() >= MEM_SIZE - PROGRAM_START as usize {
+        return Err(format!("ROM {} is too large", path));
+    }
+    let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+    cpu.load_rom(&rom);
+    cpu.set_quirks(quirks);
+    if let Some(seed) = seed {
+        cpu.seed_rng(seed);
+    }
+    Ok(cpu)
+}
+
+fn loaded(cpu: &mut Option<Cpu>) -> Result<&mut Cpu, String> {
+    cpu.as_mut()
+        .ok_or_else(|| "no program loaded, use launch or attach".to_string())
+}
+
+fn get_str<'a>(args: &'a Json, key: &str) -> Option<&'a str> {
+    args.get(key).and_then(Json::as_str)
+}
+
+fn get_i64(args: &Json, key: &str) -> Option<i64> {
+    args.get(key).and_then(Json::as_i64)
+}
+
+fn hex(addr: u16) -> Json {
+    Json::from(format!("0x{:04x}", addr))
+}
+
+fn base64(data: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .chain([0, 0].iter())
+            .take(3)
+            .fold(0u32, |n, &b| n << 8 | b as u32);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(CHARS[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// `break` spec of a DAP breakpoint at `addr` with its condition, hit
+// condition and log message
+fn break_spec(addr: u16, bp: &Json) -> String {
+    let mut conds = Vec::new();
+    if let Some(cond) = get_str(bp, "condition").filter(|c| !c.trim().is_empty()) {
+        conds.push(format!("({})", cond.trim()));
+    }
+    if let Some(hits) = get_str(bp, "hitCondition").map(str::trim) {
+        conds.push(match hits {
+            "" => String::new(),
+            h if h.starts_with(|c: char| c.is_ascii_digit()) => format!("hitcount >= {}", h),
+            h if h.starts_with('=') && !h.starts_with("==") => format!("hitcount ={}", h),
+            h => format!("hitcount {}", h),
+        });
+    }
+    conds.retain(|c| !c.is_empty());
+
+    let mut spec = format!("{:04x}", addr);
+    if !conds.is_empty() {
+        spec += &format!(" if {}", conds.join(" && "));
+    }
+    if let Some(msg) = get_str(bp, "logMessage") {
+        spec += &format!(" log {}", msg);
+    }
+    spec
+}
+
+fn variable(name: String, value: String, mem_ref: Option<u16>) -> Json {
+    let mut members = vec![
+        ("name", Json::from(name)),
+        ("value", Json::from(value)),
+        ("variablesReference", Json::from(0usize)),
+    ];
+    if let Some(addr) = mem_ref {
+        members.push(("memoryReference", hex(addr)));
+    }
+    object(members)
+}
+
+// debug adapter for one client connection
+struct Server<W: Write> {
+    out: W,
+    seq: i64,
+    cpu: Option<Cpu>,
+    // settings from the command line, used by `launch`
+    quirks: Quirks,
+    seed: Option<u64>,
+    symbols: Symbols,
+    lines: LineMap,
+    debugger: Debugger,
+    // break specs of every `source:<path>`, `function` and `instruction` set
+    breakpoints: BTreeMap<String, Vec<String>>,
+    stop_on_entry: bool,
+    running: bool,
+    // a step request is active, stops are reported as `step`
+    stepping: bool,
+    // events sent after the current response
+    queued: Vec<Json>,
+}
+
+impl<W: Write> Server<W> {
+    fn new(out: W, cpu: Option<Cpu>, args: &Args) -> Result<Server<W>, String> {
+        Ok(Server {
+            out,
+            seq: 0,
+            cpu,
+            quirks: args.quirks,
+            seed: args.seed,
+            symbols: match args.symbols {
+                Some(ref path) => Symbols::load(path)?,
+                None => Symbols::new(),
+            },
+            lines: match args.listing {
+                Some(ref path) => LineMap::load(path)?,
+                None => LineMap::new(),
+            },
+            debugger: Debugger::new(),
+            breakpoints: BTreeMap::new(),
+            stop_on_entry: false,
+            running: false,
+            stepping: false,
+            queued: Vec::new(),
+        })
+    }
+
+    fn send(&mut self, kind: &str, members: Vec<(&str, Json)>) -> Result<(), String> {
+        self.seq += 1;
+        let mut msg = vec![("seq", Json::from(self.seq)), ("type", Json::from(kind))];
+        msg.extend(members);
+        write_message(&mut self.out, &object(msg))
+            .map_err(|e| format!("Failed to send DAP message: {}", e))
+    }
+
+    fn queue(&mut self, event: &str, body: Json) {
+        let mut members = vec![("event", Json::from(event))];
+        if body != Json::Null {
+            members.push(("body", body));
+        }
+        self.queued.push(object(members));
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        for event in std::mem::take(&mut self.queued) {
+            if let Json::Object(members) = event {
+                self.send(
+                    "event",
+                    members
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.clone()))
+                        .collect(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self, reason: &str, text: String) {
+        self.running = false;
+        self.stepping = false;
+        self.debugger.cancel();
+        let mut body = vec![
+            ("reason", Json::from(reason)),
+            ("description", Json::from(text.clone())),
+            ("threadId", Json::from(THREAD_ID)),
+            ("allThreadsStopped", Json::from(true)),
+        ];
+        if reason == "exception" {
+            body.push(("text", Json::from(text)));
+        }
+        self.queue("stopped", object(body));
+    }
+
+    // answer one request, returns false when the session ends
+    fn handle(&mut self, request: &Json) -> Result<bool, String> {
+        let command = get_str(request, "command").unwrap_or("").to_string();
+        let args = request.get("arguments").cloned().unwrap_or(Json::Null);
+        let result = match command.as_str() {
+            "initialize" => Ok(self.initialize()),
+            "launch" => self.launch(&args),
+            "attach" => self.attach(&args),
+            "configurationDone" => self.configuration_done(),
+            "setBreakpoints" => self.set_breakpoints(&args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(&args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(&args),
+            "threads" => Ok(object(vec![(
+                "threads",
+                Json::from(vec![object(vec![
+                    ("id", Json::from(THREAD_ID)),
+                    ("name", Json::from("CHIP-8")),
+                ])]),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(scopes()),
+            "variables" => self.variables(&args),
+            "setVariable" => self.set_variable(&args),
+            "evaluate" => self.evaluate(&args),
+            "readMemory" => self.read_memory(&args),
+            "disassemble" => self.disassemble(&args),
+            "continue" => self.resume(),
+            "next" => self.next(),
+            "stepIn" => self.step_in(),
+            "stepOut" => self.step_out(),
+            "pause" => {
+                if self.running {
+                    self.stop("pause", "paused".to_string());
+                }
+                Ok(Json::Null)
+            }
+            "disconnect" | "terminate" => Ok(Json::Null),
+            _ => Err(format!("unsupported request '{}'", command)),
+        };
+
+        let mut response = vec![
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("command", Json::from(command.as_str())),
+        ];
+        match result {
+            Ok(Json::Null) => response.push(("success", Json::from(true))),
+            Ok(body) => {
+                response.push(("success", Json::from(true)));
+                response.push(("body", body));
+            }
+            Err(message) => {
+                response.push(("success", Json::from(false)));
+                response.push(("message", Json::from(message)));
+            }
+        }
+        self.send("response", response)?;
+        self.flush()?;
+        Ok(command != "disconnect" && command != "terminate")
+    }
+
+    fn initialize(&mut self) -> Json {
+        let supported = [
+            "supportsConfigurationDoneRequest",
+            "supportsFunctionBreakpoints",
+            "supportsConditionalBreakpoints",
+            "supportsHitConditionalBreakpoints",
+            "supportsLogPoints",
+            "supportsInstructionBreakpoints",
+            "supportsDisassembleRequest",
+            "supportsReadMemoryRequest",
+            "supportsSetVariable",
+            "supportsEvaluateForHovers",
+        ];
+        object(
+            supported
+                .iter()
+                .map(|&name| (name, Json::from(true)))
+                .collect(),
+        )
+    }
+
+    // `symbols`, `listing` and `stopOnEntry` of launch and attach
+    fn configure(&mut self, args: &Json) -> Result<(), String> {
+        if let Some(path) = get_str(args, "symbols") {
+            self.symbols = Symbols::load(path)?;
+        }
+        if let Some(path) = get_str(args, "listing") {
+            self.lines = LineMap::load(path)?;
+        }
+        self.stop_on_entry = args
+            .get("stopOnEntry")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        // configuration requests follow the initialized event
+        self.queue("initialized", Json::Null);
+        Ok(())
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        let program = get_str(args, "program").ok_or("launch needs a program")?;
+        let quirks = match get_str(args, "quirks") {
+            Some(list) => Quirks::parse(list)?,
+            None => self.quirks,
+        };
+        let seed = get_i64(args, "seed").map(|s| s as u64).or(self.seed);
+        self.cpu = Some(load_program(program, quirks, seed)?);
+        self.configure(args)?;
+        Ok(Json::Null)
+    }
+
+    // debug the ROM given on the command line
+    fn attach(&mut self, args: &Json) -> Result<Json, String> {
+        if self.cpu.is_none() {
+            return Err("no ROM given on the command line, use launch".to_string());
+        }
+        self.configure(args)?;
+        Ok(Json::Null)
+    }
+
+    fn configuration_done(&mut self) -> Result<Json, String> {
+        loaded(&mut self.cpu)?;
+        if self.stop_on_entry {
+            self.stop("entry", "entry".to_string());
+        } else {
+            self.running = true;
+        }
+        Ok(Json::Null)
+    }
+
+    // replace the breakpoints of `key` and reinstall all, returns the result
+    // of every spec of `key`
+    fn install(&mut self, key: String, specs: Vec<String>) -> Vec<Result<(), String>> {
+        self.breakpoints.insert(key.clone(), specs);
+        self.debugger.clear_points();
+        let mut results = Vec::new();
+        for (k, specs) in &self.breakpoints {
+            for spec in specs {
+                let res = self.debugger.add_break(spec, &self.symbols);
+                if *k == key {
+                    results.push(res);
+                }
+            }
+        }
+        results
+    }
+
+    // set the breakpoints of `key`, `addrs` holds the resolved address of
+    // every requested breakpoint
+    fn set_group(
+        &mut self,
+        key: String,
+        requested: &[Json],
+        addrs: Vec<Result<u16, String>>,
+    ) -> Json {
+        let specs = requested
+            .iter()
+            .zip(&addrs)
+            .filter_map(|(bp, addr)| addr.as_ref().ok().map(|&addr| break_spec(addr, bp)))
+            .collect();
+        let mut installed = self.install(key, specs).into_iter();
+
+        let breakpoints: Vec<Json> = addrs
+            .into_iter()
+            .map(
+                |addr| match addr.and_then(|a| installed.next().unwrap_or(Ok(())).map(|_| a)) {
+                    Ok(addr) => {
+                        let mut members = vec![
+                            ("verified", Json::from(true)),
+                            ("instructionReference", hex(addr)),
+                        ];
+                        if let Some((_, line)) = self.lines.get_line(addr) {
+                            members.push(("line", Json::from(line)));
+                        }
+                        object(members)
+                    }
+                    Err(e) => object(vec![
+                        ("verified", Json::from(false)),
+                        ("message", Json::from(e)),
+                    ]),
+                },
+            )
+            .collect();
+        object(vec![("breakpoints", Json::from(breakpoints))])
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let path = args
+            .get("source")
+            .and_then(|s| get_str(s, "path"))
+            .ok_or("breakpoints need a source path")?;
+        let requested = args
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or(&[]);
+        let addrs = requested
+            .iter()
+            .map(|bp| {
+                let line = get_i64(bp, "line").unwrap_or(0) as usize;
+                self.lines
+                    .get_addr(path, line)
+                    .map(|(addr, _)| addr)
+                    .ok_or_else(|| format!("no instruction at line {} in the listing map", line))
+            })
+            .collect();
+        Ok(self.set_group(format!("source:{}", path), requested, addrs))
+    }
+
+    fn set_function_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let requested = args
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or(&[]);
+        let addrs = requested
+            .iter()
+            .map(|bp| {
+                self.symbols
+                    .resolve(get_str(bp, "name").unwrap_or("").trim())
+            })
+            .collect();
+        Ok(self.set_group("function".to_string(), requested, addrs))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let requested = args
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or(&[]);
+        let addrs = requested
+            .iter()
+            .map(|bp| {
+                let base = self
+                    .symbols
+                    .resolve(get_str(bp, "instructionReference").unwrap_or(""))?;
+                let offset = get_i64(bp, "offset").unwrap_or(0);
+                Ok((base as i64 + offset) as u16 & 0x0fff)
+            })
+            .collect();
+        Ok(self.set_group("instruction".to_string(), requested, addrs))
+    }
+
+    fn frame(&self, id: usize, pc: u16) -> Json {
+        let name = self
+            .symbols
+            .describe(pc)
+            .unwrap_or_else(|| format!("{:04x}", pc));
+        let mut members = vec![
+            ("id", Json::from(id)),
+            ("name", Json::from(name)),
+            ("instructionPointerReference", hex(pc)),
+        ];
+        match self.lines.get_line(pc) {
+            Some((file, line)) => {
+                let name = std::path::Path::new(file)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                members.push((
+                    "source",
+                    object(vec![("name", Json::from(name)), ("path", Json::from(file))]),
+                ));
+                members.push(("line", Json::from(line)));
+                members.push(("column", Json::from(1usize)));
+            }
+            None => {
+                members.push(("line", Json::from(0usize)));
+                members.push(("column", Json::from(0usize)));
+            }
+        }
+        object(members)
+    }
+
+    // the current instruction, then the CALL of every active routine
+    fn stack_trace(&mut self) -> Result<Json, String> {
+        let cpu = loaded(&mut self.cpu)?;
+        let mut pcs = vec![cpu.get_registers().PC];
+        pcs.extend(
+            callstack::frames(cpu.get_stack(), cpu.get_mem())
+                .iter()
+                .rev()
+                .map(|f| f.caller),
+        );
+        let frames: Vec<Json> = pcs
+            .iter()
+            .enumerate()
+            .map(|(id, &pc)| self.frame(id, pc))
+            .collect();
+        Ok(object(vec![
+            ("stackFrames", Json::from(frames)),
+            ("totalFrames", Json::from(pcs.len())),
+        ]))
+    }
+
+    fn variables(&mut self, args: &Json) -> Result<Json, String> {
+        let cpu = loaded(&mut self.cpu)?;
+        let vars: Vec<Json> = match get_i64(args, "variablesReference") {
+            Some(REGISTERS) => {
+                let state = cpu.get_state();
+                let mut vars: Vec<Json> = state
+                    .V
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| variable(format!("V{:X}", i), format!("0x{:02x}", v), None))
+                    .collect();
+                let with_symbol = |addr: u16| match self.symbols.describe(addr) {
+                    Some(name) => format!("0x{:04x} {}", addr, name),
+                    None => format!("0x{:04x}", addr),
+                };
+                vars.push(variable(
+                    "I".to_string(),
+                    with_symbol(state.I),
+                    Some(state.I),
+                ));
+                vars.push(variable(
+                    "PC".to_string(),
+                    with_symbol(state.PC),
+                    Some(state.PC),
+                ));
+                vars.push(variable(
+                    "DT".to_string(),
+                    format!("0x{:02x}", state.DT),
+                    None,
+                ));
+                vars.push(variable(
+                    "ST".to_string(),
+                    format!("0x{:02x}", state.ST),
+                    None,
+                ));
+                vars.push(variable(
+                    "SP".to_string(),
+                    state.stack.len().to_string(),
+                    None,
+                ));
+                let stack: Vec<String> = state.stack.iter().map(|&a| with_symbol(a)).collect();
+                vars.push(variable(
+                    "stack".to_string(),
+                    format!("[{}]", stack.join(", ")),
+                    None,
+                ));
+                vars
+            }
+            Some(MEMORY) => cpu
+                .get_mem()
+                .chunks(ROW)
+                .enumerate()
+                .map(|(row, bytes)| {
+                    let addr = (row * ROW) as u16;
+                    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                    variable(format!("0x{:04x}", addr), bytes.join(" "), Some(addr))
+                })
+                .collect(),
+            Some(DISPLAY) => {
+                let plane = cpu.get_fb();
+                (0..gpu::HEIGHT)
+                    .map(|y| {
+                        let pixels = (0..gpu::WIDTH)
+                            .map(|x| if plane.get(x, y) { '#' } else { '.' })
+                            .collect();
+                        variable(format!("{:02}", y), pixels, None)
+                    })
+                    .collect()
+            }
+            _ => return Err("unknown variablesReference".to_string()),
+        };
+        Ok(object(vec![("variables", Json::from(vars))]))
+    }
+
+    fn set_variable(&mut self, args: &Json) -> Result<Json, String> {
+        let cpu = loaded(&mut self.cpu)?;
+        let name = get_str(args, "name").unwrap_or("");
+        let value = get_str(args, "value").unwrap_or("");
+
+        let shown = match get_i64(args, "variablesReference") {
+            Some(REGISTERS) => {
+                let expr = Expr::parse(value, &self.symbols)?;
+                let regs = cpu.get_registers();
+                let n = expr.eval(&expr::Context {
+                    regs: &regs,
+                    mem: cpu.get_mem(),
+                    hitcount: 0,
+                });
+                match name {
+                    "I" => cpu.set_i(n as u16),
+                    "PC" => cpu.set_pc(n as u16 & 0x0fff),
+                    "DT" => cpu.set_dt(n as u8),
+                    "ST" => cpu.set_st(n as u8),
+                    v if v.len() == 2 && v.starts_with('V') => {
+                        let x = usize::from_str_radix(&v[1..], 16)
+                            .map_err(|_| format!("unknown register '{}'", v))?;
+                        cpu.set_v(x, n as u8);
+                    }
+                    _ => return Err(format!("{} is read only", name)),
+                }
+                match name {
+                    "I" | "PC" => format!("0x{:04x}", n as u16),
+                    _ => format!("0x{:02x}", n as u8),
+                }
+            }
+            Some(MEMORY) => {
+                let addr = self.symbols.resolve(name)?;
+                let bytes = value
+                    .split_whitespace()
+                    .map(|b| u8::from_str_radix(b.trim_start_matches("0x"), 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| format!("invalid bytes '{}'", value))?;
+                cpu.get_ram_mut().write_slice(addr, &bytes)?;
+                value.to_string()
+            }
+            _ => return Err("variable is read only".to_string()),
+        };
+        Ok(object(vec![("value", Json::from(shown))]))
+    }
+
+    fn evaluate(&mut self, args: &Json) -> Result<Json, String> {
+        let cpu = loaded(&mut self.cpu)?;
+        let expr = Expr::parse(get_str(args, "expression").unwrap_or(""), &self.symbols)?;
+        let regs = cpu.get_registers();
+        let n = expr.eval(&expr::Context {
+            regs: &regs,
+            mem: cpu.get_mem(),
+            hitcount: 0,
+        });
+        let result = if n < 0 {
+            n.to_string()
+        } else {
+            format!("0x{:x}", n)
+        };
+        Ok(object(vec![
+            ("result", Json::from(result)),
+            ("variablesReference", Json::from(0usize)),
+        ]))
+    }
+
+    fn read_memory(&mut self, args: &Json) -> Result<Json, String> {
+        let cpu = loaded(&mut self.cpu)?;
+        let base = self
+            .symbols
+            .resolve(get_str(args, "memoryReference").unwrap_or(""))?;
+        let start = base as i64 + get_i64(args, "offset").unwrap_or(0);
+        let count = get_i64(args, "count").unwrap_or(0).max(0);
+        if start < 0 || start >= MEM_SIZE as i64 {
+            return Ok(object(vec![
+                ("address", Json::from(format!("0x{:x}", start))),
+                ("unreadableBytes", Json::from(count)),
+            ]));
+        }
+        let len = std::cmp::min(count, MEM_SIZE as i64 - start);
+        let data = cpu.get_ram().read_slice(start as u16, len as usize)?;
+        Ok(object(vec![
+            ("address", hex(start as u16)),
+            ("data", Json::from(base64(data))),
+            ("unreadableBytes", Json::from(count - len)),
+        ]))
+    }
+
+    fn disassemble(&mut self, args: &Json) -> Result<Json, String> {
+        let cpu = loaded(&mut self.cpu)?;
+        let base = self
+            .symbols
+            .resolve(get_str(args, "memoryReference").unwrap_or(""))?;
+        let start = base as i64
+            + get_i64(args, "offset").unwrap_or(0)
+            + 2 * get_i64(args, "instructionOffset").unwrap_or(0);
+        let count = get_i64(args, "instructionCount").unwrap_or(0).max(0);
+        let resolve = args
+            .get("resolveSymbols")
+            .and_then(Json::as_bool)
+            .unwrap_or(true);
+        let mem = cpu.get_mem();
+        let (symbols, lines) = (&self.symbols, &self.lines);
+
+        let instructions: Vec<Json> = (0..count)
+            .map(|i| start + 2 * i)
+            .map(|addr| {
+                if addr < 0 || addr + 1 >= MEM_SIZE as i64 {
+                    return object(vec![
+                        ("address", hex(addr as u16)),
+                        ("instruction", Json::from("")),
+                        ("presentationHint", Json::from("invalid")),
+                    ]);
+                }
+                let addr = addr as u16;
+                let (hi, lo) = (mem[addr as usize], mem[addr as usize + 1]);
+                let opcode = u16::from_be_bytes([hi, lo]);
+                let text = if resolve {
+                    decoder::disassemble_with(opcode, |a| symbols.format_addr(a))
+                } else {
+                    decoder::disassemble(opcode)
+                };
+                let mut members = vec![
+                    ("address", hex(addr)),
+                    (
+                        "instructionBytes",
+                        Json::from(format!("{:02x} {:02x}", hi, lo)),
+                    ),
+                    ("instruction", Json::from(text)),
+                ];
+                if let Some(name) = symbols.get(addr) {
+                    members.push(("symbol", Json::from(name)));
+                }
+                if let Some((file, line)) = lines.get_line(addr) {
+                    members.push(("location", object(vec![("path", Json::from(file))])));
+                    members.push(("line", Json::from(line)));
+                }
+                object(members)
+            })
+            .collect();
+        Ok(object(vec![("instructions", Json::from(instructions))]))
+    }
+
+    fn resume(&mut self) -> Result<Json, String> {
+        loaded(&mut self.cpu)?;
+        self.running = true;
+        Ok(object(vec![("allThreadsContinued", Json::from(true))]))
+    }
+
+    fn next(&mut self) -> Result<Json, String> {
+        self.debugger.step_over(loaded(&mut self.cpu)?);
+        self.stepping = true;
+        self.running = true;
+        Ok(Json::Null)
+    }
+
+    fn step_out(&mut self) -> Result<Json, String> {
+        self.debugger.step_out(loaded(&mut self.cpu)?)?;
+        self.stepping = true;
+        self.running = true;
+        Ok(Json::Null)
+    }
+
+    fn step_in(&mut self) -> Result<Json, String> {
+        loaded(&mut self.cpu)?;
+        self.stepping = true;
+        let (reason, text) = self
+            .step()
+            .unwrap_or_else(|| ("step", "stepped".to_string()));
+        self.stop(reason, text);
+        Ok(Json::Null)
+    }
+
+    // execute one instruction, returns the reason to stop
+    fn step(&mut self) -> Option<(&'static str, String)> {
+        let cpu = self.cpu.as_mut()?;
+        let pc = cpu.get_registers().PC;
+        let key_wait = cpu.get_next_n_instr(1)[0] & 0xf0ff == 0xf00a;
+        if let Err(err) = panic::catch_unwind(AssertUnwindSafe(|| cpu.execute(Vec::new()))) {
+            return Some(("exception", diff::panic_msg(err)));
+        }
+        if cpu.get_cycles().is_multiple_of(INSTR_PER_TICK) {
+            cpu.timer_tick();
+        }
+        let waiting = key_wait && cpu.get_registers().PC == pc;
+
+        let stop = self.debugger.check(cpu, &self.symbols);
+        for msg in self.debugger.take_logs() {
+            self.queue(
+                "output",
+                object(vec![
+                    ("category", Json::from("console")),
+                    ("output", Json::from(format!("{}\n", msg))),
+                ]),
+            );
+        }
+        match stop {
+            Some(text) if self.stepping => Some(("step", text)),
+            Some(text) => Some(("breakpoint", text)),
+            // nothing can press a key over DAP, the wait would never end
+            None if waiting => Some((
+                "pause",
+                format!(
+                    "key wait at {}, there is no keypad input over DAP",
+                    self.symbols.format_addr(pc)
+                ),
+            )),
+            None => None,
+        }
+    }
+
+    // execute up to `n` instructions while running
+    fn run(&mut self, n: usize) -> Result<(), String> {
+        for _ in 0..n {
+            if !self.running {
+                break;
+            }
+            if let Some((reason, text)) = self.step() {
+                self.stop(reason, text);
+            }
+        }
+        self.flush()
+    }
+}
+
+fn scopes() -> Json {
+    let scope = |name: &str, reference: i64, expensive: bool| {
+        object(vec![
+            ("name", Json::from(name)),
+            ("variablesReference", Json::from(reference)),
+            ("expensive", Json::from(expensive)),
+        ])
+    };
+    object(vec![(
+        "scopes",
+        Json::from(vec![
+            scope("Registers", REGISTERS, false),
+            scope("Memory", MEMORY, true),
+            scope("Display", DISPLAY, true),
+        ]),
+    )])
+}
+
+fn run_session<R: Read + Send + 'static, W: Write>(
+    reader: R,
+    mut server: Server<W>,
+) -> Result<(), String> {
+    // requests are read on their own thread to answer `pause` while running
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        loop {
+            let msg = read_message(&mut reader);
+            let done = !matches!(msg, Ok(Some(_)));
+            if tx.send(msg).is_err() || done {
+                break;
+            }
+        }
+    });
+
+    loop {
+        let msg = if server.running {
+            match rx.try_recv() {
+                Ok(msg) => Some(msg),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        } else {
+            match rx.recv() {
+                Ok(msg) => Some(msg),
+                Err(_) => return Ok(()),
+            }
+        };
+        match msg {
+            Some(Ok(Some(request))) => {
+                let more = server.handle(&request)?;
+                if !more {
+                    return Ok(());
+                }
+            }
+            // client closed the connection
+            Some(Ok(None)) => return Ok(()),
+            Some(Err(e)) => return Err(e),
+            None => {}
+        }
+        server.run(BATCH)?;
+    }
+}
+
+// serve one debugger session, a ROM on the command line can be attached to
+pub fn serve(transport: Transport, args: &Args) -> Result<(), String> {
+    let cpu = match args.rom {
+        Some(ref path) => Some(load_program(path, args.quirks, args.seed)?),
+        None => None,
+    };
+    match transport {
+        Transport::Stdio => {
+            // stdout carries the protocol, status messages go to stderr
+            eprintln!("[+] serving DAP on stdin/stdout");
+            run_session(std::io::stdin(), Server::new(std::io::stdout(), cpu, args)?)
+        }
+        Transport::Tcp(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port))
+                .map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+            eprintln!("[+] waiting for a DAP client on 127.0.0.1:{}", port);
+            let (stream, peer) = listener
+                .accept()
+                .map_err(|e| format!("Failed to accept DAP client: {}", e))?;
+            eprintln!("[+] DAP client connected from {}", peer);
+            let reader = stream
+                .try_clone()
+                .map_err(|e| format!("Failed to clone DAP stream: {}", e))?;
+            run_session(reader, Server::new(stream, cpu, args)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::args;
+    use std::io::Cursor;
+
+    fn request(server: &mut Server<Vec<u8>>, command: &str, args: Json) -> Vec<Json> {
+        server.out.clear();
+        let req = object(vec![
+            ("seq", Json::from(1usize)),
+            ("type", Json::from("request")),
+            ("command", Json::from(command)),
+            ("arguments", args),
+        ]);
+        assert!(server.handle(&req).unwrap());
+        messages(&server.out)
+    }
+
+    fn messages(out: &[u8]) -> Vec<Json> {
+        let mut reader = Cursor::new(out);
+        let mut msgs = Vec::new();
+        while let Some(msg) = read_message(&mut reader).unwrap() {
+            msgs.push(msg);
+        }
+        msgs
+    }
+
+    fn body(msg: &Json) -> &Json {
+        msg.get("body").unwrap()
+    }
+
+    fn event(msgs: &[Json], name: &str) -> Json {
+        msgs.iter()
+            .find(|m| get_str(m, "event") == Some(name))
+            .cloned()
+            .unwrap_or_else(|| panic!("no {} event in {:?}", name, msgs))
+    }
+
+    #[test]
+    fn framing() {
+        let msg = object(vec![("text", Json::from("Content-Length: 5\r\n"))]);
+        let mut out = Vec::new();
+        write_message(&mut out, &msg).unwrap();
+        write_message(&mut out, &msg).unwrap();
+        assert_eq!(messages(&out), vec![msg.clone(), msg]);
+        assert!(read_message(&mut Cursor::new(&b"Content-Length: 9\r\n\r\n{\"a\":"[..])).is_err());
+        assert_eq!(base64(b"CHIP-8"), "Q0hJUC04");
+        assert_eq!(base64(&[0xff, 0x00]), "/wA=");
+    }
+
+    #[test]
+    fn session() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        cpu.load_rom(&[
+            0x60, 0x05, // 0200: LD V0, 05
+            0x22, 0x08, // 0202: CALL 0208
+            0x12, 0x00, // 0204: JP 0200
+            0x00, 0x00, // 0206:
+            0x70, 0x01, // 0208: ADD V0, 01
+            0x00, 0xee, // 020a: RET
+        ]);
+        let args = args::parse_from(vec!["--dap".to_string()].into_iter()).unwrap();
+        let mut server = Server::new(Vec::new(), Some(cpu), &args).unwrap();
+        server.symbols = Symbols::parse("0x208 sub\n").unwrap();
+        server.lines = LineMap::parse("0x200 /src/game.8o:1\n0x202 /src/game.8o:2\n").unwrap();
+
+        let msgs = request(&mut server, "initialize", Json::Null);
+        assert_eq!(
+            body(&msgs[0]).get("supportsLogPoints"),
+            Some(&Json::Bool(true))
+        );
+
+        let msgs = request(
+            &mut server,
+            "attach",
+            object(vec![("stopOnEntry", Json::from(true))]),
+        );
+        assert_eq!(msgs[0].get("success"), Some(&Json::Bool(true)));
+        event(&msgs, "initialized");
+
+        let msgs = request(
+            &mut server,
+            "setFunctionBreakpoints",
+            Json::parse(r#"{"breakpoints": [{"name": "sub"}, {"name": "nowhere"}]}"#).unwrap(),
+        );
+        let bps = body(&msgs[0])
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap();
+        assert_eq!(bps[0].get("verified"), Some(&Json::Bool(true)));
+        assert_eq!(bps[1].get("verified"), Some(&Json::Bool(false)));
+
+        let msgs = request(
+            &mut server,
+            "setBreakpoints",
+            Json::parse(
+                r#"{"source": {"path": "/src/game.8o"},
+                    "breakpoints": [{"line": 1, "logMessage": "V0={V0}"}, {"line": 40}]}"#,
+            )
+            .unwrap(),
+        );
+        let bps = body(&msgs[0])
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap();
+        assert_eq!(bps[0].get("line").and_then(Json::as_i64), Some(1));
+        assert_eq!(bps[1].get("verified"), Some(&Json::Bool(false)));
+
+        let msgs = request(&mut server, "configurationDone", Json::Null);
+        assert_eq!(
+            get_str(body(&event(&msgs, "stopped")), "reason"),
+            Some("entry")
+        );
+
+        // runs into the function breakpoint after the CALL
+        request(&mut server, "continue", Json::Null);
+        server.out.clear();
+        server.run(100).unwrap();
+        let stopped = event(&messages(&server.out), "stopped");
+        assert_eq!(get_str(body(&stopped), "reason"), Some("breakpoint"));
+        assert_eq!(
+            get_str(body(&stopped), "description"),
+            Some("breakpoint sub")
+        );
+
+        let msgs = request(&mut server, "stackTrace", Json::Null);
+        let frames = body(&msgs[0])
+            .get("stackFrames")
+            .and_then(Json::as_array)
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(get_str(&frames[0], "name"), Some("sub"));
+        assert_eq!(
+            get_str(&frames[1], "instructionPointerReference"),
+            Some("0x0202")
+        );
+        assert_eq!(frames[1].get("line").and_then(Json::as_i64), Some(2));
+
+        let msgs = request(
+            &mut server,
+            "setVariable",
+            Json::parse(r#"{"variablesReference": 1, "name": "V1", "value": "V0 + 1"}"#).unwrap(),
+        );
+        assert_eq!(get_str(body(&msgs[0]), "value"), Some("0x06"));
+        let msgs = request(
+            &mut server,
+            "variables",
+            object(vec![("variablesReference", Json::from(REGISTERS))]),
+        );
+        let vars = body(&msgs[0])
+            .get("variables")
+            .and_then(Json::as_array)
+            .unwrap();
+        assert_eq!(get_str(&vars[1], "value"), Some("0x06"));
+        assert_eq!(get_str(&vars[17], "value"), Some("0x0208 sub"));
+
+        let msgs = request(
+            &mut server,
+            "readMemory",
+            Json::parse(r#"{"memoryReference": "sub", "offset": 2, "count": 2}"#).unwrap(),
+        );
+        assert_eq!(
+            get_str(body(&msgs[0]), "data"),
+            Some(base64(&[0x00, 0xee]).as_str())
+        );
+
+        let msgs = request(
+            &mut server,
+            "disassemble",
+            Json::parse(r#"{"memoryReference": "0x202", "instructionCount": 2}"#).unwrap(),
+        );
+        let instrs = body(&msgs[0])
+            .get("instructions")
+            .and_then(Json::as_array)
+            .unwrap();
+        assert_eq!(get_str(&instrs[0], "instruction"), Some("CALL sub"));
+        assert_eq!(get_str(&instrs[1], "instructionBytes"), Some("12 00"));
+
+        let msgs = request(&mut server, "stepIn", Json::Null);
+        assert_eq!(
+            get_str(body(&event(&msgs, "stopped")), "reason"),
+            Some("step")
+        );
+
+        // RET, JP and the logpoint at 0200 on the way out
+        request(&mut server, "stepOut", Json::Null);
+        server.out.clear();
+        server.run(100).unwrap();
+        let msgs = messages(&server.out);
+        assert_eq!(
+            get_str(body(&event(&msgs, "stopped")), "description"),
+            Some("stepped out to 0204")
+        );
+        request(&mut server, "continue", Json::Null);
+        server.out.clear();
+        server.run(100).unwrap();
+        let msgs = messages(&server.out);
+        assert_eq!(
+            get_str(body(&event(&msgs, "output")), "output"),
+            Some("V0=6\n")
+        );
+
+        request(&mut server, "continue", Json::Null);
+        let msgs = request(&mut server, "pause", Json::Null);
+        assert_eq!(
+            get_str(body(&event(&msgs, "stopped")), "reason"),
+            Some("pause")
+        );
+        server.out.clear();
+        let req = object(vec![
+            ("seq", Json::from(9usize)),
+            ("command", Json::from("disconnect")),
+        ]);
+        assert!(!server.handle(&req).unwrap());
+    }
+
+    #[test]
+    fn key_wait() {
+        let args = args::parse_from(vec!["--dap".to_string()].into_iter()).unwrap();
+        let server = |rom: &[u8]| {
+            let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+            cpu.load_rom(rom);
+            Server::new(Vec::new(), Some(cpu), &args).unwrap()
+        };
+
+        // JP 0200 stays in place without waiting for a key
+        assert_eq!(server(&[0x12, 0x00]).step(), None);
+        // LD V0, K
+        let stop = server(&[0xf0, 0x0a]).step();
+        assert_eq!(stop.map(|(reason, _)| reason), Some("pause"));
+    }
+}
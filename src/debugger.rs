@@ -0,0 +1,896 @@
+use super::cpu::{AccessKind, Cpu, PROGRAM_START};
+use super::expr::{self, Expr};
+use super::history::History;
+use super::symbols::Symbols;
+
+use std::io::BufRead;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+pub const HELP: &str = "\
+commands, <loc> is a symbol, `symbol+off` or hex address:
+  break [<loc>] [if <expr>] [log <msg>]
+                   stop before executing the instruction at <loc>, without
+                   <loc> the condition is checked for every instruction
+  watch <loc> [if <expr>] [log <msg>]
+                   stop after a write to the byte at <loc>
+  delete <loc>|#n  remove breakpoints and watches at <loc> or number n
+  info             list breakpoints and watches
+  catch <event>    stop on an event: cls, collision, keywait, sound,
+                   depth <n>, lowwrite, oddpc
+  uncatch <event>  remove an event breakpoint
+  x <loc> [len]    print memory
+  rstep [n]        step n instructions backwards
+  rcontinue        run backwards to the previous breakpoint or watch hit
+  lastwrite <loc>  show when the byte at <loc> was last written
+  next             run until the next instruction returns (step over)
+  finish           run until the current routine returns (step out)
+  until <loc>      run until PC reaches <loc>
+  frame            run until the next 60Hz timer tick
+  draw             run until the next DRW instruction
+  help             print this help";
+
+// read debugger commands from stdin without blocking the emulation loop
+pub fn spawn_console() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    if tx.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+    rx
+}
+
+// condition that ends a run started from Stepping mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Goal {
+    // stack depth is back at the given depth or below, steps over a CALL
+    StepOver(usize),
+    // stack depth dropped below the given depth
+    StepOut(usize),
+    Address(u16),
+    // next timer tick
+    Frame,
+    // next instruction is a DRW
+    Draw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PointKind {
+    Break,
+    Watch,
+}
+
+// breakpoint or watch with an optional condition
+struct Point {
+    kind: PointKind,
+    // None for a breakpoint checked on every instruction
+    addr: Option<u16>,
+    // source text and parsed condition
+    cond: Option<(String, Expr)>,
+    // print the expanded message instead of stopping
+    log: Option<String>,
+    // number of times the location was reached, `hitcount` in conditions
+    hits: u64,
+}
+
+// offset of the first whitespace separated `word` in `text`
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    let mut pos = 0;
+    for w in text.split(' ') {
+        if w == word {
+            return Some(pos);
+        }
+        pos += w.len() + 1;
+    }
+    None
+}
+
+impl Point {
+    // parse `[<loc>] [if <expr>] [log <msg>]`
+    fn parse(kind: PointKind, spec: &str, symbols: &Symbols) -> Result<Point, String> {
+        let (spec, log) = match find_word(spec, "log") {
+            Some(pos) => {
+                let msg = spec[pos + 3..].trim().trim_matches('"');
+                (&spec[..pos], Some(msg.to_string()))
+            }
+            None => (spec, None),
+        };
+        let (loc, cond) = match find_word(spec, "if") {
+            Some(pos) => {
+                let text = spec[pos + 2..].trim();
+                let cond = Expr::parse(text, symbols)
+                    .map_err(|e| format!("invalid condition '{}': {}", text, e))?;
+                (&spec[..pos], Some((text.to_string(), cond)))
+            }
+            None => (spec, None),
+        };
+        let addr = match loc.trim() {
+            "" if kind == PointKind::Watch => return Err("watch needs a location".to_string()),
+            "" if cond.is_none() => return Err("break needs a location or condition".to_string()),
+            "" => None,
+            loc => Some(symbols.resolve(loc)?),
+        };
+        Ok(Point {
+            kind,
+            addr,
+            cond,
+            log,
+            hits: 0,
+        })
+    }
+
+    fn describe(&self, symbols: &Symbols) -> String {
+        let mut text = match self.kind {
+            PointKind::Break => "break".to_string(),
+            PointKind::Watch => "watch".to_string(),
+        };
+        if let Some(addr) = self.addr {
+            text += &format!(" {:04x} {}", addr, symbols.format_addr(addr));
+        }
+        if let Some((ref cond, _)) = self.cond {
+            text += &format!(" if {}", cond);
+        }
+        if let Some(ref msg) = self.log {
+            text += &format!(" log \"{}\"", msg);
+        }
+        text
+    }
+}
+
+// event breakpoints, independent of an address
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Event {
+    // any CLS
+    Cls,
+    // DRW that turned off a pixel
+    Collision,
+    // before entering a FX0A key wait
+    KeyWait,
+    // LD ST, Vx starting the sound timer
+    Sound,
+    // CALL nesting deeper than the given depth
+    Depth(usize),
+    // write into the interpreter area below PROGRAM_START
+    LowWrite,
+    // before executing an instruction at an odd address
+    OddPc,
+}
+
+impl Event {
+    fn parse(args: &[&str]) -> Result<Event, String> {
+        match args {
+            ["cls"] => Ok(Event::Cls),
+            ["collision"] => Ok(Event::Collision),
+            ["keywait"] => Ok(Event::KeyWait),
+            ["sound"] => Ok(Event::Sound),
+            ["depth", n] => n
+                .parse::<usize>()
+                .map(Event::Depth)
+                .map_err(|_| format!("invalid depth '{}'", n)),
+            ["lowwrite"] => Ok(Event::LowWrite),
+            ["oddpc"] => Ok(Event::OddPc),
+            _ => Err(format!("unknown event '{}'", args.join(" "))),
+        }
+    }
+
+    // reason to stop after the last executed instruction
+    fn check(&self, cpu: &Cpu, symbols: &Symbols) -> Option<String> {
+        let regs = cpu.get_registers();
+        let accesses = cpu.get_accesses();
+        // executed instruction, assembled from its two fetch accesses
+        let (pc, opcode) = match accesses
+            .iter()
+            .filter(|a| a.kind == AccessKind::Fetch)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [hi, lo] => (hi.addr, u16::from_be_bytes([hi.value, lo.value])),
+            _ => return None,
+        };
+        let at = symbols.format_addr(pc);
+
+        match *self {
+            Event::Cls if opcode == 0x00e0 => Some(format!("CLS at {}", at)),
+            Event::Collision if opcode & 0xf000 == 0xd000 && regs.V[0xf] != 0 => {
+                Some(format!("DRW collision at {}", at))
+            }
+            Event::KeyWait if cpu.get_next_n_instr(1)[0] & 0xf0ff == 0xf00a && regs.PC != pc => {
+                Some(format!("key wait at {}", symbols.format_addr(regs.PC)))
+            }
+            Event::Sound if cpu.get_prev_st() == 0 && regs.ST != 0 => {
+                Some(format!("sound timer set to {:02x} at {}", regs.ST, at))
+            }
+            Event::Depth(n) if opcode & 0xf000 == 0x2000 && regs.SP > n => {
+                Some(format!("stack depth {} > {} at {}", regs.SP, n, at))
+            }
+            Event::LowWrite => accesses
+                .iter()
+                .find(|a| a.kind == AccessKind::Write && a.addr < PROGRAM_START)
+                .map(|a| {
+                    format!(
+                        "write to interpreter area {:04x} = {:02x} at {}",
+                        a.addr, a.value, at
+                    )
+                }),
+            Event::OddPc if regs.PC & 1 != 0 => Some(format!("odd PC {:04x}", regs.PC)),
+            _ => None,
+        }
+    }
+}
+
+// undo the last `n` instructions
+pub fn step_back(
+    cpu: &mut Cpu,
+    history: &mut History,
+    n: usize,
+    symbols: &Symbols,
+) -> Result<(), String> {
+    for _ in 0..n {
+        if history.step_back(cpu).is_none() {
+            return Err("no more history to step back".to_string());
+        }
+    }
+    println!(
+        "[+] stepped back to {} cycle {}",
+        symbols.format_addr(cpu.get_registers().PC),
+        cpu.get_cycles()
+    );
+    Ok(())
+}
+
+// breakpoints and memory watches set from the command console
+pub struct Debugger {
+    points: Vec<Point>,
+    events: Vec<Event>,
+    goal: Option<Goal>,
+    // expanded messages of logging breakpoints, see `take_logs`
+    logs: Vec<String>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            points: Vec::new(),
+            events: Vec::new(),
+            goal: None,
+            logs: Vec::new(),
+        }
+    }
+
+    // true while a run started by one of the step commands is active
+    pub fn is_running(&self) -> bool {
+        self.goal.is_some()
+    }
+
+    pub fn cancel(&mut self) {
+        self.goal = None;
+    }
+
+    pub fn step_over(&mut self, cpu: &Cpu) {
+        self.goal = Some(Goal::StepOver(cpu.get_registers().SP));
+    }
+
+    pub fn step_out(&mut self, cpu: &Cpu) -> Result<(), String> {
+        match cpu.get_registers().SP {
+            0 => Err("not inside a subroutine".to_string()),
+            depth => {
+                self.goal = Some(Goal::StepOut(depth));
+                Ok(())
+            }
+        }
+    }
+
+    pub fn run_to(&mut self, addr: u16) {
+        self.goal = Some(Goal::Address(addr));
+    }
+
+    pub fn run_frame(&mut self) {
+        self.goal = Some(Goal::Frame);
+    }
+
+    pub fn run_to_draw(&mut self) {
+        self.goal = Some(Goal::Draw);
+    }
+
+    // add a breakpoint from a `break` spec without printing it
+    pub fn add_break(&mut self, spec: &str, symbols: &Symbols) -> Result<(), String> {
+        self.points
+            .push(Point::parse(PointKind::Break, spec, symbols)?);
+        Ok(())
+    }
+
+    // remove all breakpoints and watches
+    pub fn clear_points(&mut self) {
+        self.points.clear();
+    }
+
+    // log messages of the breakpoints hit since the last call
+    pub fn take_logs(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.logs)
+    }
+
+    // execute one console command, the output is printed to stdout
+    pub fn command(
+        &mut self,
+        line: &str,
+        cpu: &mut Cpu,
+        history: &mut History,
+        symbols: &Symbols,
+    ) -> Result<(), String> {
+        let line = line.trim();
+        let (cmd, spec) = match line.find(' ') {
+            Some(pos) => (&line[..pos], line[pos + 1..].trim()),
+            None => (line, ""),
+        };
+        let kind = match cmd {
+            "break" | "b" => Some(PointKind::Break),
+            "watch" | "w" => Some(PointKind::Watch),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            let point = Point::parse(kind, spec, symbols)?;
+            match point.addr {
+                Some(addr) if kind == PointKind::Break && symbols.is_data(addr) => {
+                    println!("[-] {} is a data symbol", symbols.format_addr(addr))
+                }
+                _ => {}
+            }
+            println!("[+] #{} {}", self.points.len(), point.describe(symbols));
+            self.points.push(point);
+            return Ok(());
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [] => {}
+            ["delete", n] | ["d", n] if n.starts_with('#') => {
+                let n = n[1..]
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n < self.points.len())
+                    .ok_or(format!("no breakpoint {}", n))?;
+                self.points.remove(n);
+                println!("[+] deleted #{}", n);
+            }
+            ["delete", loc] | ["d", loc] => {
+                let addr = symbols.resolve(loc)?;
+                self.points.retain(|p| p.addr != Some(addr));
+                println!("[+] deleted {:04x}", addr);
+            }
+            ["info"] | ["i"] => {
+                for (n, p) in self.points.iter().enumerate() {
+                    let value = match p.addr {
+                        Some(addr) if p.kind == PointKind::Watch => {
+                            format!(" = {:02x}", cpu.get_mem()[addr as usize])
+                        }
+                        _ => String::new(),
+                    };
+                    println!("#{} {}{}, {} hits", n, p.describe(symbols), value, p.hits);
+                }
+                for e in &self.events {
+                    println!("catch {:?}", e);
+                }
+            }
+            ["catch", event @ ..] => {
+                let event = Event::parse(event)?;
+                if !self.events.contains(&event) {
+                    self.events.push(event);
+                }
+                println!("[+] catch {:?}", event);
+            }
+            ["uncatch", event @ ..] => {
+                let event = Event::parse(event)?;
+                self.events.retain(|&e| e != event);
+                println!("[+] removed catch {:?}", event);
+            }
+            ["x", loc] | ["x", loc, _] => {
+                let addr = symbols.resolve(loc)? as usize;
+                let len = match fields.get(2) {
+                    Some(len) => len
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid length '{}'", len))?,
+                    None => 1,
+                };
+                let mem = cpu.get_mem();
+                let end = std::cmp::min(addr + len, mem.len());
+                for row in (addr..end).step_by(8) {
+                    let bytes: Vec<String> = mem[row..std::cmp::min(row + 8, end)]
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect();
+                    println!(
+                        "{:04x} {:16} {}",
+                        row,
+                        symbols.format_addr(row as u16),
+                        bytes.join(" ")
+                    );
+                }
+            }
+            ["rstep"] | ["rs"] => step_back(cpu, history, 1, symbols)?,
+            ["rstep", n] | ["rs", n] => {
+                let n = n
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid count '{}'", n))?;
+                step_back(cpu, history, n, symbols)?;
+            }
+            ["rcontinue"] | ["rc"] => {
+                let reason = self.reverse_continue(cpu, history, symbols);
+                println!("[+] {}", reason);
+            }
+            ["lastwrite", loc] => {
+                let addr = symbols.resolve(loc)?;
+                match history.last_write(addr) {
+                    Some((entry, old, new)) => println!(
+                        "[+] {} last written in cycle {} by {}: {:02x} -> {:02x}",
+                        symbols.format_addr(addr),
+                        entry.state.cycles + 1,
+                        symbols.format_addr(entry.state.regs.PC),
+                        old,
+                        new
+                    ),
+                    None => println!(
+                        "[+] {} not written in the last {} instructions",
+                        symbols.format_addr(addr),
+                        history.len()
+                    ),
+                }
+            }
+            ["next"] | ["n"] => self.step_over(cpu),
+            ["finish"] => self.step_out(cpu)?,
+            ["until", loc] | ["u", loc] => self.run_to(symbols.resolve(loc)?),
+            ["frame"] => self.run_frame(),
+            ["draw"] => self.run_to_draw(),
+            ["help"] | ["h"] => println!("{}", HELP),
+            _ => return Err(format!("unknown command '{}', try 'help'", line.trim())),
+        }
+        Ok(())
+    }
+
+    // undo instructions until a breakpoint or watch would have stopped, the
+    // hit counts are not changed
+    pub fn reverse_continue(
+        &mut self,
+        cpu: &mut Cpu,
+        history: &mut History,
+        symbols: &Symbols,
+    ) -> String {
+        self.goal = None;
+        while let Some(entry) = history.step_back(cpu) {
+            let regs = cpu.get_registers();
+            for p in &self.points {
+                let reason = match (p.kind, p.addr) {
+                    (PointKind::Break, Some(addr)) if regs.PC == addr => {
+                        format!("breakpoint {}", symbols.format_addr(addr))
+                    }
+                    (PointKind::Watch, Some(addr)) => {
+                        match entry.writes.iter().find(|w| w.0 == addr) {
+                            Some(&(_, old, new)) => format!(
+                                "watch {}: {:02x} -> {:02x} by {}",
+                                symbols.format_addr(addr),
+                                old,
+                                new,
+                                symbols.format_addr(regs.PC)
+                            ),
+                            None => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+                let ctx = expr::Context {
+                    regs: &regs,
+                    mem: cpu.get_mem(),
+                    hitcount: p.hits,
+                };
+                let hit = match p.cond {
+                    Some((_, ref cond)) => cond.eval(&ctx) != 0,
+                    None => true,
+                };
+                if hit && p.log.is_none() {
+                    return reason;
+                }
+            }
+        }
+        format!(
+            "start of history at {}",
+            symbols.format_addr(cpu.get_registers().PC)
+        )
+    }
+
+    // check the last executed instruction, returns why execution should stop
+    pub fn check(&mut self, cpu: &Cpu, symbols: &Symbols) -> Option<String> {
+        let reason = self
+            .check_points(cpu, symbols)
+            .or_else(|| self.events.iter().find_map(|e| e.check(cpu, symbols)))
+            .or_else(|| self.check_goal(cpu, symbols));
+        if reason.is_some() {
+            self.goal = None;
+        }
+        reason
+    }
+
+    // all points are evaluated to count hits and print log messages, the
+    // first one that stops is reported
+    fn check_points(&mut self, cpu: &Cpu, symbols: &Symbols) -> Option<String> {
+        let regs = cpu.get_registers();
+        let mut stop = None;
+
+        for p in &mut self.points {
+            let reason = match (p.kind, p.addr) {
+                (PointKind::Break, Some(addr)) if regs.PC == addr => {
+                    format!("breakpoint {}", symbols.format_addr(addr))
+                }
+                (PointKind::Break, None) => format!(
+                    "condition {} at {}",
+                    p.cond.as_ref().map(|c| c.0.as_str()).unwrap_or(""),
+                    symbols.format_addr(regs.PC)
+                ),
+                (PointKind::Watch, Some(addr)) => match cpu
+                    .get_accesses()
+                    .iter()
+                    .find(|a| a.kind == AccessKind::Write && a.addr == addr)
+                {
+                    Some(a) => format!(
+                        "watch {}: {:02x} -> {:02x}",
+                        symbols.format_addr(addr),
+                        a.old,
+                        a.value
+                    ),
+                    None => continue,
+                },
+                _ => continue,
+            };
+
+            p.hits += 1;
+            let ctx = expr::Context {
+                regs: &regs,
+                mem: cpu.get_mem(),
+                hitcount: p.hits,
+            };
+            if let Some((_, ref cond)) = p.cond {
+                if cond.eval(&ctx) == 0 {
+                    continue;
+                }
+            }
+            match p.log {
+                Some(ref msg) => self.logs.push(expr::format_message(msg, symbols, &ctx)),
+                None => stop = stop.or(Some(reason)),
+            }
+        }
+        stop
+    }
+
+    fn check_goal(&self, cpu: &Cpu, symbols: &Symbols) -> Option<String> {
+        let regs = cpu.get_registers();
+        let pc = symbols.format_addr(regs.PC);
+        match self.goal {
+            Some(Goal::StepOver(depth)) if regs.SP <= depth => Some(format!("stepped to {}", pc)),
+            Some(Goal::StepOut(depth)) if regs.SP < depth => Some(format!("stepped out to {}", pc)),
+            Some(Goal::Address(addr)) if regs.PC == addr => Some(format!("reached {}", pc)),
+            Some(Goal::Draw) if cpu.get_next_n_instr(1)[0] & 0xf000 == 0xd000 => {
+                Some(format!("next DRW at {}", pc))
+            }
+            _ => None,
+        }
+    }
+
+    // called on every timer tick, ends a frame advance
+    pub fn tick(&mut self, cpu: &Cpu, symbols: &Symbols) -> Option<String> {
+        match self.goal {
+            Some(Goal::Frame) => {
+                self.goal = None;
+                Some(format!(
+                    "frame done at {}",
+                    symbols.format_addr(cpu.get_registers().PC)
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpu::Gpu;
+    use crate::history::CAPACITY;
+    use crate::memory::Memory;
+
+    #[test]
+    fn break_and_watch() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        // LD I, 0300 | LD V0, 05 | LD [I], V0 | JP 0200
+        cpu.load_rom(&[0xa3, 0x00, 0x60, 0x05, 0xf0, 0x55, 0x12, 0x00]);
+        let symbols = Symbols::parse("0x204 store\n0x300 score data\n").unwrap();
+
+        let mut dbg = Debugger::new();
+        let mut history = History::new(CAPACITY);
+        dbg.command("break store", &mut cpu, &mut history, &symbols)
+            .unwrap();
+        dbg.command("watch score", &mut cpu, &mut history, &symbols)
+            .unwrap();
+        assert!(dbg
+            .command("break nowhere", &mut cpu, &mut history, &symbols)
+            .is_err());
+        assert!(dbg
+            .command("jump", &mut cpu, &mut history, &symbols)
+            .is_err());
+
+        cpu.execute(vec![]);
+        assert_eq!(dbg.check(&cpu, &symbols), None);
+        cpu.execute(vec![]);
+        assert_eq!(
+            dbg.check(&cpu, &symbols),
+            Some("breakpoint store".to_string())
+        );
+        cpu.execute(vec![]);
+        assert_eq!(
+            dbg.check(&cpu, &symbols),
+            Some("watch score: 00 -> 05".to_string())
+        );
+
+        dbg.command("delete store", &mut cpu, &mut history, &symbols)
+            .unwrap();
+        cpu.execute(vec![]);
+        cpu.execute(vec![]);
+        cpu.execute(vec![]);
+        assert_eq!(dbg.check(&cpu, &symbols), None);
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        // 0200: CALL 0206 | JP 0200 | 0206: LD V0, 01 | CALL 020c | RET | 020c: RET
+        cpu.load_rom(&[
+            0x22, 0x06, 0x12, 0x00, 0x00, 0x00, 0x60, 0x01, 0x22, 0x0c, 0x00, 0xee, 0x00, 0xee,
+        ]);
+        let symbols = Symbols::new();
+        let mut dbg = Debugger::new();
+
+        let run = |dbg: &mut Debugger, cpu: &mut Cpu| {
+            for _ in 0..100 {
+                cpu.execute(vec![]);
+                if let Some(reason) = dbg.check(cpu, &symbols) {
+                    return reason;
+                }
+            }
+            panic!("goal not reached");
+        };
+
+        dbg.step_over(&cpu);
+        assert_eq!(run(&mut dbg, &mut cpu), "stepped to 0202");
+        assert!(!dbg.is_running());
+
+        cpu.execute(vec![]);
+        cpu.execute(vec![]);
+        // inside the routine at 0206
+        assert!(dbg.step_out(&Cpu::new(Memory::new(), Gpu::new())).is_err());
+        dbg.step_out(&cpu).unwrap();
+        assert_eq!(run(&mut dbg, &mut cpu), "stepped out to 0202");
+
+        dbg.run_to(0x20c);
+        assert_eq!(run(&mut dbg, &mut cpu), "reached 020c");
+
+        dbg.run_frame();
+        assert_eq!(dbg.check(&cpu, &symbols), None);
+        assert_eq!(
+            dbg.tick(&cpu, &symbols),
+            Some("frame done at 020c".to_string())
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        // 0200: ADD V3, 04 | JP 0200
+        cpu.load_rom(&[0x73, 0x04, 0x12, 0x00]);
+        let symbols = Symbols::parse("0x200 loop\n").unwrap();
+
+        let mut dbg = Debugger::new();
+        let mut history = History::new(CAPACITY);
+        dbg.command("break loop if V3 > 0x10", &mut cpu, &mut history, &symbols)
+            .unwrap();
+        dbg.command(
+            "break loop log \"V3={V3}\"",
+            &mut cpu,
+            &mut history,
+            &symbols,
+        )
+        .unwrap();
+        dbg.command(
+            "break if hitcount == 3 && PC == 0x202",
+            &mut cpu,
+            &mut history,
+            &symbols,
+        )
+        .unwrap();
+        assert!(dbg
+            .command("break", &mut cpu, &mut history, &symbols)
+            .is_err());
+        assert!(dbg
+            .command("watch if V0", &mut cpu, &mut history, &symbols)
+            .is_err());
+        assert!(dbg
+            .command("break loop if V3 >", &mut cpu, &mut history, &symbols)
+            .is_err());
+
+        let mut stops = Vec::new();
+        for _ in 0..12 {
+            cpu.execute(vec![]);
+            if let Some(reason) = dbg.check(&cpu, &symbols) {
+                stops.push((cpu.get_registers().V[3], reason));
+            }
+        }
+        assert_eq!(
+            stops,
+            vec![
+                (
+                    0x08,
+                    "condition hitcount == 3 && PC == 0x202 at loop+2".to_string()
+                ),
+                (0x14, "breakpoint loop".to_string()),
+                (0x18, "breakpoint loop".to_string()),
+            ]
+        );
+        assert_eq!(dbg.points[1].hits, 6);
+
+        dbg.command("delete #0", &mut cpu, &mut history, &symbols)
+            .unwrap();
+        dbg.command("delete loop", &mut cpu, &mut history, &symbols)
+            .unwrap();
+        assert_eq!(dbg.points.len(), 1);
+    }
+
+    #[test]
+    fn events() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        cpu.load_rom(&[
+            0x00, 0xe0, // 0200: CLS
+            0x60, 0x05, // 0202: LD V0, 05
+            0xf0, 0x18, // 0204: LD ST, V0
+            0xa0, 0x00, // 0206: LD I, 0000
+            0xd0, 0x05, // 0208: DRW V0, V0, 5
+            0xd0, 0x05, // 020a: DRW V0, V0, 5
+            0xf0, 0x55, // 020c: LD [I], V0
+            0x22, 0x14, // 020e: CALL 0214
+            0x00, 0x00, // 0210:
+            0x00, 0x00, // 0212:
+            0x61, 0x01, // 0214: LD V1, 01
+            0xf0, 0x0a, // 0216: LD V0, K
+            0x12, 0x1b, // 0218: JP 021b
+        ]);
+        let symbols = Symbols::new();
+        let mut dbg = Debugger::new();
+        let mut history = History::new(CAPACITY);
+        for event in &[
+            "cls",
+            "collision",
+            "keywait",
+            "sound",
+            "depth 0",
+            "lowwrite",
+            "oddpc",
+        ] {
+            dbg.command(
+                &format!("catch {}", event),
+                &mut cpu,
+                &mut history,
+                &symbols,
+            )
+            .unwrap();
+        }
+        assert!(dbg
+            .command("catch nothing", &mut cpu, &mut history, &symbols)
+            .is_err());
+        assert!(dbg
+            .command("catch depth x", &mut cpu, &mut history, &symbols)
+            .is_err());
+
+        let mut stops = Vec::new();
+        for _ in 0..11 {
+            cpu.execute(vec![1]);
+            if let Some(reason) = dbg.check(&cpu, &symbols) {
+                stops.push(reason);
+            }
+        }
+        assert_eq!(
+            stops,
+            vec![
+                "CLS at 0200",
+                "sound timer set to 05 at 0204",
+                "DRW collision at 020a",
+                "write to interpreter area 0000 = 05 at 020c",
+                "stack depth 1 > 0 at 020e",
+                "key wait at 0216",
+                "odd PC 021b",
+            ]
+        );
+
+        dbg.command("uncatch oddpc", &mut cpu, &mut history, &symbols)
+            .unwrap();
+        assert_eq!(dbg.events.len(), 6);
+    }
+
+    #[test]
+    fn sound_reload() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        cpu.load_rom(&[
+            0x60, 0x05, // 0200: LD V0, 05
+            0xf0, 0x18, // 0202: LD ST, V0
+            0xf0, 0x18, // 0204: LD ST, V0
+            0x61, 0x00, // 0206: LD V1, 00
+            0xf1, 0x18, // 0208: LD ST, V1
+            0xf0, 0x18, // 020a: LD ST, V0
+        ]);
+        let symbols = Symbols::new();
+        let mut dbg = Debugger::new();
+        let mut history = History::new(CAPACITY);
+        dbg.command("catch sound", &mut cpu, &mut history, &symbols)
+            .unwrap();
+
+        let mut stops = Vec::new();
+        for _ in 0..6 {
+            cpu.execute(vec![]);
+            if let Some(reason) = dbg.check(&cpu, &symbols) {
+                stops.push(reason);
+            }
+        }
+        // the reload at 0204 keeps the running tone
+        assert_eq!(
+            stops,
+            vec![
+                "sound timer set to 05 at 0202",
+                "sound timer set to 05 at 020a",
+            ]
+        );
+    }
+
+    #[test]
+    fn reverse() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        // 0200: ADD V3, 04 | LD I, 0300 | LD [I], V3 | JP 0200
+        cpu.load_rom(&[0x73, 0x04, 0xa3, 0x00, 0xf3, 0x55, 0x12, 0x00]);
+        let symbols = Symbols::new();
+        let mut dbg = Debugger::new();
+        let mut history = History::new(CAPACITY);
+        for _ in 0..12 {
+            let pending = History::begin(&cpu);
+            cpu.execute(vec![]);
+            history.push(pending, &cpu);
+        }
+
+        dbg.command("break 202 if V3 == 8", &mut cpu, &mut history, &symbols)
+            .unwrap();
+        assert_eq!(
+            dbg.reverse_continue(&mut cpu, &mut history, &symbols),
+            "breakpoint 0202"
+        );
+        assert_eq!(cpu.get_registers().V[3], 8);
+
+        dbg.command("watch 303", &mut cpu, &mut history, &symbols)
+            .unwrap();
+        assert_eq!(
+            dbg.reverse_continue(&mut cpu, &mut history, &symbols),
+            "watch 0303: 00 -> 04 by 0204"
+        );
+
+        dbg.command("rstep 2", &mut cpu, &mut history, &symbols)
+            .unwrap();
+        assert_eq!(cpu.get_registers().PC, 0x200);
+        assert!(dbg
+            .command("rstep", &mut cpu, &mut history, &symbols)
+            .is_err());
+        assert_eq!(
+            dbg.reverse_continue(&mut cpu, &mut history, &symbols),
+            "start of history at 0200"
+        );
+    }
+}
@@ -36,6 +36,49 @@ pub enum Instruction {
     DisplaySpriteVxVyNibble(usize, usize, u8),
 }
 
+impl Instruction {
+    // name of the instruction variant without operands
+    pub fn name(&self) -> &'static str {
+        use Instruction::*;
+        match self {
+            ClearDisplay => "ClearDisplay",
+            Return => "Return",
+            Jump(..) => "Jump",
+            JumpV0Addr(..) => "JumpV0Addr",
+            Call(..) => "Call",
+            SkipEqVxByte(..) => "SkipEqVxByte",
+            SkipEqVxVy(..) => "SkipEqVxVy",
+            SkipKeyPressedVx(..) => "SkipKeyPressedVx",
+            SkipKeyNotPressedVx(..) => "SkipKeyNotPressedVx",
+            SkipNeqVxByte(..) => "SkipNeqVxByte",
+            SkipNeqVxVy(..) => "SkipNeqVxVy",
+            LoadRegsVx(..) => "LoadRegsVx",
+            StoreRegsVx(..) => "StoreRegsVx",
+            LoadBVx(..) => "LoadBVx",
+            LoadDTVx(..) => "LoadDTVx",
+            LoadIAddr(..) => "LoadIAddr",
+            LoadSTVx(..) => "LoadSTVx",
+            LoadSpriteAddrVx(..) => "LoadSpriteAddrVx",
+            LoadVxByte(..) => "LoadVxByte",
+            LoadVxDT(..) => "LoadVxDT",
+            LoadVxKey(..) => "LoadVxKey",
+            LoadVxVy(..) => "LoadVxVy",
+            AddIVx(..) => "AddIVx",
+            AddVxByte(..) => "AddVxByte",
+            AddVxVy(..) => "AddVxVy",
+            SubVxVy(..) => "SubVxVy",
+            SubnVxVy(..) => "SubnVxVy",
+            XorVxVy(..) => "XorVxVy",
+            AndVxVy(..) => "AndVxVy",
+            OrVxVy(..) => "OrVxVy",
+            ShlVxby1(..) => "ShlVxby1",
+            ShrVxby1(..) => "ShrVxby1",
+            RandVxAndByte(..) => "RandVxAndByte",
+            DisplaySpriteVxVyNibble(..) => "DisplaySpriteVxVyNibble",
+        }
+    }
+}
+
 struct InstructionCode {
     opcode: u16,
     mask: u16,
@@ -275,6 +318,11 @@ pub fn decode(instr: u16) -> Option<Instruction> {
 }
 
 pub fn disassemble(instr: u16) -> String {
+    disassemble_with(instr, |addr| format!("{:04x}", addr))
+}
+
+// disassemble with `fmt_addr` formatting the address operands
+pub fn disassemble_with<F: Fn(u16) -> String>(instr: u16, fmt_addr: F) -> String {
     match CHIP8_INSTRUCTIONS
         .iter()
         .find(|i| (instr & i.mask) == i.opcode)
@@ -292,8 +340,8 @@ pub fn disassemble(instr: u16) -> String {
             let disasm = match opcode {
                 0x00e0 => format!("CLS"),
                 0x00ee => format!("RET"),
-                0x1000 => format!("JP {:04x}", nnn),
-                0x2000 => format!("CALL {:04x}", nnn),
+                0x1000 => format!("JP {}", fmt_addr(nnn)),
+                0x2000 => format!("CALL {}", fmt_addr(nnn)),
                 0x3000 => format!("SE V{:1x}, {:02x}", vx, nn),
                 0x4000 => format!("SNE V{:1x}, {:02x}", vx, nn),
                 0x5000 => format!("SE V{:1x}, V{:1x}", vx, vy),
@@ -309,8 +357,8 @@ pub fn disassemble(instr: u16) -> String {
                 0x8007 => format!("SUBN V{:1x}, V{:1x}", vx, vy),
                 0x800e => format!("SHL {:1x}", vx),
                 0x9000 => format!("SNE V{:1x}, V{:1x}", vx, vy),
-                0xa000 => format!("LD I, {:04x}", nnn),
-                0xb000 => format!("JP V0, {:04x}", nnn),
+                0xa000 => format!("LD I, {}", fmt_addr(nnn)),
+                0xb000 => format!("JP V0, {}", fmt_addr(nnn)),
                 0xc000 => format!("RND V{:1x}, {:04x}", vx, nn),
                 0xd000 => format!("DRW V{:1x}, V{:1x}, {:1x}", vx, vy, n),
                 0xe09e => format!("SKP V{:1x}", vx),
@@ -397,6 +445,25 @@ mod unittest {
         assert_eq!(Some(Instruction::LoadRegsVx(0xb)), decode(0xfb65));
     }
 
+    #[test]
+    fn test_instr_name() {
+        assert_eq!(decode(0x00e0).unwrap().name(), "ClearDisplay");
+        assert_eq!(decode(0xdabc).unwrap().name(), "DisplaySpriteVxVyNibble");
+    }
+
+    #[test]
+    fn test_disassemble_with() {
+        assert_eq!(disassemble(0x22a4), "CALL 02a4");
+        assert_eq!(
+            disassemble_with(0x22a4, |_| "draw".to_string()),
+            "CALL draw"
+        );
+        assert_eq!(
+            disassemble_with(0x6012, |_| "draw".to_string()),
+            "LD V0, 12"
+        );
+    }
+
     #[test]
     fn test_unknown_nistr() {
         assert_eq!(None, decode(0xf00d));
@@ -0,0 +1,338 @@
+use super::cpu::{Cpu, Quirks, Registers, INSTR_PER_TICK};
+use super::decoder;
+use super::trace;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+// number of instructions shown before a divergence
+const CONTEXT: usize = 8;
+
+// (cycle, pc, opcode) of the recently executed instructions
+type History = VecDeque<(u64, u16, u16)>;
+
+// list of registers which differ between `a` and `b`
+fn diff_registers(a: &Registers, b: &Registers) -> Vec<String> {
+    let mut diffs = Vec::new();
+    for i in 0..16 {
+        if a.V[i] != b.V[i] {
+            diffs.push(format!("V{:X}: a={:02x} b={:02x}", i, a.V[i], b.V[i]));
+        }
+    }
+    if a.I != b.I {
+        diffs.push(format!("I : a={:04x} b={:04x}", a.I, b.I));
+    }
+    if a.DT != b.DT {
+        diffs.push(format!("DT: a={:02x} b={:02x}", a.DT, b.DT));
+    }
+    if a.ST != b.ST {
+        diffs.push(format!("ST: a={:02x} b={:02x}", a.ST, b.ST));
+    }
+    if a.PC != b.PC {
+        diffs.push(format!("PC: a={:04x} b={:04x}", a.PC, b.PC));
+    }
+    if a.SP != b.SP {
+        diffs.push(format!("SP: a={} b={}", a.SP, b.SP));
+    }
+    diffs
+}
+
+// first differing register, memory byte and framebuffer pixel
+fn diff_cpus(a: &Cpu, b: &Cpu) -> Vec<String> {
+    let mut diffs = diff_registers(&a.get_registers(), &b.get_registers());
+
+    if let Some(addr) = (0..a.get_mem().len()).find(|&i| a.get_mem()[i] != b.get_mem()[i]) {
+        diffs.push(format!(
+            "mem[{:03x}]: a={:02x} b={:02x}",
+            addr,
+            a.get_mem()[addr],
+            b.get_mem()[addr]
+        ));
+    }
+
+    let (fa, fb) = (a.get_fb(), b.get_fb());
+    if let Some((x, y)) = fa.diff(fb).next() {
+        diffs.push(format!(
+            "pixel ({}, {}): a={} b={}",
+            x,
+            y,
+            fa.get(x, y) as u8,
+            fb.get(x, y) as u8
+        ));
+    }
+    diffs
+}
+
+pub fn panic_msg(err: Box<dyn std::any::Any + Send>) -> String {
+    match err.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(err) => match err.downcast::<&str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+// execute one instruction without input, a panicking cpu is reported as error
+fn step(cpu: &mut Cpu, history: &mut History) -> Result<(), String> {
+    let pc = cpu.get_registers().PC;
+    let opcode = cpu.get_next_n_instr(1)[0];
+
+    panic::catch_unwind(AssertUnwindSafe(|| cpu.execute(Vec::new()))).map_err(panic_msg)?;
+    if cpu.get_cycles().is_multiple_of(INSTR_PER_TICK) {
+        cpu.timer_tick();
+    }
+
+    if history.len() == CONTEXT {
+        history.pop_front();
+    }
+    history.push_back((cpu.get_cycles(), pc, opcode));
+    Ok(())
+}
+
+fn print_context(name: &str, cpu: &Cpu, history: &History) {
+    println!("---- {} ----", name);
+    for &(cycle, pc, opcode) in history {
+        println!(
+            "  {:>8} {:04x}: {:04x}  {}",
+            cycle,
+            pc,
+            opcode,
+            decoder::disassemble(opcode)
+        );
+    }
+    let pc = cpu.get_registers().PC;
+    for (i, &opcode) in cpu.get_next_n_instr(2).iter().enumerate() {
+        println!(
+            "{} {:>8} {:04x}: {:04x}  {}",
+            if i == 0 { ">" } else { " " },
+            "",
+            pc + 2 * i as u16,
+            opcode,
+            decoder::disassemble(opcode)
+        );
+    }
+}
+
+// run `cpu` and a copy of it using `quirks_b` in lock step for at most
+// `max_cycles` instructions and report the first state which differs,
+// returns the number of instructions executed up to the divergence
+pub fn run_side_by_side(
+    cpu: Cpu,
+    quirks_b: Quirks,
+    max_cycles: u64,
+) -> Result<Option<u64>, String> {
+    let mut a = cpu;
+    let mut b = a.clone();
+    b.set_quirks(quirks_b);
+
+    let mut history_a = History::with_capacity(CONTEXT);
+    let mut history_b = History::with_capacity(CONTEXT);
+
+    while a.get_cycles() < max_cycles {
+        let res_a = step(&mut a, &mut history_a);
+        let res_b = step(&mut b, &mut history_b);
+
+        let mut diffs = diff_cpus(&a, &b);
+        match (res_a, res_b) {
+            (Ok(_), Ok(_)) => {}
+            (Err(e), Ok(_)) => diffs.push(format!("a stopped: {}", e)),
+            (Ok(_), Err(e)) => diffs.push(format!("b stopped: {}", e)),
+            (Err(_), Err(e)) if diffs.is_empty() => {
+                println!(
+                    "[+] no divergence, both stopped after {} instructions: {}",
+                    a.get_cycles(),
+                    e
+                );
+                return Ok(None);
+            }
+            (Err(e_a), Err(e_b)) => {
+                diffs.push(format!("a stopped: {}", e_a));
+                diffs.push(format!("b stopped: {}", e_b));
+            }
+        }
+
+        if !diffs.is_empty() {
+            println!("[!] first divergence after instruction {}", a.get_cycles());
+            for d in &diffs {
+                println!("    {}", d);
+            }
+            print_context("a", &a, &history_a);
+            print_context("b", &b, &history_b);
+            return Ok(Some(a.get_cycles()));
+        }
+    }
+
+    println!("[+] no divergence within {} instructions", max_cycles);
+    Ok(None)
+}
+
+fn print_records(name: &str, records: &[trace::Record], mark: usize) {
+    println!("---- {} ----", name);
+    let start = mark.saturating_sub(CONTEXT);
+    let end = std::cmp::min(mark + 2, records.len());
+    for (i, r) in records.iter().enumerate().take(end).skip(start) {
+        println!(
+            "{} {:>8} {:04x}: {:04x}  {}",
+            if i == mark { ">" } else { " " },
+            r.cycle,
+            r.pc,
+            r.opcode,
+            decoder::disassemble(r.opcode)
+        );
+    }
+}
+
+fn compare_binary_traces<P: AsRef<Path>>(path_a: P, path_b: P) -> Result<Option<usize>, String> {
+    let a = trace::read_binary(path_a)?;
+    let b = trace::read_binary(path_b)?;
+
+    for (i, (ra, rb)) in a.iter().zip(b.iter()).enumerate() {
+        let mut diffs = Vec::new();
+        if ra.pc != rb.pc {
+            diffs.push(format!("executed PC: a={:04x} b={:04x}", ra.pc, rb.pc));
+        }
+        if ra.opcode != rb.opcode {
+            diffs.push(format!("opcode: a={:04x} b={:04x}", ra.opcode, rb.opcode));
+        }
+        diffs.extend(diff_registers(&ra.regs, &rb.regs));
+
+        if !diffs.is_empty() {
+            println!("[!] first divergence at record {} (cycle {})", i, ra.cycle);
+            for d in &diffs {
+                println!("    {}", d);
+            }
+            print_records("a", &a, i);
+            print_records("b", &b, i);
+            return Ok(Some(i));
+        }
+    }
+
+    if a.len() != b.len() {
+        let end = std::cmp::min(a.len(), b.len());
+        println!(
+            "[!] traces are equal up to record {}, a has {} and b has {} records",
+            end,
+            a.len(),
+            b.len()
+        );
+        Ok(Some(end))
+    } else {
+        println!("[+] traces are equal ({} records)", a.len());
+        Ok(None)
+    }
+}
+
+fn read_lines<P: AsRef<Path>>(path: P) -> Result<Vec<String>, String> {
+    File::open(&path)
+        .and_then(|f| BufReader::new(f).lines().collect())
+        .map_err(|e| format!("Failed to read {}: {}", path.as_ref().display(), e))
+}
+
+fn print_lines(name: &str, lines: &[String], mark: usize) {
+    println!("---- {} ----", name);
+    let start = mark.saturating_sub(CONTEXT);
+    let end = std::cmp::min(mark + 2, lines.len());
+    for (i, line) in lines.iter().enumerate().take(end).skip(start) {
+        println!(
+            "{} {:>6}: {}",
+            if i == mark { ">" } else { " " },
+            i + 1,
+            line
+        );
+    }
+}
+
+fn compare_text_traces<P: AsRef<Path>>(path_a: P, path_b: P) -> Result<Option<usize>, String> {
+    let a = read_lines(path_a)?;
+    let b = read_lines(path_b)?;
+
+    match a.iter().zip(b.iter()).position(|(la, lb)| la != lb) {
+        Some(i) => {
+            println!("[!] first divergence at line {}", i + 1);
+            print_lines("a", &a, i);
+            print_lines("b", &b, i);
+            Ok(Some(i))
+        }
+        None if a.len() != b.len() => {
+            let end = std::cmp::min(a.len(), b.len());
+            println!(
+                "[!] traces are equal up to line {}, a has {} and b has {} lines",
+                end,
+                a.len(),
+                b.len()
+            );
+            Ok(Some(end))
+        }
+        None => {
+            println!("[+] traces are equal ({} lines)", a.len());
+            Ok(None)
+        }
+    }
+}
+
+// compare two trace files written with `--trace`, both must use the same format,
+// returns the index of the first record (or line) which differs
+pub fn compare_traces<P: AsRef<Path>>(path_a: P, path_b: P) -> Result<Option<usize>, String> {
+    match (trace::is_binary(&path_a), trace::is_binary(&path_b)) {
+        (true, true) => compare_binary_traces(path_a, path_b),
+        (false, false) => compare_text_traces(path_a, path_b),
+        _ => Err("Can not compare a binary with a text trace".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpu::Gpu;
+    use crate::memory::Memory;
+
+    #[test]
+    fn registers() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        // LD V1, 0xff | SHR V0, V1
+        cpu.load_rom(&[0x61, 0xff, 0x80, 0x16]);
+        let mut history = History::new();
+        step(&mut cpu, &mut history).unwrap();
+
+        let mut b = cpu.clone();
+        b.set_quirks(Quirks::parse("shift-vy").unwrap());
+        step(&mut cpu, &mut history).unwrap();
+        step(&mut b, &mut history).unwrap();
+
+        assert_eq!(
+            diff_cpus(&cpu, &b),
+            vec!["V0: a=00 b=7f".to_string(), "VF: a=00 b=01".to_string()]
+        );
+        assert_eq!(history.len(), 3);
+    }
+
+    #[test]
+    fn side_by_side() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        // LD V1, 0xff | LD V2, 0x01 | SHR V0, V1 | JP 0x206
+        cpu.load_rom(&[0x61, 0xff, 0x62, 0x01, 0x80, 0x16, 0x12, 0x06]);
+        let quirks = Quirks::parse("shift-vy").unwrap();
+        assert_eq!(run_side_by_side(cpu.clone(), quirks, 100), Ok(Some(3)));
+        assert_eq!(run_side_by_side(cpu, Quirks::default(), 100), Ok(None));
+    }
+
+    #[test]
+    fn text_traces() {
+        let dir = std::env::temp_dir();
+        let path_a = dir.join(format!("chip8-diff-{}-a.txt", std::process::id()));
+        let path_b = dir.join(format!("chip8-diff-{}-b.txt", std::process::id()));
+        std::fs::write(&path_a, "0200: 61ff\n0202: 6201\n0204: 8016\n").unwrap();
+        std::fs::write(&path_b, "0200: 61ff\n0202: 6201\n0204: 8006\n").unwrap();
+
+        let res = compare_traces(&path_a, &path_b);
+        let same = compare_traces(&path_a, &path_a);
+        std::fs::remove_file(&path_a).unwrap();
+        std::fs::remove_file(&path_b).unwrap();
+        assert_eq!(res, Ok(Some(2)));
+        assert_eq!(same, Ok(None));
+    }
+}
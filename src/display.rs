@@ -0,0 +1,152 @@
+use super::gpu;
+
+// colors of the game pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub fg: u32,
+    pub bg: u32,
+}
+
+impl Default for Palette {
+    // red on black, the original look
+    fn default() -> Palette {
+        Palette {
+            fg: 0x00ff0000,
+            bg: 0x00000000,
+        }
+    }
+}
+
+impl Palette {
+    // color of a pixel with brightness `level` 0.0 - 1.0, between bg and fg
+    pub fn mix(&self, level: f32) -> u32 {
+        if level >= 1.0 {
+            return self.fg;
+        } else if level <= 0.0 {
+            return self.bg;
+        }
+        let channel = |shift: u32| {
+            let fg = (self.fg >> shift & 0xff) as f32;
+            let bg = (self.bg >> shift & 0xff) as f32;
+            ((bg + (fg - bg) * level).round() as u32) << shift
+        };
+        channel(16) | channel(8) | channel(0)
+    }
+}
+
+// parse a `rrggbb` color, optionally prefixed with `#` or `0x`
+pub fn parse_color(s: &str) -> Result<u32, String> {
+    let hex = s.trim_start_matches('#').trim_start_matches("0x");
+    match u32::from_str_radix(hex, 16) {
+        Ok(color) if hex.len() == 6 => Ok(color),
+        _ => Err(format!("invalid color '{}', expected rrggbb", s)),
+    }
+}
+
+// 0x00rrggbb pixels, row major
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, color: u32) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![color; width * height],
+        }
+    }
+
+    // copy `src` rows of `src_width` pixels with their top left corner at
+    // (x, y), clipped to this image
+    pub fn blit(&mut self, src: &[u32], src_width: usize, x: usize, y: usize) {
+        if x >= self.width || src_width == 0 {
+            return;
+        }
+        let w = std::cmp::min(src_width, self.width - x);
+        for (row, line) in src.chunks(src_width).enumerate() {
+            if y + row >= self.height {
+                break;
+            }
+            let start = (y + row) * self.width + x;
+            self.pixels[start..start + w].copy_from_slice(&line[..w]);
+        }
+    }
+}
+
+// game pixel brightness (see filter.rs) scaled by `scale` in the palette
+// colors
+pub fn render(levels: &[f32], palette: Palette, scale: usize) -> Image {
+    let mut img = Image::new(gpu::WIDTH * scale, gpu::HEIGHT * scale, palette.bg);
+    for (idx, _) in levels.iter().enumerate().filter(|&(_, &l)| l > 0.0) {
+        let (x, y) = (idx % gpu::WIDTH * scale, idx / gpu::WIDTH * scale);
+        let color = palette.mix(levels[idx]);
+        for row in y..y + scale {
+            let start = row * img.width + x;
+            for p in &mut img.pixels[start..start + scale] {
+                *p = color;
+            }
+        }
+    }
+    img
+}
+
+// largest integer scale at which the game fits into `width` x `height` and
+// the top left corner that centers it, the remaining border is letterboxed
+pub fn letterbox(width: usize, height: usize) -> (usize, usize, usize) {
+    let scale = std::cmp::max(1, std::cmp::min(width / gpu::WIDTH, height / gpu::HEIGHT));
+    let x = width.saturating_sub(gpu::WIDTH * scale) / 2;
+    let y = height.saturating_sub(gpu::HEIGHT * scale) / 2;
+    (scale, x, y)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn letterboxing() {
+        assert_eq!(letterbox(640, 320), (10, 0, 0));
+        // 4:3 window, bars above and below
+        assert_eq!(letterbox(640, 480), (10, 0, 80));
+        // not an integer multiple, bars on all sides
+        assert_eq!(letterbox(700, 200), (6, 158, 4));
+        assert_eq!(letterbox(10, 10), (1, 0, 0));
+    }
+
+    #[test]
+    fn render_and_blit() {
+        let mut levels = vec![0.0; gpu::WIDTH * gpu::HEIGHT];
+        levels[gpu::WIDTH + 1] = 1.0;
+        let palette = Palette { fg: 0xff, bg: 0x11 };
+        let game = render(&levels, palette, 2);
+        assert_eq!((game.width, game.height), (128, 64));
+        assert_eq!(game.pixels[2 * 128 + 2], 0xff);
+        assert_eq!(game.pixels[3 * 128 + 3], 0xff);
+        assert_eq!(game.pixels[3 * 128 + 4], 0x11);
+
+        let mut out = Image::new(100, 70, 0);
+        out.blit(&game.pixels, game.width, 0, 4);
+        assert_eq!(out.pixels[6 * 100 + 2], 0xff);
+        assert_eq!(out.pixels[3 * 100], 0);
+        // clipped right, the game ends in row 67
+        assert_eq!(out.pixels[67 * 100 + 99], 0x11);
+        assert_eq!(out.pixels[68 * 100], 0);
+
+        assert_eq!(parse_color("#00ff80"), Ok(0x00ff80));
+        assert!(parse_color("fff").is_err());
+    }
+
+    #[test]
+    fn mixing() {
+        let palette = Palette {
+            fg: 0x00ff8000,
+            bg: 0x00000040,
+        };
+        assert_eq!(palette.mix(1.0), 0x00ff8000);
+        assert_eq!(palette.mix(0.0), 0x00000040);
+        assert_eq!(palette.mix(0.5), 0x00804020);
+    }
+}
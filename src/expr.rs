@@ -0,0 +1,352 @@
+use super::cpu::Registers;
+use super::symbols::Symbols;
+
+// expressions for breakpoint conditions and log messages
+//
+//   operands   decimal or 0x hex numbers, V0-VF, I, PC, DT, ST, SP (stack
+//              depth), hitcount and symbol names (their address)
+//   memory     [expr] reads the byte at expr
+//   operators  by increasing precedence: || && == != < <= > >= | ^ & + - and
+//              the unary ! -
+//
+// comparisons evaluate to 1 or 0, a non zero value is true
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(i64),
+    Var(Var),
+    Mem(Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Var {
+    V(usize),
+    I,
+    PC,
+    DT,
+    ST,
+    SP,
+    HitCount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+// binary operators per precedence level, lowest first
+const LEVELS: [&[(&str, BinOp)]; 7] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[
+        ("==", BinOp::Eq),
+        ("!=", BinOp::Ne),
+        ("<=", BinOp::Le),
+        (">=", BinOp::Ge),
+        ("<", BinOp::Lt),
+        (">", BinOp::Gt),
+    ],
+    &[("|", BinOp::BitOr)],
+    &[("^", BinOp::BitXor)],
+    &[("&", BinOp::BitAnd)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+];
+
+// state an expression is evaluated against
+pub struct Context<'a> {
+    pub regs: &'a Registers,
+    pub mem: &'a [u8],
+    pub hitcount: u64,
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else {
+            let two: String = chars[i..std::cmp::min(i + 2, chars.len())].iter().collect();
+            if ["||", "&&", "==", "!=", "<=", ">="].contains(&two.as_str()) {
+                tokens.push(two);
+                i += 2;
+            } else if "|&<>^+-![]()".contains(c) {
+                tokens.push(c.to_string());
+                i += 1;
+            } else {
+                return Err(format!("unexpected character '{}'", c));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<String>,
+    pos: usize,
+    symbols: &'a Symbols,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| "unexpected end of expression".to_string())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        match self.next()? {
+            ref t if t == token => Ok(()),
+            t => Err(format!("expected '{}', found '{}'", token, t)),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(&(_, op)) = LEVELS[level]
+            .iter()
+            .find(|&&(token, _)| self.peek() == Some(token))
+        {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = self.next()?;
+        match token.as_str() {
+            "!" => Ok(Expr::Not(Box::new(self.unary()?))),
+            "-" => Ok(Expr::Neg(Box::new(self.unary()?))),
+            "(" => {
+                let e = self.binary(0)?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            "[" => {
+                let e = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Mem(Box::new(e)))
+            }
+            _ => self.operand(&token),
+        }
+    }
+
+    fn operand(&self, token: &str) -> Result<Expr, String> {
+        let var = match token.to_ascii_uppercase().as_str() {
+            "I" => Some(Var::I),
+            "PC" => Some(Var::PC),
+            "DT" => Some(Var::DT),
+            "ST" => Some(Var::ST),
+            "SP" => Some(Var::SP),
+            "HITCOUNT" => Some(Var::HitCount),
+            t if t.len() == 2 && t.starts_with('V') => {
+                usize::from_str_radix(&t[1..], 16).ok().map(Var::V)
+            }
+            _ => None,
+        };
+        if let Some(var) = var {
+            return Ok(Expr::Var(var));
+        }
+
+        if let Some(addr) = self.symbols.lookup(token) {
+            return Ok(Expr::Num(addr as i64));
+        }
+        let num = match token
+            .strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+        {
+            Some(hex) => i64::from_str_radix(hex, 16),
+            None => token.parse::<i64>(),
+        };
+        num.map(Expr::Num)
+            .map_err(|_| format!("unknown operand '{}'", token))
+    }
+}
+
+impl Expr {
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            symbols,
+        };
+        let e = parser.binary(0)?;
+        match parser.peek() {
+            None => Ok(e),
+            Some(t) => Err(format!("unexpected '{}'", t)),
+        }
+    }
+
+    pub fn eval(&self, ctx: &Context) -> i64 {
+        match self {
+            Expr::Num(n) => *n,
+            Expr::Var(var) => match var {
+                Var::V(i) => ctx.regs.V[*i] as i64,
+                Var::I => ctx.regs.I as i64,
+                Var::PC => ctx.regs.PC as i64,
+                Var::DT => ctx.regs.DT as i64,
+                Var::ST => ctx.regs.ST as i64,
+                Var::SP => ctx.regs.SP as i64,
+                Var::HitCount => ctx.hitcount as i64,
+            },
+            Expr::Mem(addr) => {
+                let addr = addr.eval(ctx).rem_euclid(ctx.mem.len() as i64);
+                ctx.mem[addr as usize] as i64
+            }
+            Expr::Not(e) => (e.eval(ctx) == 0) as i64,
+            Expr::Neg(e) => e.eval(ctx).wrapping_neg(),
+            Expr::Binary(op, lhs, rhs) => {
+                let l = lhs.eval(ctx);
+                // short circuit the logical operators
+                match op {
+                    BinOp::Or if l != 0 => return 1,
+                    BinOp::And if l == 0 => return 0,
+                    _ => {}
+                }
+                let r = rhs.eval(ctx);
+                match op {
+                    BinOp::Or | BinOp::And => (r != 0) as i64,
+                    BinOp::Eq => (l == r) as i64,
+                    BinOp::Ne => (l != r) as i64,
+                    BinOp::Lt => (l < r) as i64,
+                    BinOp::Le => (l <= r) as i64,
+                    BinOp::Gt => (l > r) as i64,
+                    BinOp::Ge => (l >= r) as i64,
+                    BinOp::BitOr => l | r,
+                    BinOp::BitXor => l ^ r,
+                    BinOp::BitAnd => l & r,
+                    BinOp::Add => l.wrapping_add(r),
+                    BinOp::Sub => l.wrapping_sub(r),
+                }
+            }
+        }
+    }
+}
+
+// expand `{expr}` placeholders in a log message to their hex value
+pub fn format_message(msg: &str, symbols: &Symbols, ctx: &Context) -> String {
+    let mut out = String::new();
+    let mut rest = msg;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        out.push_str(&rest[..start]);
+        match Expr::parse(&rest[start + 1..end], symbols) {
+            Ok(e) => out.push_str(&format!("{:x}", e.eval(ctx))),
+            // keep invalid placeholders verbatim
+            Err(_) => out.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval(text: &str) -> i64 {
+        let mut regs = Registers {
+            V: [0; 16],
+            I: 0x300,
+            DT: 0,
+            ST: 0,
+            PC: 0x2a4,
+            SP: 2,
+        };
+        regs.V[3] = 0x11;
+        let mut mem = vec![0u8; 4096];
+        mem[0x302] = 0xff;
+
+        let symbols = Symbols::parse("0x300 score data\n").unwrap();
+        let ctx = Context {
+            regs: &regs,
+            mem: &mem,
+            hitcount: 50,
+        };
+        Expr::parse(text, &symbols).unwrap().eval(&ctx)
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(eval("PC == 0x2A4 && V3 > 0x10"), 1);
+        assert_eq!(eval("PC == 0x2A4 && v3 > 0x11"), 0);
+        assert_eq!(eval("[I+2] == 0xFF"), 1);
+        assert_eq!(eval("[score + 2]"), 0xff);
+        assert_eq!(eval("hitcount >= 50"), 1);
+        assert_eq!(eval("SP == 2 || [0x1234]"), 1);
+        assert_eq!(eval("!(1 + 2 - 3) && 6 & 3 == 2"), 1);
+        assert_eq!(eval("-1 + 1 | 4 ^ 1"), 5);
+    }
+
+    #[test]
+    fn errors() {
+        let symbols = Symbols::new();
+        assert!(Expr::parse("PC ==", &symbols).is_err());
+        assert!(Expr::parse("[I", &symbols).is_err());
+        assert!(Expr::parse("V3 > 1 2", &symbols).is_err());
+        assert!(Expr::parse("VG", &symbols).is_err());
+        assert!(Expr::parse("PC * 2", &symbols).is_err());
+    }
+
+    #[test]
+    fn message() {
+        let regs = Registers {
+            V: [0; 16],
+            I: 0x300,
+            DT: 0,
+            ST: 0,
+            PC: 0x2a4,
+            SP: 0,
+        };
+        let ctx = Context {
+            regs: &regs,
+            mem: &[0; 4096],
+            hitcount: 3,
+        };
+        assert_eq!(
+            format_message("hit {hitcount} at {PC}, {V0+1} {bad", &Symbols::new(), &ctx),
+            "hit 3 at 2a4, 1 {bad"
+        );
+    }
+}
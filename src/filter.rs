@@ -0,0 +1,241 @@
+use super::gpu::{self, Plane};
+
+use std::collections::VecDeque;
+
+// most frames averaged by the blend filter
+const MAX_BLEND: usize = 8;
+
+// display filters against the flicker of sprites that are erased and redrawn
+// with XOR, applied between the gpu framebuffer and the window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Off,
+    // average of the last frames
+    Blend,
+    // pixels fade out instead of switching off
+    Decay,
+    // a pixel set in the current or previous frame is shown
+    MaxOfTwo,
+}
+
+#[derive(Clone)]
+pub struct Filter {
+    kind: Kind,
+    // strength of each kind, kept while switching at runtime
+    blend_frames: usize,
+    // brightness kept per frame
+    decay: f32,
+    // brightness of a pixel only set in the previous frame
+    max_prev: f32,
+    // last pushed frame, the one on screen
+    shown: Option<Plane>,
+    // frames before the shown one, the newest last
+    history: VecDeque<Plane>,
+    // phosphor brightness for Decay before the shown frame
+    levels: Vec<f32>,
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter {
+            kind: Kind::Off,
+            blend_frames: 3,
+            decay: 0.6,
+            max_prev: 1.0,
+            shown: None,
+            history: VecDeque::new(),
+            levels: vec![0.0; gpu::WIDTH * gpu::HEIGHT],
+        }
+    }
+
+    // parse `off`, `blend[:<frames>]`, `decay[:<kept>]` or `max2[:<prev>]`
+    pub fn parse(spec: &str) -> Result<Filter, String> {
+        let mut filter = Filter::new();
+        let (name, strength) = match spec.find(':') {
+            Some(colon) => (&spec[..colon], Some(&spec[colon + 1..])),
+            None => (spec, None),
+        };
+        let invalid = || format!("invalid filter strength in '{}'", spec);
+        match name {
+            "off" => {}
+            "blend" => {
+                filter.kind = Kind::Blend;
+                if let Some(s) = strength {
+                    match s.parse::<usize>() {
+                        Ok(n) if (2..=MAX_BLEND).contains(&n) => filter.blend_frames = n,
+                        _ => return Err(invalid()),
+                    }
+                }
+            }
+            "decay" => {
+                filter.kind = Kind::Decay;
+                if let Some(s) = strength {
+                    match s.parse::<f32>() {
+                        Ok(k) if k > 0.0 && k < 1.0 => filter.decay = k,
+                        _ => return Err(invalid()),
+                    }
+                }
+            }
+            "max2" => {
+                filter.kind = Kind::MaxOfTwo;
+                if let Some(s) = strength {
+                    match s.parse::<f32>() {
+                        Ok(k) if k > 0.0 && k <= 1.0 => filter.max_prev = k,
+                        _ => return Err(invalid()),
+                    }
+                }
+            }
+            _ => return Err(format!("unknown filter '{}'", name)),
+        }
+        Ok(filter)
+    }
+
+    pub fn describe(&self) -> String {
+        match self.kind {
+            Kind::Off => "off".to_string(),
+            Kind::Blend => format!("blend {} frames", self.blend_frames),
+            Kind::Decay => format!("decay {:.2}", self.decay),
+            Kind::MaxOfTwo => format!("max of two frames, previous {:.1}", self.max_prev),
+        }
+    }
+
+    // switch to the next filter kind
+    pub fn next_kind(&mut self) {
+        self.kind = match self.kind {
+            Kind::Off => Kind::Blend,
+            Kind::Blend => Kind::Decay,
+            Kind::Decay => Kind::MaxOfTwo,
+            Kind::MaxOfTwo => Kind::Off,
+        };
+    }
+
+    // increase or decrease the strength of the current kind
+    pub fn adjust(&mut self, stronger: bool) {
+        let step = if stronger { 1.0 } else { -1.0 };
+        match self.kind {
+            Kind::Off => {}
+            Kind::Blend => {
+                let n = self.blend_frames as i32 + step as i32;
+                self.blend_frames = n.clamp(2, MAX_BLEND as i32) as usize;
+            }
+            Kind::Decay => self.decay = (self.decay + 0.05 * step).clamp(0.05, 0.95),
+            Kind::MaxOfTwo => self.max_prev = (self.max_prev + 0.1 * step).clamp(0.1, 1.0),
+        }
+    }
+
+    // record the frame shown from now on, called on every timer tick
+    pub fn push(&mut self, fb: &Plane) {
+        let prev = match self.shown.replace(fb.clone()) {
+            Some(prev) => prev,
+            None => return,
+        };
+        for (level, on) in self.levels.iter_mut().zip(prev.pixels()) {
+            *level = if on { 1.0 } else { *level * self.decay };
+        }
+        if self.history.len() == MAX_BLEND {
+            self.history.pop_front();
+        }
+        self.history.push_back(prev);
+    }
+
+    // brightness 0.0 - 1.0 of every pixel of the current framebuffer `fb`,
+    // either the last pushed frame or a newer one while stepping, compared
+    // with the frames pushed before it
+    pub fn apply(&self, fb: &Plane) -> Vec<f32> {
+        let on = |p: bool| if p { 1.0 } else { 0.0 };
+        match self.kind {
+            Kind::Off => fb.pixels().map(on).collect(),
+            Kind::Blend => {
+                let frames: Vec<&Plane> = self
+                    .history
+                    .iter()
+                    .rev()
+                    .take(self.blend_frames - 1)
+                    .collect();
+                let mut sum: Vec<f32> = fb.pixels().map(on).collect();
+                for f in &frames {
+                    for (s, p) in sum.iter_mut().zip(f.pixels()) {
+                        *s += on(p);
+                    }
+                }
+                let n = (frames.len() + 1) as f32;
+                sum.iter().map(|s| s / n).collect()
+            }
+            Kind::Decay => fb
+                .pixels()
+                .zip(&self.levels)
+                .map(|(p, &level)| if p { 1.0 } else { level * self.decay })
+                .collect(),
+            Kind::MaxOfTwo => match self.history.back() {
+                Some(prev) => fb
+                    .pixels()
+                    .zip(prev.pixels())
+                    .map(|(p, q)| if p { 1.0 } else { on(q) * self.max_prev })
+                    .collect(),
+                None => fb.pixels().map(on).collect(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(set: &[usize]) -> Plane {
+        let mut fb = Plane::new();
+        for &i in set {
+            fb.set(i % gpu::WIDTH, i / gpu::WIDTH, true);
+        }
+        fb
+    }
+
+    #[test]
+    fn filters() {
+        // pixel 0 flickers, pixel 1 stays on, pixel 2 switched off
+        let frames = [frame(&[0, 1, 2]), frame(&[1]), frame(&[0, 1])];
+        let run = |spec: &str, current: &Plane| {
+            let mut filter = Filter::parse(spec).unwrap();
+            for f in &frames {
+                filter.push(f);
+            }
+            // the shown frame is pushed before it is filtered, see
+            // `Session::end_frame`
+            filter.push(current);
+            filter.apply(current)[..4].to_vec()
+        };
+        let current = frame(&[1]);
+
+        assert_eq!(run("off", &current), vec![0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(run("blend:4", &current), vec![0.5, 1.0, 0.25, 0.0]);
+        assert_eq!(run("blend:2", &current), vec![0.5, 1.0, 0.0, 0.0]);
+        assert_eq!(run("max2:0.5", &current), vec![0.5, 1.0, 0.0, 0.0]);
+        let decay = run("decay:0.5", &current);
+        assert_eq!(decay[..2], [0.5, 1.0]);
+        assert_eq!(decay[2], 0.5 * 0.5 * 0.5);
+
+        assert!(Filter::parse("blend:1").is_err());
+        assert!(Filter::parse("decay:1.5").is_err());
+        assert!(Filter::parse("crt").is_err());
+    }
+
+    #[test]
+    fn runtime_switch() {
+        let mut filter = Filter::new();
+        filter.adjust(true);
+        assert_eq!(filter.describe(), "off");
+        filter.next_kind();
+        filter.adjust(true);
+        assert_eq!(filter.describe(), "blend 4 frames");
+        for _ in 0..10 {
+            filter.adjust(true);
+        }
+        assert_eq!(filter.describe(), "blend 8 frames");
+        filter.next_kind();
+        filter.adjust(false);
+        assert_eq!(filter.describe(), "decay 0.55");
+        filter.next_kind();
+        filter.next_kind();
+        assert_eq!(filter.describe(), "off");
+    }
+}
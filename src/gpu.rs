@@ -1,5 +1,12 @@
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
+// word holding a framebuffer row, column 0 is the most significant bit, see
+// `Plane`. Wider modes pick a wider word, e.g. u128 for 128 columns.
+pub type Row = u64;
+const ROW_BITS: usize = Row::BITS as usize;
+const _: () = assert!(WIDTH <= ROW_BITS);
+// bits of the WIDTH columns of a row
+const ROW_MASK: Row = !0 << (ROW_BITS - WIDTH);
 
 #[derive(PartialEq, Debug)]
 pub enum Collision {
@@ -7,96 +14,284 @@ pub enum Collision {
     NoCollision,
 }
 
+// a sprite line is drawn MSB first, `col` 0 is the leftmost pixel
+pub fn sprite_pixel(line: u8, col: usize) -> bool {
+    (line & (0x80 >> col)) != 0
+}
+
+// when the frame shown by frontends is latched from the framebuffer the game
+// draws into, games erase and redraw their sprites so the framebuffer itself
+// is often half drawn
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Present {
+    // no latch, frontends show the framebuffer as it is
+    Immediate,
+    // latch at every 60Hz vblank
+    #[default]
+    Vblank,
+    // latch at the vblank only if a DRW happened in that frame
+    Draw,
+    // latch the finished picture before a CLS, and at the vblank of frames
+    // with a DRW but without CLS
+    Clear,
+}
+
+impl Present {
+    pub const NAMES: [&'static str; 4] = ["immediate", "vblank", "draw", "clear"];
+
+    pub fn parse(name: &str) -> Result<Present, String> {
+        match name {
+            "immediate" => Ok(Present::Immediate),
+            "vblank" => Ok(Present::Vblank),
+            "draw" => Ok(Present::Draw),
+            "clear" => Ok(Present::Clear),
+            _ => Err(format!(
+                "Unknown present mode '{}', available: {}",
+                name,
+                Present::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+// framebuffer and latch state for save states, the clipping comes from the
+// cpu quirks
+#[derive(Clone, PartialEq, Debug)]
+pub struct GpuState {
+    pub fb: Plane,
+    pub front: Plane,
+    pub present: Present,
+    pub drawn: bool,
+    pub cleared: bool,
+}
+
+#[derive(Clone)]
 pub struct Gpu {
-    fb: PixelBuffer<bool>,
+    fb: Plane,
+    // last latched frame
+    front: Plane,
+    present: Present,
+    // DRW since the last latch
+    drawn: bool,
+    // CLS since the last vblank
+    cleared: bool,
+    // sprite pixels beyond the right/bottom edge are clipped instead of
+    // wrapping around to the opposite edge
+    clip_x: bool,
+    clip_y: bool,
+}
+
+impl Default for Gpu {
+    fn default() -> Gpu {
+        Gpu::new()
+    }
 }
 
 impl Gpu {
     pub fn new() -> Gpu {
         Gpu {
-            fb: PixelBuffer::new(),
+            fb: Plane::new(),
+            front: Plane::new(),
+            present: Present::default(),
+            drawn: false,
+            cleared: false,
+            clip_x: false,
+            clip_y: false,
         }
     }
 
+    pub fn set_clipping(&mut self, clip_x: bool, clip_y: bool) {
+        self.clip_x = clip_x;
+        self.clip_y = clip_y;
+    }
+
+    // framebuffer index of the sprite pixel (`line`, `col`) drawn at (`x`,
+    // `y`), None if it is clipped. Uses the same addressing as
+    // `write_sprite`: the start position wraps around the screen, the pixels
+    // beyond an edge wrap or are clipped per axis.
+    pub fn sprite_pixel_index(&self, x: usize, y: usize, line: usize, col: usize) -> Option<usize> {
+        let (x, y) = (x % WIDTH + col, y % HEIGHT + line);
+        if (self.clip_x && x >= WIDTH) || (self.clip_y && y >= HEIGHT) {
+            return None;
+        }
+        Some((y % HEIGHT) * WIDTH + x % WIDTH)
+    }
+
+    pub fn get_state(&self) -> GpuState {
+        GpuState {
+            fb: self.fb.clone(),
+            front: self.front.clone(),
+            present: self.present,
+            drawn: self.drawn,
+            cleared: self.cleared,
+        }
+    }
+
+    pub fn set_state(&mut self, state: &GpuState) {
+        self.fb = state.fb.clone();
+        self.front = state.front.clone();
+        self.present = state.present;
+        self.drawn = state.drawn;
+        self.cleared = state.cleared;
+    }
+
+    pub fn set_present(&mut self, present: Present) {
+        self.present = present;
+        self.latch();
+    }
+
+    // copy the framebuffer to the frame shown by frontends
+    pub fn latch(&mut self) {
+        self.front = self.fb.clone();
+        self.drawn = false;
+    }
+
+    // 60Hz vertical blank, latch depending on the present mode
+    pub fn vblank(&mut self) {
+        match self.present {
+            Present::Immediate => {}
+            Present::Vblank => self.latch(),
+            Present::Draw if self.drawn => self.latch(),
+            Present::Clear if self.drawn && !self.cleared => self.latch(),
+            Present::Draw | Present::Clear => {}
+        }
+        self.cleared = false;
+    }
+
     pub fn write_sprite(&mut self, x: usize, y: usize, sprite_lines: &[u8]) -> Collision {
+        self.drawn = true;
         let mut collision = Collision::NoCollision;
-        for (line, sprite) in sprite_lines.iter().enumerate() {
-            let mut fb_pixels = [false; 8];
-            self.fb.read_pos(x, y + line, &mut fb_pixels);
-            for col in 0..8 {
-                let sprite_pixel = (sprite & (0x80 >> col)) != 0;
-                fb_pixels[col] ^= sprite_pixel;
-                // collision if any pixel transition 1 -> 0
-                // collision = (have & new) > 0
-                if sprite_pixel == true && fb_pixels[col] == false {
-                    collision = Collision::Collision;
-                }
+        let (x, y) = (x % WIDTH, y % HEIGHT);
+        for (line, &sprite) in sprite_lines.iter().enumerate() {
+            if self.clip_y && y + line >= HEIGHT {
+                break;
+            }
+            // the sprite line at column 0, moved to column x, the part
+            // beyond the right edge wraps around to column 0 or is clipped
+            let bits = (sprite as Row) << (ROW_BITS - 8);
+            let mut moved = bits >> x;
+            if !self.clip_x && x + 8 > WIDTH {
+                moved |= bits << (WIDTH - x);
+            }
+            if self.fb.xor_row(y + line, moved & ROW_MASK) {
+                collision = Collision::Collision;
             }
-            self.fb.write_pos(x, y + line, &fb_pixels);
         }
         collision
     }
 
     pub fn clear(&mut self) {
-        self.fb.write(&[false; WIDTH * HEIGHT]);
+        if self.present == Present::Clear && self.drawn {
+            self.latch();
+        }
+        self.cleared = true;
+        self.fb = Plane::new();
+    }
+
+    pub fn toggle_pixel(&mut self, idx: usize) {
+        self.fb.toggle(idx % WIDTH, idx / WIDTH);
     }
-}
 
-impl AsRef<[bool]> for Gpu {
-    fn as_ref(&self) -> &[bool] {
-        self.fb.as_ref()
+    pub fn get_fb(&self) -> &Plane {
+        &self.fb
+    }
+
+    // complete frame for frontends
+    pub fn get_frame(&self) -> &Plane {
+        match self.present {
+            Present::Immediate => &self.fb,
+            _ => &self.front,
+        }
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.fb.get(x, y)
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        self.fb.set(x, y, on);
     }
 }
 
-struct PixelBuffer<T: Default + Copy> {
-    buf: [T; WIDTH * HEIGHT],
+// one bit per pixel, a `Row` per row with column 0 in the most significant bit
+// like the pixels of a sprite line
+#[derive(Clone, PartialEq, Debug)]
+pub struct Plane {
+    rows: [Row; HEIGHT],
 }
 
-impl<T: Default + Copy> PixelBuffer<T> {
-    fn new() -> PixelBuffer<T> {
-        PixelBuffer {
-            buf: [T::default(); WIDTH * HEIGHT],
-        }
+impl Default for Plane {
+    fn default() -> Plane {
+        Plane::new()
     }
+}
 
-    fn write(&mut self, pixels: &[T]) {
-        self.write_pos(0, 0, pixels);
+impl Plane {
+    pub fn new() -> Plane {
+        Plane { rows: [0; HEIGHT] }
     }
 
-    fn write_pos(&mut self, x: usize, y: usize, pixels: &[T]) {
-        assert!(x < WIDTH && y < HEIGHT);
+    pub fn from_rows(rows: [Row; HEIGHT]) -> Plane {
+        Plane { rows }
+    }
 
-        let mut index = y * WIDTH + x;
-        for &pixel in pixels {
-            if index == WIDTH * HEIGHT {
-                index = 0;
-            }
-            self.buf[index] = pixel;
-            index += 1;
-        }
+    pub fn get_rows(&self) -> &[Row] {
+        &self.rows
     }
 
-    fn read(&mut self, buf: &mut [T]) {
-        self.read_pos(0, 0, buf);
+    // bit of column `x` in a row, coordinates wrap around the screen
+    fn bit(x: usize) -> Row {
+        1 << (ROW_BITS - 1 - x % WIDTH)
     }
 
-    fn read_pos(&mut self, x: usize, y: usize, buf: &mut [T]) {
-        assert!(x < WIDTH && y < HEIGHT);
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.rows[y % HEIGHT] & Plane::bit(x) != 0
+    }
 
-        let mut index = y * WIDTH + x;
-        for val in buf {
-            if index == WIDTH * HEIGHT {
-                index = 0;
-            }
-            *val = self.buf[index];
-            index += 1;
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        if on {
+            self.rows[y % HEIGHT] |= Plane::bit(x);
+        } else {
+            self.rows[y % HEIGHT] &= !Plane::bit(x);
         }
     }
-}
 
-impl<T: Default + Copy> AsRef<[T]> for PixelBuffer<T> {
-    fn as_ref(&self) -> &[T] {
-        &self.buf
+    pub fn toggle(&mut self, x: usize, y: usize) {
+        self.rows[y % HEIGHT] ^= Plane::bit(x);
+    }
+
+    // xor `bits` into the row `y`, true if a set pixel is cleared
+    fn xor_row(&mut self, y: usize, bits: Row) -> bool {
+        let row = &mut self.rows[y % HEIGHT];
+        let collision = *row & bits != 0;
+        *row ^= bits;
+        collision
+    }
+
+    // row major pixels, WIDTH * HEIGHT
+    pub fn pixels(&self) -> impl Iterator<Item = bool> + '_ {
+        self.rows
+            .iter()
+            .flat_map(|&row| (0..WIDTH).map(move |x| row & Plane::bit(x) != 0))
+    }
+
+    // (x, y) of the pixels which differ from `other`, row major
+    pub fn diff<'a>(&'a self, other: &'a Plane) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.rows
+            .iter()
+            .zip(other.rows.iter())
+            .enumerate()
+            .flat_map(|(y, (a, b))| {
+                let mut changed = a ^ b;
+                std::iter::from_fn(move || {
+                    if changed == 0 {
+                        return None;
+                    }
+                    let x = changed.leading_zeros() as usize;
+                    changed &= !Plane::bit(x);
+                    Some((x, y))
+                })
+            })
     }
 }
 
@@ -105,47 +300,44 @@ mod test {
     use super::*;
 
     #[test]
-    fn pb_write() {
-        let pixels: &[u32] = &[1, 2, 3, 4];
-        let mut pb = PixelBuffer::<u32>::new();
-
-        pb.write(pixels);
-        assert_eq!(pb.buf[0..4], [1, 2, 3, 4]);
-
-        pb.write_pos(0, 1, pixels);
-        assert_eq!(pb.buf[WIDTH..WIDTH + 4], [1, 2, 3, 4]);
+    fn plane_pixels() {
+        let mut plane = Plane::new();
+        plane.set(0, 0, true);
+        plane.set(WIDTH - 1, 1, true);
+        plane.set(6, 4, true);
+        assert_eq!(plane.get_rows()[0], 1 << (ROW_BITS - 1));
+        assert_eq!(plane.get_rows()[1], 1 << (ROW_BITS - WIDTH));
+        assert!(plane.get(6, 4) && !plane.get(7, 4));
+        // coordinates wrap around the screen
+        assert!(plane.get(WIDTH + 6, HEIGHT + 4));
 
-        pb.write_pos(6, 4, pixels);
-        assert_eq!(pb.buf[4 * WIDTH + 6..4 * WIDTH + 6 + 4], [1, 2, 3, 4]);
-    }
-
-    #[test]
-    fn pb_write_wrapping() {
-        let pixels: &[u32] = &[4, 3, 2, 1];
-        let mut pb = PixelBuffer::new();
+        assert_eq!(plane.pixels().count(), WIDTH * HEIGHT);
+        let set: Vec<usize> = plane
+            .pixels()
+            .enumerate()
+            .filter(|&(_, on)| on)
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(set, [0, 2 * WIDTH - 1, 4 * WIDTH + 6]);
+        assert_eq!(
+            plane.diff(&Plane::new()).collect::<Vec<_>>(),
+            [(0, 0), (WIDTH - 1, 1), (6, 4)]
+        );
 
-        pb.write_pos(WIDTH - 1, HEIGHT - 1, pixels);
-        assert_eq!(pb.buf[WIDTH * HEIGHT - 1], 4);
-        assert_eq!(pb.buf[0..3], [3, 2, 1]);
+        plane.toggle(6, 4);
+        plane.set(0, 0, false);
+        assert_eq!(plane.get_rows().iter().filter(|&&row| row != 0).count(), 1);
     }
 
     #[test]
-    fn pb_read() {
-        let pixels: &[u32] = &[1, 2, 3, 4];
-        let mut pb = PixelBuffer::new();
-        pb.write(pixels);
-        pb.write_pos(7, 7, pixels);
-
-        {
-            let mut buf = [0u32; 4];
-            pb.read(&mut buf);
-            assert_eq!(buf, [1, 2, 3, 4]);
-        }
-        {
-            let mut buf = [0u32; 4];
-            pb.read_pos(7, 7, &mut buf);
-            assert_eq!(buf, [1, 2, 3, 4]);
-        }
+    fn plane_xor_row() {
+        let mut plane = Plane::new();
+        assert!(!plane.xor_row(3, 0xf0 << (ROW_BITS - 8)));
+        assert!(!plane.xor_row(3, 0x0f << (ROW_BITS - 8)));
+        assert_eq!(plane.get_rows()[3], 0xff << (ROW_BITS - 8));
+        // clearing any set pixel collides
+        assert!(plane.xor_row(HEIGHT + 3, 0x01 << (ROW_BITS - 8)));
+        assert_eq!(plane.get_rows()[3], 0xfe << (ROW_BITS - 8));
     }
 
     #[test]
@@ -154,11 +346,11 @@ mod test {
         let sprite: &[u8] = &[0b11110000, 0b00001111];
         // helper
         let fb_to_byte = |gpu: &Gpu, start| {
-            gpu.as_ref()
-                .iter()
+            gpu.get_fb()
+                .pixels()
                 .skip(start)
                 .take(8)
-                .fold(0u8, |s, &v| (s << 1) | v as u8)
+                .fold(0u8, |s, v| (s << 1) | v as u8)
         };
 
         // write some sprite
@@ -171,4 +363,172 @@ mod test {
         assert_eq!(fb_to_byte(&gpu, 0), 0b00000000);
         assert_eq!(fb_to_byte(&gpu, WIDTH), 0b00000000);
     }
+
+    #[test]
+    fn gpu_sprite_pixel_index() {
+        let mut gpu = Gpu::new();
+        let sprite: &[u8] = &[0b10000001, 0b01000010];
+        gpu.write_sprite(60, 3, sprite);
+
+        let mut expected = Vec::new();
+        for (line, &byte) in sprite.iter().enumerate() {
+            for col in (0..8).filter(|&col| sprite_pixel(byte, col)) {
+                expected.extend(gpu.sprite_pixel_index(60, 3, line, col));
+            }
+        }
+        expected.sort();
+        let set: Vec<usize> = (0..WIDTH * HEIGHT)
+            .filter(|&i| gpu.get_pixel(i % WIDTH, i / WIDTH))
+            .collect();
+        assert_eq!(set, expected);
+    }
+
+    // set pixels as (x, y)
+    fn lit(gpu: &Gpu) -> Vec<(usize, usize)> {
+        gpu.get_fb().diff(&Plane::new()).collect()
+    }
+
+    #[test]
+    fn gpu_wrap_edges() {
+        // 2x2 square
+        let square: &[u8] = &[0b11000000, 0b11000000];
+        // last column and row
+        let (r, b) = (WIDTH - 1, HEIGHT - 1);
+        let draw = |x, y| {
+            let mut gpu = Gpu::new();
+            gpu.write_sprite(x, y, square);
+            lit(&gpu)
+        };
+
+        // right edge, back to column 0 of the same rows
+        assert_eq!(draw(WIDTH - 1, 4), [(0, 4), (r, 4), (0, 5), (r, 5)]);
+        // bottom edge
+        assert_eq!(draw(4, HEIGHT - 1), [(4, 0), (5, 0), (4, b), (5, b)]);
+        // left and top edges, start positions beyond the screen wrap
+        assert_eq!(draw(WIDTH + 2, 4), draw(2, 4));
+        assert_eq!(draw(4, HEIGHT + 2), draw(4, 2));
+        assert_eq!(draw(255, 255), draw(255 % WIDTH, 255 % HEIGHT));
+        // bottom right corner, one pixel in every corner
+        assert_eq!(
+            draw(WIDTH - 1, HEIGHT - 1),
+            [(0, 0), (r, 0), (0, b), (r, b)]
+        );
+        // top left corner
+        assert_eq!(draw(WIDTH, HEIGHT), [(0, 0), (1, 0), (0, 1), (1, 1)]);
+
+        // a tall sprite wraps its lines
+        let mut gpu = Gpu::new();
+        gpu.write_sprite(0, 30, &[0x80; 15]);
+        assert_eq!(lit(&gpu).len(), 15);
+        assert!(gpu.get_pixel(0, 12) && !gpu.get_pixel(0, 13));
+    }
+
+    #[test]
+    fn gpu_clip_edges() {
+        let square: &[u8] = &[0b11000000, 0b11000000];
+        // last column and row
+        let (r, b) = (WIDTH - 1, HEIGHT - 1);
+        let draw = |x, y, clip_x, clip_y| {
+            let mut gpu = Gpu::new();
+            gpu.set_clipping(clip_x, clip_y);
+            gpu.write_sprite(x, y, square);
+            lit(&gpu)
+        };
+
+        // right and bottom edges
+        assert_eq!(draw(WIDTH - 1, 4, true, true), [(r, 4), (r, 5)]);
+        assert_eq!(draw(4, HEIGHT - 1, true, true), [(4, b), (5, b)]);
+        // the start position still wraps, left and top edges
+        assert_eq!(
+            draw(WIDTH + 2, 4, true, true),
+            [(2, 4), (3, 4), (2, 5), (3, 5)]
+        );
+        assert_eq!(
+            draw(4, HEIGHT, true, true),
+            [(4, 0), (5, 0), (4, 1), (5, 1)]
+        );
+        // corners, clipped on both axes or only on one
+        assert_eq!(draw(WIDTH - 1, HEIGHT - 1, true, true), [(r, b)]);
+        assert_eq!(draw(WIDTH - 1, HEIGHT - 1, true, false), [(r, 0), (r, b)]);
+        assert_eq!(draw(WIDTH - 1, HEIGHT - 1, false, true), [(0, b), (r, b)]);
+        assert_eq!(
+            draw(WIDTH, HEIGHT, true, true),
+            [(0, 0), (1, 0), (0, 1), (1, 1)]
+        );
+
+        // clipped pixels collide with nothing
+        let mut gpu = Gpu::new();
+        gpu.set_clipping(true, true);
+        gpu.set_pixel(0, 4, true);
+        assert_eq!(
+            gpu.write_sprite(WIDTH - 1, 4, square),
+            Collision::NoCollision
+        );
+        assert_eq!(gpu.write_sprite(WIDTH - 1, 4, square), Collision::Collision);
+        assert_eq!(gpu.sprite_pixel_index(WIDTH - 1, 0, 0, 1), None);
+        assert_eq!(gpu.sprite_pixel_index(WIDTH - 1, 0, 0, 0), Some(WIDTH - 1));
+    }
+
+    #[test]
+    fn gpu_pixels() {
+        let mut gpu = Gpu::new();
+        gpu.set_pixel(3, 1, true);
+        assert!(gpu.get_pixel(3, 1));
+        assert!(gpu.get_fb().get(3, 1));
+        // coordinates wrap like sprites do
+        assert!(gpu.get_pixel(WIDTH + 3, HEIGHT + 1));
+        gpu.set_pixel(3, 1, false);
+        assert!(gpu.get_fb().pixels().all(|p| !p));
+    }
+
+    #[test]
+    fn gpu_present() {
+        let dot: &[u8] = &[0b10000000];
+        let lit = |gpu: &Gpu| gpu.get_frame().pixels().filter(|&p| p).count();
+
+        let mut gpu = Gpu::new();
+        gpu.write_sprite(0, 0, dot);
+        assert_eq!(lit(&gpu), 0);
+        gpu.vblank();
+        assert_eq!(lit(&gpu), 1);
+        // a half drawn frame is not shown before the vblank
+        gpu.clear();
+        assert_eq!(lit(&gpu), 1);
+        gpu.vblank();
+        assert_eq!(lit(&gpu), 0);
+
+        gpu.set_present(Present::Immediate);
+        gpu.write_sprite(0, 0, dot);
+        assert_eq!(lit(&gpu), 1);
+
+        // keep the last picture through frames without DRW
+        gpu.set_present(Present::Draw);
+        gpu.clear();
+        gpu.vblank();
+        assert_eq!(lit(&gpu), 1);
+        gpu.write_sprite(1, 0, dot);
+        gpu.vblank();
+        assert_eq!(lit(&gpu), 1);
+        assert!(gpu.get_frame().get(1, 0));
+
+        // latch before the CLS, the redraw after it is shown at the next CLS
+        gpu.set_present(Present::Clear);
+        gpu.write_sprite(2, 0, dot);
+        gpu.clear();
+        assert_eq!(lit(&gpu), 2);
+        gpu.write_sprite(3, 0, dot);
+        gpu.vblank();
+        assert_eq!(lit(&gpu), 2);
+        gpu.clear();
+        assert_eq!(lit(&gpu), 1);
+        assert!(gpu.get_frame().get(3, 0));
+        // without CLS in a frame, latch at its vblank
+        gpu.vblank();
+        gpu.write_sprite(4, 0, dot);
+        gpu.vblank();
+        assert!(gpu.get_frame().get(4, 0));
+
+        assert_eq!(Present::parse("clear"), Ok(Present::Clear));
+        assert!(Present::parse("never").is_err());
+    }
 }
@@ -0,0 +1,156 @@
+use super::cpu::{AccessKind, Checkpoint, Cpu};
+use super::gpu::{self, Plane};
+
+use std::collections::VecDeque;
+
+// number of executed instructions that can be undone
+pub const CAPACITY: usize = 100_000;
+
+// undo information of one executed instruction
+pub struct Entry {
+    // state before execution
+    pub state: Checkpoint,
+    // (addr, old, new) of the written bytes
+    pub writes: Vec<(u16, u8, u8)>,
+    // framebuffer pixels changed by CLS or DRW
+    pixels: Vec<u16>,
+}
+
+// state captured before executing an instruction
+pub struct Pending {
+    state: Checkpoint,
+    // framebuffer copy, only taken for CLS and DRW
+    fb: Option<Plane>,
+}
+
+// journal of the last executed instructions to step backwards
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    // capture the state before the next instruction executes
+    pub fn begin(cpu: &Cpu) -> Pending {
+        let opcode = cpu.get_next_n_instr(1)[0];
+        let draws = opcode == 0x00e0 || opcode & 0xf000 == 0xd000;
+        Pending {
+            state: cpu.checkpoint(),
+            fb: if draws {
+                Some(cpu.get_fb().clone())
+            } else {
+                None
+            },
+        }
+    }
+
+    // finish the entry after the instruction executed
+    pub fn push(&mut self, pending: Pending, cpu: &Cpu) {
+        let writes = cpu
+            .get_accesses()
+            .iter()
+            .filter(|a| a.kind == AccessKind::Write)
+            .map(|a| (a.addr, a.old, a.value))
+            .collect();
+        let mut pixels = Vec::new();
+        if let Some(fb) = pending.fb {
+            for (x, y) in fb.diff(cpu.get_fb()) {
+                pixels.push((y * gpu::WIDTH + x) as u16);
+            }
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            state: pending.state,
+            writes,
+            pixels,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // undo the last executed instruction, returns its entry
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> Option<Entry> {
+        let entry = self.entries.pop_back()?;
+        for &(addr, old, _) in entry.writes.iter().rev() {
+            cpu.write_mem(addr, old);
+        }
+        for &idx in &entry.pixels {
+            cpu.toggle_pixel(idx as usize);
+        }
+        cpu.rollback(&entry.state);
+        Some(entry)
+    }
+
+    // most recent recorded write to `addr`
+    pub fn last_write(&self, addr: u16) -> Option<(&Entry, u8, u8)> {
+        self.entries.iter().rev().find_map(|e| {
+            e.writes
+                .iter()
+                .rev()
+                .find(|w| w.0 == addr)
+                .map(|&(_, old, new)| (e, old, new))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpu::Gpu;
+    use crate::memory::Memory;
+
+    #[test]
+    fn step_back() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        cpu.load_rom(&[
+            0x60, 0x05, // 0200: LD V0, 05
+            0xa3, 0x00, // 0202: LD I, 0300
+            0xf0, 0x55, // 0204: LD [I], V0
+            0x22, 0x0a, // 0206: CALL 020a
+            0x00, 0x00, // 0208:
+            0xd0, 0x05, // 020a: DRW V0, V0, 5
+            0x00, 0xe0, // 020c: CLS
+            0x00, 0xee, // 020e: RET
+        ]);
+        let mut history = History::new(5);
+
+        let mut states = Vec::new();
+        for _ in 0..7 {
+            states.push((
+                cpu.get_registers(),
+                cpu.get_mem().to_vec(),
+                cpu.get_fb().clone(),
+            ));
+            let pending = History::begin(&cpu);
+            cpu.execute(vec![]);
+            history.push(pending, &cpu);
+        }
+        assert_eq!(history.len(), 5);
+
+        let (entry, old, new) = history.last_write(0x300).unwrap();
+        assert_eq!((entry.state.regs.PC, old, new), (0x204, 0x00, 0x05));
+        assert!(history.last_write(0x301).is_none());
+
+        // undo RET, CLS, DRW, CALL and LD [I], V0
+        for i in (2..7).rev() {
+            history.step_back(&mut cpu).unwrap();
+            assert_eq!(cpu.get_registers(), states[i].0);
+            assert!(cpu.get_mem() == &states[i].1[..]);
+            assert_eq!(cpu.get_fb(), &states[i].2);
+        }
+        assert!(history.step_back(&mut cpu).is_none());
+        assert_eq!(cpu.get_cycles(), 2);
+    }
+}
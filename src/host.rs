@@ -0,0 +1,522 @@
+use super::capture;
+use super::cpu::{Cpu, INSTR_PER_TICK};
+use super::debugger::Debugger;
+use super::display::Palette;
+use super::filter::Filter;
+use super::gpu::Plane;
+use super::symbols::Symbols;
+use super::{step, Tools};
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunMode {
+    FreeRunning,
+    Stepping,
+}
+
+// requests of a frontend to the emulation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Run,
+    Break,
+    // one instruction in Stepping mode
+    Step,
+    StepBack,
+    StepOver,
+    StepOut,
+    RunTo(u16),
+    RunFrame,
+    RunToDraw,
+    // debugger console command
+    Command(String),
+    Screenshot,
+    ToggleRecording,
+    NextFilter,
+    // stronger or weaker
+    FilterStrength(bool),
+    Quit,
+}
+
+// the emulator and its tools, driven by `run` and shown by the frontends
+pub struct Session {
+    pub cpu: Cpu,
+    pub tools: Tools,
+    pub symbols: Symbols,
+    pub debugger: Debugger,
+    pub filter: Filter,
+    pub mode: RunMode,
+    pub palette: Palette,
+    pub capture_scale: usize,
+    // screenshots and recordings are named after the ROM
+    pub capture_prefix: String,
+}
+
+// shows the emulation, `present` is called once per frame
+pub trait VideoSink {
+    // `changed` is set if the cpu executed instructions or events were
+    // handled since the last call
+    fn present(&mut self, session: &Session, changed: bool) -> Result<(), String>;
+
+    // called after every executed instruction
+    fn step(&mut self, _cpu: &Cpu) {}
+
+    // status and error messages of the emulation
+    fn message(&mut self, msg: &str) {
+        println!("{}", msg);
+    }
+}
+
+// keys and commands, `poll` is called once per frame
+pub trait InputSource {
+    // events since the last poll, frontend views may change the session
+    // directly, e.g. when editing memory
+    fn poll(&mut self, session: &mut Session) -> Vec<Event>;
+
+    // currently pressed chip8 keys
+    fn keys(&mut self) -> Vec<u8>;
+}
+
+pub trait AudioSink {
+    // called at the end of every frame with the state of the sound timer
+    fn frame(&mut self, sound: bool);
+}
+
+// frontends can be put together from separate parts
+impl<V: VideoSink, I, A> VideoSink for (V, I, A) {
+    fn present(&mut self, session: &Session, changed: bool) -> Result<(), String> {
+        self.0.present(session, changed)
+    }
+
+    fn step(&mut self, cpu: &Cpu) {
+        self.0.step(cpu)
+    }
+
+    fn message(&mut self, msg: &str) {
+        self.0.message(msg)
+    }
+}
+
+impl<V, I: InputSource, A> InputSource for (V, I, A) {
+    fn poll(&mut self, session: &mut Session) -> Vec<Event> {
+        self.1.poll(session)
+    }
+
+    fn keys(&mut self) -> Vec<u8> {
+        self.1.keys()
+    }
+}
+
+impl<V, I, A: AudioSink> AudioSink for (V, I, A) {
+    fn frame(&mut self, sound: bool) {
+        self.2.frame(sound)
+    }
+}
+
+// no video output, messages are printed
+pub struct NoVideo;
+
+impl VideoSink for NoVideo {
+    fn present(&mut self, _: &Session, _: bool) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub struct Silence;
+
+impl AudioSink for Silence {
+    fn frame(&mut self, _: bool) {}
+}
+
+// keys replayed from a script with lines `<frame> <keys>`, the hex digits of
+// the keys held from that frame on (`-` for none); starts the run in the
+// first frame and quits after `frames` frames
+pub struct ScriptInput {
+    changes: Vec<(u64, Vec<u8>)>,
+    frames: u64,
+    frame: u64,
+    keys: Vec<u8>,
+}
+
+impl ScriptInput {
+    pub fn new(frames: u64) -> ScriptInput {
+        ScriptInput {
+            changes: Vec::new(),
+            frames,
+            frame: 0,
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn parse(text: &str, frames: u64) -> Result<ScriptInput, String> {
+        let mut input = ScriptInput::new(frames);
+        for (nr, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("invalid input script line {}: '{}'", nr + 1, line);
+            let mut fields = line.split_whitespace();
+            let frame = fields
+                .next()
+                .and_then(|f| f.parse::<u64>().ok())
+                .ok_or_else(invalid)?;
+            let keys = match fields.next() {
+                Some("-") => Vec::new(),
+                Some(keys) => keys
+                    .chars()
+                    .map(|c| c.to_digit(16).map(|k| k as u8))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(invalid)?,
+                None => return Err(invalid()),
+            };
+            input.changes.push((frame, keys));
+        }
+        input.changes.sort_by_key(|&(frame, _)| frame);
+        Ok(input)
+    }
+}
+
+impl InputSource for ScriptInput {
+    fn poll(&mut self, _: &mut Session) -> Vec<Event> {
+        if self.frame == self.frames {
+            return vec![Event::Quit];
+        }
+        let frame = self.frame;
+        if let Some((_, keys)) = self.changes.iter().rev().find(|&&(f, _)| f == frame) {
+            self.keys = keys.clone();
+        }
+        self.frame += 1;
+        if self.frame == 1 {
+            vec![Event::Run]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn keys(&mut self) -> Vec<u8> {
+        self.keys.clone()
+    }
+}
+
+impl Session {
+    // frame to show, the framebuffer itself while stepping so that every
+    // instruction is visible
+    pub fn frame(&self) -> &Plane {
+        match self.mode {
+            RunMode::Stepping => self.cpu.get_fb(),
+            RunMode::FreeRunning => self.cpu.get_frame(),
+        }
+    }
+
+    // 60Hz timer tick, the end of a frame for the display filter and recording
+    fn end_frame<F: VideoSink + AudioSink>(&mut self, frontend: &mut F) {
+        self.cpu.timer_tick();
+        // the frame latched at this vblank is shown until the next one, the
+        // filter compares it with the frames pushed before
+        let frame = self.cpu.get_frame();
+        self.filter.push(frame);
+        let sound = self.cpu.get_registers().ST > 0;
+        if let Some(ref mut r) = self.tools.recorder {
+            if let Err(e) = r.frame(frame, sound) {
+                frontend.message(&format!("[-] failed to record, stop recording: {}", e));
+                self.tools.recorder = None;
+            }
+        }
+        frontend.frame(sound);
+    }
+
+    fn step<F: VideoSink>(&mut self, keys: Vec<u8>, frontend: &mut F) {
+        step(&mut self.cpu, keys, &mut self.tools);
+        frontend.step(&self.cpu);
+    }
+
+    fn set_mode<F: VideoSink>(&mut self, mode: RunMode, reason: Option<String>, frontend: &mut F) {
+        self.mode = mode;
+        match reason {
+            Some(reason) => {
+                frontend.message(&format!("[+] {}, switching RunMode: {:?}", reason, mode))
+            }
+            None => frontend.message(&format!("switching RunMode: {:?}", mode)),
+        }
+    }
+
+    // returns false to quit
+    fn handle<F>(&mut self, event: Event, frontend: &mut F) -> bool
+    where
+        F: VideoSink + InputSource + AudioSink,
+    {
+        let result = match event {
+            Event::Run => {
+                if self.mode != RunMode::FreeRunning {
+                    self.set_mode(RunMode::FreeRunning, None, frontend);
+                }
+                Ok(())
+            }
+            Event::Break => {
+                self.debugger.cancel();
+                self.set_mode(RunMode::Stepping, None, frontend);
+                Ok(())
+            }
+            Event::Step => {
+                if self.mode == RunMode::Stepping {
+                    // like the original frontend every step is a timer tick
+                    let keys = frontend.keys();
+                    self.step(keys, frontend);
+                    self.end_frame(frontend);
+                }
+                Ok(())
+            }
+            Event::StepBack => match self.tools.history.step_back(&mut self.cpu) {
+                Some(_) => Ok(()),
+                None => Err("no more history to step back".to_string()),
+            },
+            Event::StepOver => {
+                self.debugger.step_over(&self.cpu);
+                Ok(())
+            }
+            Event::StepOut => self.debugger.step_out(&self.cpu),
+            Event::RunTo(addr) => {
+                self.debugger.run_to(addr);
+                Ok(())
+            }
+            Event::RunFrame => {
+                self.debugger.run_frame();
+                Ok(())
+            }
+            Event::RunToDraw => {
+                self.debugger.run_to_draw();
+                Ok(())
+            }
+            Event::Command(line) => {
+                self.debugger
+                    .command(&line, &mut self.cpu, &mut self.tools.history, &self.symbols)
+            }
+            Event::Screenshot => {
+                let path = capture::free_path(&self.capture_prefix, "png");
+                capture::screenshot(&path, self.frame(), self.palette, self.capture_scale)
+                    .map(|_| frontend.message(&format!("[+] wrote screenshot: {}", path)))
+            }
+            Event::ToggleRecording => match self.tools.recorder.take() {
+                Some(r) => {
+                    let path = r.get_path().to_string();
+                    r.finish().map(|frames| {
+                        frontend.message(&format!(
+                            "[+] wrote recording: {} ({} frames)",
+                            path, frames
+                        ))
+                    })
+                }
+                None => {
+                    let path = capture::free_path(&self.capture_prefix, "gif");
+                    capture::Recorder::start(&path, self.palette, self.capture_scale).map(|r| {
+                        frontend.message(&format!("[+] recording to {}", path));
+                        self.tools.recorder = Some(r);
+                    })
+                }
+            },
+            Event::NextFilter | Event::FilterStrength(_) => {
+                match event {
+                    Event::NextFilter => self.filter.next_kind(),
+                    _ => self.filter.adjust(event == Event::FilterStrength(true)),
+                }
+                frontend.message(&format!("[+] Display filter: {}", self.filter.describe()));
+                Ok(())
+            }
+            Event::Quit => return false,
+        };
+        if let Err(e) = result {
+            frontend.message(&format!("[-] {}", e));
+        }
+        true
+    }
+
+    // one frame of INSTR_PER_TICK instructions, a breakpoint ends it early
+    fn run_frame<F: VideoSink + InputSource + AudioSink>(&mut self, frontend: &mut F) {
+        let keys = frontend.keys();
+        for _ in 0..INSTR_PER_TICK {
+            self.step(keys.clone(), frontend);
+            let stop = self.debugger.check(&self.cpu, &self.symbols);
+            for msg in self.debugger.take_logs() {
+                frontend.message(&format!("[log] {}", msg));
+            }
+            if let Some(reason) = stop {
+                self.set_mode(RunMode::Stepping, Some(reason), frontend);
+                return;
+            }
+        }
+        self.end_frame(frontend);
+        if let Some(reason) = self.debugger.tick(&self.cpu, &self.symbols) {
+            self.set_mode(RunMode::Stepping, Some(reason), frontend);
+        }
+    }
+}
+
+// the emulation loop shared by all frontends: poll the input, run one frame
+// and present it; `realtime` paces the frames to 60Hz, otherwise they run as
+// fast as possible
+pub fn run<F>(session: &mut Session, frontend: &mut F, realtime: bool) -> Result<(), String>
+where
+    F: VideoSink + InputSource + AudioSink,
+{
+    let frame = Duration::from_micros(1_000_000 / 60);
+    let mut next_frame = Instant::now();
+    let mut changed = true;
+
+    loop {
+        for event in frontend.poll(session) {
+            changed = true;
+            if !session.handle(event, frontend) {
+                return Ok(());
+            }
+        }
+
+        // the step commands run freely until their goal is reached
+        if session.debugger.is_running() && session.mode == RunMode::Stepping {
+            session.mode = RunMode::FreeRunning;
+        }
+        if session.mode == RunMode::FreeRunning {
+            session.run_frame(frontend);
+            changed = true;
+        }
+
+        frontend.present(session, changed)?;
+        changed = false;
+
+        if realtime {
+            next_frame += frame;
+            let now = Instant::now();
+            if next_frame > now {
+                std::thread::sleep(next_frame - now);
+            } else {
+                // too slow, do not try to catch up
+                next_frame = now;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpu::Gpu;
+    use crate::history::History;
+    use crate::memory::Memory;
+    use crate::provenance::Provenance;
+
+    fn new_session(rom: &[u8]) -> Session {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        cpu.load_rom(rom);
+        Session {
+            cpu,
+            tools: Tools {
+                tracer: None,
+                profiler: None,
+                cdl: None,
+                history: History::new(100),
+                provenance: Provenance::new(),
+                recorder: None,
+            },
+            symbols: Symbols::new(),
+            debugger: Debugger::new(),
+            filter: Filter::new(),
+            mode: RunMode::Stepping,
+            palette: Palette::default(),
+            capture_scale: 1,
+            capture_prefix: "test".to_string(),
+        }
+    }
+
+    // counts the presented frames and the frames with sound
+    struct Counter {
+        frames: usize,
+        sound: usize,
+    }
+
+    impl VideoSink for Counter {
+        fn present(&mut self, _: &Session, _: bool) -> Result<(), String> {
+            self.frames += 1;
+            Ok(())
+        }
+    }
+
+    impl AudioSink for Counter {
+        fn frame(&mut self, sound: bool) {
+            self.sound += sound as usize;
+        }
+    }
+
+    #[test]
+    fn headless_script() {
+        // LD V0, K; LD ST, V0; ADD V1, 1; JP 0x204
+        let rom = [0xf0, 0x0a, 0xf0, 0x18, 0x71, 0x01, 0x12, 0x04];
+        let mut session = new_session(&rom);
+        let input = ScriptInput::parse("# frame keys\n5 -\n3 5a\n", 10).unwrap();
+        let mut frontend = (
+            Counter {
+                frames: 0,
+                sound: 0,
+            },
+            input,
+            Silence,
+        );
+        run(&mut session, &mut frontend, false).unwrap();
+
+        assert_eq!(frontend.0.frames, 10);
+        assert_eq!(session.mode, RunMode::FreeRunning);
+        // the key wait ends in frame 3, the sound timer runs 5 ticks
+        assert_eq!(session.cpu.get_registers().V[0], 5);
+        assert_eq!(session.cpu.get_registers().ST, 0);
+
+        let mut frontend = (
+            NoVideo,
+            ScriptInput::new(4),
+            Counter {
+                frames: 0,
+                sound: 0,
+            },
+        );
+        let mut session = new_session(&rom);
+        session.cpu.set_v(0, 3);
+        session.cpu.set_pc(0x202);
+        run(&mut session, &mut frontend, false).unwrap();
+        assert_eq!(frontend.2.sound, 2);
+
+        assert!(ScriptInput::parse("1\n", 5).is_err());
+        assert!(ScriptInput::parse("x 1\n", 5).is_err());
+        assert!(ScriptInput::parse("1 g\n", 5).is_err());
+    }
+
+    #[test]
+    fn breakpoint_stops_frame() {
+        // ADD V1, 1; ADD V1, 1; JP 0x200
+        let mut session = new_session(&[0x71, 0x01, 0x71, 0x01, 0x12, 0x00]);
+        session
+            .debugger
+            .add_break("0x202 if V1 == 3", &session.symbols)
+            .unwrap();
+        let mut frontend = (NoVideo, ScriptInput::new(3), Silence);
+        run(&mut session, &mut frontend, false).unwrap();
+        // the frame ended at the breakpoint, the script does not resume
+        assert_eq!(session.mode, RunMode::Stepping);
+        assert_eq!(session.cpu.get_registers().V[1], 3);
+        assert_eq!(session.cpu.get_registers().PC, 0x202);
+    }
+
+    #[test]
+    fn filter_flicker() {
+        let mut session = new_session(&[]);
+        session.mode = RunMode::FreeRunning;
+        session.filter = Filter::parse("max2:0.5").unwrap();
+        let mut frontend = Counter {
+            frames: 0,
+            sound: 0,
+        };
+        // pixel 0 is drawn in one frame and erased in the next
+        session.cpu.toggle_pixel(0);
+        session.end_frame(&mut frontend);
+        session.cpu.toggle_pixel(0);
+        session.end_frame(&mut frontend);
+        assert_eq!(session.filter.apply(session.frame())[..2], [0.5, 0.0]);
+    }
+}
@@ -0,0 +1,11 @@
+// libretro core, the emulator without any of the frontends and debug tools
+// of the binary. The binary uses the core modules from here.
+
+pub mod cpu;
+pub mod decoder;
+pub mod display;
+pub mod gpu;
+mod libretro;
+pub mod memory;
+pub mod symbols;
+pub mod tone;
@@ -0,0 +1,689 @@
+use super::cpu::{Cpu, CpuState, Quirks, INSTR_PER_TICK, PROGRAM_START, TIMER_HZ};
+use super::display::Palette;
+use super::gpu;
+use super::memory::{self, MEM_SIZE};
+use super::tone::{self, SAMPLE_RATE};
+
+use std::os::raw::{c_char, c_uint, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard};
+
+const RETRO_API_VERSION: c_uint = 1;
+
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_KEYBOARD: c_uint = 3;
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+const RETRO_REGION_NTSC: c_uint = 0;
+
+// a frame per timer tick like the frontends of the binary
+const SAMPLES_PER_FRAME: u64 = SAMPLE_RATE as u64 / TIMER_HZ;
+
+// save states: version, V, I, DT, ST, PC, stack depth and slots, cycles, RND
+// state, quirks, present mode, latch flags, crashed, audio phase, memory,
+// framebuffer and latched frame
+const STATE_VERSION: u8 = 3;
+const STACK_SLOTS: usize = 16;
+const CPU_STATE_SIZE: usize = 16 + 2 + 1 + 1 + 2 + 1 + 2 * STACK_SLOTS + 8;
+const ROW_BYTES: usize = std::mem::size_of::<gpu::Row>();
+const STATE_SIZE: usize = 1 + CPU_STATE_SIZE + 8 + 5 + 8 + MEM_SIZE + 2 * ROW_BYTES * gpu::HEIGHT;
+// index of the present mode in save states
+const PRESENT: [gpu::Present; 4] = [
+    gpu::Present::Immediate,
+    gpu::Present::Vblank,
+    gpu::Present::Draw,
+    gpu::Present::Clear,
+];
+
+// chip8 key of every RETRO_DEVICE_ID_JOYPAD_* button: B Y SELECT START UP
+// DOWN LEFT RIGHT A X L R L2 R2 L3 R3, the d-pad is the usual 2/4/6/8
+const JOYPAD: [u8; 16] = [
+    0x5, 0x7, 0xC, 0xF, 0x2, 0x8, 0x4, 0x6, 0x9, 0x1, 0x3, 0xA, 0xB, 0xD, 0xE, 0x0,
+];
+// RETROK_* codes of the keyboard layout of the binary, indexed by chip8 key
+const KEYBOARD: [u8; 16] = [
+    b'x', b'1', b'2', b'3', b'q', b'w', b'e', b'a', b's', b'd', b'z', b'c', b'4', b'r', b'f', b'v',
+];
+const KEY_NAMES: [&[u8]; 16] = [
+    b"Key 0\0", b"Key 1\0", b"Key 2\0", b"Key 3\0", b"Key 4\0", b"Key 5\0", b"Key 6\0", b"Key 7\0",
+    b"Key 8\0", b"Key 9\0", b"Key A\0", b"Key B\0", b"Key C\0", b"Key D\0", b"Key E\0", b"Key F\0",
+];
+
+#[repr(C)]
+pub struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    geometry: GameGeometry,
+    timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+struct InputDescriptor {
+    port: c_uint,
+    device: c_uint,
+    index: c_uint,
+    id: c_uint,
+    description: *const c_char,
+}
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+// callbacks of the frontend, they are copied out of the lock before calling
+// them so that a frontend may call back into the core
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample: Option<AudioSampleFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Core {
+    cpu: Cpu,
+    rom: Vec<u8>,
+    palette: Palette,
+    // XRGB8888 pixels of the last frame
+    frame: Vec<u32>,
+    // samples since the game was loaded, the phase of the square wave
+    samples: u64,
+    // the cpu panicked, the last frame is shown from then on
+    crashed: bool,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+// a panic while holding a lock is caught in retro_run, the state is still
+// usable
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn callbacks() -> Callbacks {
+    *lock(&CALLBACKS)
+}
+
+// quirks in save states, one bit each
+fn quirk_bits(q: &Quirks) -> u8 {
+    [
+        q.shift_vy,
+        q.load_store_inc_i,
+        q.jump_vx,
+        q.vf_reset,
+        q.clip_x,
+        q.clip_y,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |bits, (i, &on)| bits | (on as u8) << i)
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let bit = |i: u8| bits & (1 << i) != 0;
+    Quirks {
+        shift_vy: bit(0),
+        load_store_inc_i: bit(1),
+        jump_vx: bit(2),
+        vf_reset: bit(3),
+        clip_x: bit(4),
+        clip_y: bit(5),
+    }
+}
+
+impl Core {
+    fn new(rom: &[u8]) -> Core {
+        let mut cpu = Cpu::new(memory::Memory::new(), gpu::Gpu::new());
+        cpu.load_rom(rom);
+        Core {
+            cpu,
+            rom: rom.to_vec(),
+            palette: Palette::default(),
+            frame: vec![0; gpu::WIDTH * gpu::HEIGHT],
+            samples: 0,
+            crashed: false,
+        }
+    }
+
+    fn run_frame(&mut self, keys: Vec<u8>) {
+        if !self.crashed {
+            let cpu = &mut self.cpu;
+            // unwinding into the frontend is undefined behaviour
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                for _ in 0..INSTR_PER_TICK {
+                    cpu.execute(keys.clone());
+                }
+            }));
+            if result.is_err() {
+                eprintln!("[-] cpu crashed, stop executing");
+                self.crashed = true;
+            } else {
+                self.cpu.timer_tick();
+            }
+        }
+        for (p, on) in self.frame.iter_mut().zip(self.cpu.get_frame().pixels()) {
+            *p = if on { self.palette.fg } else { self.palette.bg };
+        }
+    }
+
+    // interleaved stereo samples of one frame
+    fn audio(&mut self) -> Vec<i16> {
+        let sound = !self.crashed && self.cpu.get_registers().ST > 0;
+        let mut out = Vec::with_capacity(2 * SAMPLES_PER_FRAME as usize);
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = tone::sample(self.samples, sound);
+            out.push(sample);
+            out.push(sample);
+            self.samples += 1;
+        }
+        out
+    }
+
+    // saving must not change the machine, RND is saved as generator state
+    fn save_state(&self) -> Result<Vec<u8>, String> {
+        let state = self.cpu.get_state();
+        if state.stack.len() > STACK_SLOTS {
+            return Err(format!("stack deeper than {} entries", STACK_SLOTS));
+        }
+        let gpu = self.cpu.get_gpu().get_state();
+        let present = PRESENT.iter().position(|&p| p == gpu.present).unwrap();
+
+        let mut out = Vec::with_capacity(STATE_SIZE);
+        out.push(STATE_VERSION);
+        out.extend_from_slice(&state.V);
+        out.extend_from_slice(&state.I.to_le_bytes());
+        out.push(state.DT);
+        out.push(state.ST);
+        out.extend_from_slice(&state.PC.to_le_bytes());
+        out.push(state.stack.len() as u8);
+        for slot in 0..STACK_SLOTS {
+            let addr = state.stack.get(slot).copied().unwrap_or(0);
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+        out.extend_from_slice(&state.cycles.to_le_bytes());
+        out.extend_from_slice(&self.cpu.get_rng_state().to_le_bytes());
+        out.push(quirk_bits(&self.cpu.get_quirks()));
+        out.push(present as u8);
+        out.push(gpu.drawn as u8);
+        out.push(gpu.cleared as u8);
+        out.push(self.crashed as u8);
+        out.extend_from_slice(&self.samples.to_le_bytes());
+        out.extend_from_slice(self.cpu.get_mem());
+        for plane in &[&gpu.fb, &gpu.front] {
+            for row in plane.get_rows() {
+                out.extend_from_slice(&row.to_le_bytes());
+            }
+        }
+        assert_eq!(out.len(), STATE_SIZE);
+        Ok(out)
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() < STATE_SIZE {
+            return Err(format!("state of {} bytes is too short", data.len()));
+        }
+        if data[0] != STATE_VERSION {
+            return Err(format!("unknown state version {}", data[0]));
+        }
+        let mut pos = 1;
+        let mut take = |len: usize| {
+            let bytes = &data[pos..pos + len];
+            pos += len;
+            bytes
+        };
+        let u16_at = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]);
+        let u64_at = |b: &[u8]| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(b);
+            u64::from_le_bytes(bytes)
+        };
+
+        let mut state = CpuState {
+            V: [0; 16],
+            I: 0,
+            DT: 0,
+            ST: 0,
+            PC: 0,
+            stack: Vec::new(),
+            cycles: 0,
+        };
+        state.V.copy_from_slice(take(16));
+        state.I = u16_at(take(2));
+        state.DT = take(1)[0];
+        state.ST = take(1)[0];
+        state.PC = u16_at(take(2));
+        let depth = take(1)[0] as usize;
+        if depth > STACK_SLOTS {
+            return Err(format!("stack depth {} out of range", depth));
+        }
+        let slots = take(2 * STACK_SLOTS);
+        state.stack = slots.chunks(2).take(depth).map(u16_at).collect();
+        state.cycles = u64_at(take(8));
+        let rng = u64_at(take(8));
+        let quirks = quirks_from_bits(take(1)[0]);
+        let present = match PRESENT.get(take(1)[0] as usize) {
+            Some(&present) => present,
+            None => return Err("unknown present mode".to_string()),
+        };
+        let (drawn, cleared) = (take(1)[0] != 0, take(1)[0] != 0);
+        let crashed = take(1)[0] != 0;
+        let samples = u64_at(take(8));
+        let mem = take(MEM_SIZE);
+        let mut planes = take(2 * ROW_BYTES * gpu::HEIGHT)
+            .chunks(ROW_BYTES * gpu::HEIGHT)
+            .map(|plane| {
+                let mut rows = [0; gpu::HEIGHT];
+                for (row, bytes) in rows.iter_mut().zip(plane.chunks(ROW_BYTES)) {
+                    let mut le = [0u8; ROW_BYTES];
+                    le.copy_from_slice(bytes);
+                    *row = gpu::Row::from_le_bytes(le);
+                }
+                gpu::Plane::from_rows(rows)
+            });
+        let (fb, front) = (planes.next().unwrap(), planes.next().unwrap());
+
+        self.cpu.set_state(&state);
+        self.cpu.set_quirks(quirks);
+        self.cpu.seed_rng(rng);
+        self.cpu.get_ram_mut().write_slice(0, mem)?;
+        self.cpu.get_gpu_mut().set_state(&gpu::GpuState {
+            fb,
+            front,
+            present,
+            drawn,
+            cleared,
+        });
+        self.samples = samples;
+        self.crashed = crashed;
+        Ok(())
+    }
+}
+
+fn pressed_keys(cb: &Callbacks) -> Vec<u8> {
+    let input_state = match cb.input_state {
+        Some(f) => f,
+        None => return Vec::new(),
+    };
+    let held = |device, id| unsafe { input_state(0, device, 0, id) != 0 };
+    (0..16u8)
+        .filter(|&key| {
+            let button = JOYPAD.iter().position(|&k| k == key).unwrap();
+            held(RETRO_DEVICE_JOYPAD, button as c_uint)
+                || held(RETRO_DEVICE_KEYBOARD, KEYBOARD[key as usize] as c_uint)
+        })
+        .collect()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *lock(&CORE) = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    if info.is_null() {
+        return;
+    }
+    *info = SystemInfo {
+        library_name: b"chip8-remu\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    if info.is_null() {
+        return;
+    }
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: gpu::WIDTH as c_uint,
+            base_height: gpu::HEIGHT as c_uint,
+            max_width: gpu::WIDTH as c_uint,
+            max_height: gpu::HEIGHT as c_uint,
+            aspect_ratio: gpu::WIDTH as f32 / gpu::HEIGHT as f32,
+        },
+        timing: SystemTiming {
+            fps: TIMER_HZ as f64,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(f: EnvironmentFn) {
+    lock(&CALLBACKS).environment = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(f: VideoRefreshFn) {
+    lock(&CALLBACKS).video_refresh = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(f: AudioSampleFn) {
+    lock(&CALLBACKS).audio_sample = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(f: AudioSampleBatchFn) {
+    lock(&CALLBACKS).audio_sample_batch = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(f: InputPollFn) {
+    lock(&CALLBACKS).input_poll = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(f: InputStateFn) {
+    lock(&CALLBACKS).input_state = Some(f);
+}
+
+// every port is a keypad
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    let mut core = lock(&CORE);
+    if let Some(ref mut c) = *core {
+        *c = Core::new(&c.rom);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let cb = callbacks();
+    if let Some(poll) = cb.input_poll {
+        unsafe { poll() };
+    }
+    let keys = pressed_keys(&cb);
+
+    let (frame, audio) = {
+        let mut core = lock(&CORE);
+        let core = match *core {
+            Some(ref mut core) => core,
+            None => return,
+        };
+        core.run_frame(keys);
+        (core.frame.clone(), core.audio())
+    };
+
+    unsafe {
+        if let Some(video) = cb.video_refresh {
+            video(
+                frame.as_ptr() as *const c_void,
+                gpu::WIDTH as c_uint,
+                gpu::HEIGHT as c_uint,
+                gpu::WIDTH * 4,
+            );
+        }
+        if let Some(batch) = cb.audio_sample_batch {
+            let mut rest = &audio[..];
+            while !rest.is_empty() {
+                let written = batch(rest.as_ptr(), rest.len() / 2);
+                if written == 0 {
+                    break;
+                }
+                rest = &rest[std::cmp::min(2 * written, rest.len())..];
+            }
+        } else if let Some(sample) = cb.audio_sample {
+            for s in audio.chunks(2) {
+                sample(s[0], s[1]);
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    STATE_SIZE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if data.is_null() || size < STATE_SIZE {
+        return false;
+    }
+    let core = lock(&CORE);
+    let state = match *core {
+        Some(ref core) => core.save_state(),
+        None => return false,
+    };
+    match state {
+        Ok(state) => {
+            std::ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+            true
+        }
+        Err(e) => {
+            eprintln!("[-] failed to save state: {}", e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let data = std::slice::from_raw_parts(data as *const u8, size);
+    let mut core = lock(&CORE);
+    let result = match *core {
+        Some(ref mut core) => core.load_state(data),
+        None => return false,
+    };
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("[-] failed to load state: {}", e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = std::slice::from_raw_parts((*game).data as *const u8, (*game).size);
+    if rom.len() >= MEM_SIZE - PROGRAM_START as usize {
+        eprintln!("[-] rom of {} bytes does not fit into memory", rom.len());
+        return false;
+    }
+
+    let cb = callbacks();
+    if let Some(env) = cb.environment {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        if !env(
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+            &mut format as *mut c_uint as *mut c_void,
+        ) {
+            eprintln!("[-] frontend does not support XRGB8888");
+            return false;
+        }
+
+        // terminated by an entry without description
+        let mut descriptors: Vec<InputDescriptor> = JOYPAD
+            .iter()
+            .enumerate()
+            .map(|(button, &key)| InputDescriptor {
+                port: 0,
+                device: RETRO_DEVICE_JOYPAD,
+                index: 0,
+                id: button as c_uint,
+                description: KEY_NAMES[key as usize].as_ptr() as *const c_char,
+            })
+            .collect();
+        descriptors.push(InputDescriptor {
+            port: 0,
+            device: 0,
+            index: 0,
+            id: 0,
+            description: std::ptr::null(),
+        });
+        env(
+            RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+            descriptors.as_mut_ptr() as *mut c_void,
+        );
+    }
+
+    *lock(&CORE) = Some(Core::new(rom));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _type: c_uint,
+    _info: *const GameInfo,
+    _num: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *lock(&CORE) = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+// the pointer stays valid until the game is unloaded
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    let mut core = lock(&CORE);
+    match *core {
+        Some(ref mut core) if id == RETRO_MEMORY_SYSTEM_RAM => {
+            core.cpu.get_ram_mut().as_mut().as_mut_ptr() as *mut c_void
+        }
+        _ => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match *lock(&CORE) {
+        Some(ref core) if id == RETRO_MEMORY_SYSTEM_RAM => core.cpu.get_mem().len(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn state_restores_rnd() {
+        // RND V0, FF | RND V1, FF | DRW V0, V1, 5 | JP 0200
+        let rom = [0xc0, 0xff, 0xc1, 0xff, 0xd0, 0x15, 0x12, 0x00];
+        let run = |core: &mut Core| {
+            (0..3)
+                .map(|_| {
+                    core.run_frame(vec![]);
+                    (
+                        core.cpu.get_registers(),
+                        core.cpu.get_fb().clone(),
+                        core.cpu.get_frame().clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut core = Core::new(&rom);
+        core.cpu.set_quirks(Quirks::parse("clip,vf-reset").unwrap());
+        core.run_frame(vec![]);
+        let state = core.save_state().unwrap();
+        let frames = run(&mut core);
+
+        let mut other = Core::new(&rom);
+        other.load_state(&state).unwrap();
+        assert_eq!(other.cpu.get_quirks(), core.cpu.get_quirks());
+        assert_eq!(run(&mut other), frames);
+        core.load_state(&state).unwrap();
+        assert_eq!(run(&mut core), frames);
+
+        // saving every frame, like rewind and runahead, keeps the sequence
+        let mut saving = Core::new(&rom);
+        let mut plain = Core::new(&rom);
+        saving.cpu.seed_rng(7);
+        plain.cpu.seed_rng(7);
+        for _ in 0..3 {
+            saving.save_state().unwrap();
+            saving.run_frame(vec![]);
+            plain.run_frame(vec![]);
+        }
+        assert_eq!(saving.cpu.get_registers(), plain.cpu.get_registers());
+        assert_eq!(saving.cpu.get_fb(), plain.cpu.get_fb());
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// maximum number of lines a source breakpoint is moved down to find code
+const MAX_SHIFT: usize = 16;
+
+// mapping between instruction addresses and assembler source lines, read
+// from a listing map with lines `<addr> <file>:<line>`, e.g.
+//
+//   0x200 game.8o:12
+//   0x202 game.8o:13
+//
+// empty lines and lines starting with `#` are ignored
+#[derive(Clone)]
+pub struct LineMap {
+    by_addr: BTreeMap<u16, (String, usize)>,
+}
+
+impl LineMap {
+    pub fn new() -> LineMap {
+        LineMap {
+            by_addr: BTreeMap::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<LineMap, String> {
+        let text = std::fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Failed to read listing map {}: {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+        let mut map = LineMap::parse(&text)?;
+
+        // editors need absolute source paths, relative ones start at the map
+        let dir = match path.as_ref().parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let dir = dir.canonicalize().unwrap_or(dir);
+        for (file, _) in map.by_addr.values_mut() {
+            if Path::new(file.as_str()).is_relative() {
+                *file = dir.join(file.as_str()).to_string_lossy().into_owned();
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn parse(text: &str) -> Result<LineMap, String> {
+        let mut map = LineMap::new();
+        for (nr, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("invalid listing map line {}: '{}'", nr + 1, line);
+
+            let mut fields = line.split_whitespace();
+            let addr = fields
+                .next()
+                .map(|a| a.trim_start_matches("0x").trim_start_matches("0X"))
+                .and_then(|a| u16::from_str_radix(a, 16).ok())
+                .ok_or_else(invalid)?;
+            let loc = fields.next().ok_or_else(invalid)?;
+            let colon = loc.rfind(':').ok_or_else(invalid)?;
+            let src_line = loc[colon + 1..].parse::<usize>().map_err(|_| invalid())?;
+            map.by_addr
+                .insert(addr, (loc[..colon].to_string(), src_line));
+        }
+        Ok(map)
+    }
+
+    // source file and line of the instruction at `addr`
+    pub fn get_line(&self, addr: u16) -> Option<(&str, usize)> {
+        self.by_addr
+            .get(&addr)
+            .map(|(file, line)| (file.as_str(), *line))
+    }
+
+    // address of the first instruction at or below `line` of `path`, the
+    // map may name files relative to the path the editor uses
+    pub fn get_addr(&self, path: &str, line: usize) -> Option<(u16, usize)> {
+        let path = Path::new(path);
+        (line..line + MAX_SHIFT).find_map(|l| {
+            self.by_addr
+                .iter()
+                .find(|(_, (file, src_line))| *src_line == l && path.ends_with(file))
+                .map(|(&addr, _)| (addr, l))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let map = LineMap::parse(
+            "# game listing\n\
+             0x200 src/game.8o:3\n\
+             202 src/game.8o:5\n\
+             0x300 font.8o:1\n",
+        )
+        .unwrap();
+        assert_eq!(map.get_line(0x202), Some(("src/game.8o", 5)));
+        assert_eq!(map.get_line(0x204), None);
+
+        assert_eq!(map.get_addr("/home/dev/src/game.8o", 3), Some((0x200, 3)));
+        // comment line, moved to the next instruction
+        assert_eq!(map.get_addr("/home/dev/src/game.8o", 4), Some((0x202, 5)));
+        assert_eq!(map.get_addr("/home/dev/game.8o", 3), None);
+        assert_eq!(map.get_addr("font.8o", 1), Some((0x300, 1)));
+
+        assert!(LineMap::parse("0x200 game.8o\n").is_err());
+        assert!(LineMap::parse("zz game.8o:1\n").is_err());
+    }
+}
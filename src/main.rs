@@ -1,41 +1,36 @@
 extern crate minifb;
-use minifb::{Key, Window, WindowOptions};
-
-use pixel_engine::PixelBuffer;
 
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
-use std::time::{Duration, Instant};
-
-mod cpu;
-mod decoder;
-mod gpu;
-mod memory;
-
-fn remap_keys(keys: Vec<Key>) -> Vec<u8> {
-    keys.iter()
-        .filter_map(|key| match key {
-            Key::X => Some(0x0),
-            Key::Key1 => Some(0x1),
-            Key::Key2 => Some(0x2),
-            Key::Key3 => Some(0x3),
-            Key::Q => Some(0x4),
-            Key::W => Some(0x5),
-            Key::E => Some(0x6),
-            Key::A => Some(0x7),
-            Key::S => Some(0x8),
-            Key::D => Some(0x9),
-            Key::Z => Some(0xA),
-            Key::C => Some(0xB),
-            Key::Key4 => Some(0xC),
-            Key::R => Some(0xD),
-            Key::F => Some(0xE),
-            Key::V => Some(0xF),
-            _ => None,
-        })
-        .collect::<Vec<u8>>()
-}
+
+// the core is the library crate, shared with the libretro core
+use chip8_remu::{cpu, decoder, display, gpu, memory, symbols, tone};
+
+mod args;
+mod callstack;
+mod capture;
+mod cdl;
+mod dap;
+mod debugger;
+mod diff;
+mod expr;
+mod filter;
+mod history;
+mod host;
+mod json;
+mod linemap;
+mod mem_view;
+mod postfx;
+mod profile;
+mod provenance;
+mod reg_view;
+mod sprite_view;
+mod stack_view;
+mod trace;
+mod tui;
+mod ui;
+mod window;
 
 fn load_rom_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, String> {
     println!("[+] using ROM file: {}", path.as_ref().to_str().unwrap());
@@ -54,147 +49,233 @@ fn load_rom_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, String> {
     }
 }
 
-fn load_rom_from_disk() -> Result<Vec<u8>, String> {
-    match std::env::args().nth(1) {
-        Some(p) => load_rom_file(p),
-        None => Err(format!("Use as {} <rom>", std::env::args().nth(0).unwrap())),
+fn create_tracer(
+    args: &args::Args,
+    symbols: &symbols::Symbols,
+) -> Result<Option<trace::Tracer>, String> {
+    match args.trace {
+        Some(ref path) => {
+            let format = if args.trace_binary {
+                trace::Format::Binary
+            } else {
+                trace::Format::Text(
+                    args.trace_format
+                        .clone()
+                        .unwrap_or_else(|| trace::DEFAULT_FORMAT.to_string()),
+                )
+            };
+            println!("[+] tracing to: {}", path);
+            trace::Tracer::create(path, format, symbols.clone()).map(Some)
+        }
+        None => Ok(None),
     }
 }
 
-fn main() {
-    let rom_data = match load_rom_from_disk() {
-        Ok(rom) => rom,
+// optional instrumentation fed with every executed instruction
+struct Tools {
+    tracer: Option<trace::Tracer>,
+    profiler: Option<profile::Profiler>,
+    // code/data log and the sidecar file it is saved to
+    cdl: Option<(cdl::CodeDataLog, String)>,
+    history: history::History,
+    provenance: provenance::Provenance,
+    recorder: Option<capture::Recorder>,
+}
+
+impl Tools {
+    fn finish(self, args: &args::Args, cpu: &cpu::Cpu) -> Result<(), String> {
+        if let Some(mut t) = self.tracer {
+            t.flush()
+                .map_err(|e| format!("Failed to flush trace: {}", e))?;
+        }
+        if let Some((cdl, path)) = self.cdl {
+            cdl.save(&path)?;
+            println!("[+] wrote code/data log: {}", path);
+        }
+        if let Some(r) = self.recorder {
+            let path = r.get_path().to_string();
+            let frames = r.finish()?;
+            println!("[+] wrote recording: {} ({} frames)", path, frames);
+        }
+        if let Some(p) = self.profiler {
+            if let Some(ref path) = args.profile {
+                p.save_report(path, cpu.get_mem())?;
+                println!("[+] wrote profile: {}", path);
+            }
+            if let Some(ref path) = args.profile_folded {
+                p.save_folded(path)?;
+                println!("[+] wrote folded stacks: {}", path);
+            }
+        }
+        Ok(())
+    }
+}
+
+// execute a single instruction and feed it to the enabled tools
+fn step(cpu: &mut cpu::Cpu, keys: Vec<u8>, tools: &mut Tools) {
+    let before = cpu.get_registers();
+    let opcode = cpu.get_next_n_instr(1)[0];
+
+    let pending = history::History::begin(cpu);
+    cpu.execute(keys);
+    tools.history.push(pending, cpu);
+
+    let instr = decoder::decode(opcode);
+    tools.provenance.record(&before, instr.as_ref(), cpu);
+    if let Some(ref mut p) = tools.profiler {
+        if let Some(ref instr) = instr {
+            p.record(before.PC, instr);
+        }
+    }
+
+    if let Some((ref mut cdl, _)) = tools.cdl {
+        cdl.record(cpu.get_accesses());
+    }
+
+    if let Some(ref mut t) = tools.tracer {
+        if let Err(e) = t.log(cpu.get_cycles(), opcode, &before, &cpu.get_registers()) {
+            eprintln!("[-] failed to write trace, disable tracing: {}", e);
+            tools.tracer = None;
+        }
+    }
+}
+
+// disassembly shown in the debug panel, logged data is shown as `DB`
+fn disasm_line(addr: u16, instr: u16, tools: &Tools, symbols: &symbols::Symbols) -> String {
+    match tools.cdl {
+        Some((ref cdl, _)) if cdl.is_data(addr) => format!("DB {:04X}", instr),
+        _ => {
+            // uppercase mnemonic and registers, symbol names are shown as written
+            let addr = instr & 0x0fff;
+            let name = symbols
+                .describe(addr)
+                .unwrap_or_else(|| format!("{:04X}", addr));
+            decoder::disassemble_with(instr, |_| "@".to_string())
+                .to_ascii_uppercase()
+                .replace('@', &name)
+        }
+    }
+}
+
+fn exit_on_err<T>(res: Result<T, String>) -> T {
+    match res {
+        Ok(v) => v,
         Err(e) => {
             eprintln!("FAILED: {}", e);
             std::process::exit(1);
         }
-    };
+    }
+}
 
-    let mut cpu = cpu::Cpu::new(memory::Memory::new(), gpu::Gpu::new());
-    cpu.load_rom(&rom_data);
+fn main() {
+    let args = exit_on_err(args::parse());
 
-    let mut window = Window::new(
-        "CHIP-8 - ESC to exit",
-        640,
-        480,
-        WindowOptions {
-            borderless: false,
-            title: true,
-            resize: false,
-            scale: minifb::Scale::X1,
-        },
-    )
-    .unwrap_or_else(|e| {
-        panic!("{}", e);
-    });
-
-    let mut f500hz_ref = Instant::now();
-    let mut f60hz_ref = Instant::now();
-    let mut f30hz_ref = Instant::now();
-
-    #[derive(Debug)]
-    enum RunMode {
-        FreeRunning,
-        Stepping,
+    if let Some((ref a, ref b)) = args.diff_traces {
+        exit_on_err(diff::compare_traces(a, b));
+        return;
     }
-    let mut run_mode = RunMode::Stepping;
-
-    println!("[+] RunMode: {:?}", run_mode);
-    println!("[+] Change RunMode with 'G' | 'B'");
-    println!("    'G': FreeRunning");
-    println!("    'B': Stepping");
-    println!("[+] In Stepping mode use 'SPACE' to step one instruction");
-
-    let mut fb = pixel_engine::PixelVec::new(640, 480);
-
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        window
-            .get_keys_pressed(minifb::KeyRepeat::No)
-            .unwrap()
-            .iter()
-            .for_each(|&k| match k {
-                Key::B => {
-                    run_mode = RunMode::Stepping;
-                    println!("switching RunMode: {:?}", run_mode);
-                }
-                Key::G => {
-                    run_mode = RunMode::FreeRunning;
-                    println!("switching RunMode: {:?}", run_mode);
-                }
-                _ => {}
-            });
 
-        let mut draw_dbg = false;
-        let mut draw_fb = false;
+    if let Some(transport) = args.dap {
+        exit_on_err(dap::serve(transport, &args));
+        return;
+    }
 
-        match run_mode {
-            RunMode::FreeRunning => {
-                let now = Instant::now();
+    let rom_path = args.rom.clone().unwrap();
+    let rom_data = exit_on_err(load_rom_file(&rom_path));
 
-                if (now - f500hz_ref) > Duration::from_millis(2) {
-                    f500hz_ref = now;
-                    cpu.execute(remap_keys(window.get_keys().unwrap_or_default()));
-                    draw_dbg = true;
-                }
+    let mut cpu = cpu::Cpu::new(memory::Memory::new(), gpu::Gpu::new());
+    cpu.load_rom(&rom_data);
+    cpu.set_quirks(args.quirks);
+    cpu.get_gpu_mut().set_present(args.present);
+    if let Some(seed) = args.seed {
+        cpu.seed_rng(seed);
+    }
 
-                if (now - f60hz_ref) > Duration::from_millis(16) {
-                    f60hz_ref = now;
-                    cpu.timer_tick();
-                }
+    let symbols = match args.symbols {
+        Some(ref path) => exit_on_err(symbols::Symbols::load(path)),
+        None => symbols::Symbols::new(),
+    };
 
-                if (now - f30hz_ref) > Duration::from_millis(32) {
-                    f30hz_ref = now;
-                    draw_fb = true;
-                }
-            }
-            RunMode::Stepping => {
-                if window.is_key_pressed(Key::Space, minifb::KeyRepeat::Yes) {
-                    cpu.execute(remap_keys(window.get_keys().unwrap_or_default()));
-                    cpu.timer_tick();
-
-                    draw_dbg = true;
-                    draw_fb = true;
-                } else {
-                    window.update();
-                }
-            }
+    let cdl_path = cdl::sidecar_path(&rom_path);
+    let cdl = if args.cdl || args.disasm {
+        Some(exit_on_err(cdl::CodeDataLog::load(&cdl_path)))
+    } else {
+        None
+    };
+
+    if args.disasm {
+        let start = cpu::PROGRAM_START;
+        let end = start + rom_data.len() as u16;
+        for line in cdl.unwrap().listing(cpu.get_mem(), start, end, &symbols) {
+            println!("{}", line);
         }
+        return;
+    }
 
-        if draw_dbg {
-            // clear screen
-            pixel_engine::draw_rect(&mut fb, 4 * gpu::WIDTH + 22, 0, 0x00000000, 200, 400);
+    if let Some(quirks_b) = args.diff {
+        exit_on_err(diff::run_side_by_side(cpu, quirks_b, args.diff_cycles));
+        return;
+    }
 
-            for (c, &instr) in cpu.get_next_n_instr(10).iter().enumerate() {
-                let disasm = decoder::disassemble(instr).to_ascii_uppercase();
-                pixel_engine::draw_str(&mut fb, 4 * gpu::WIDTH + 22, c * 12, 0x00ffffff, disasm.as_str());
-            }
+    let tools = Tools {
+        tracer: exit_on_err(create_tracer(&args, &symbols)),
+        profiler: if args.profile.is_some() || args.profile_folded.is_some() {
+            Some(profile::Profiler::new())
+        } else {
+            None
+        },
+        cdl: cdl.map(|cdl| (cdl, cdl_path)),
+        history: history::History::new(history::CAPACITY),
+        provenance: provenance::Provenance::new(),
+        recorder: match args.record {
+            Some(ref path) => Some(exit_on_err(capture::Recorder::start(
+                path,
+                args.palette,
+                args.capture_scale,
+            ))),
+            None => None,
+        },
+    };
+    // screenshots and recordings are named after the ROM
+    let capture_prefix = Path::new(&rom_path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "chip8".to_string());
 
-            let y_offset = 10 * 12;
-            for (c, state) in cpu.dump_to_vec_str().iter().enumerate() {
-                pixel_engine::draw_str(
-                    &mut fb,
-                    4 * gpu::WIDTH + 22,
-                    y_offset + 12 * c,
-                    0x00ffffff,
-                    state.as_str(),
-                );
-            }
-        }
+    let mut session = host::Session {
+        cpu,
+        tools,
+        symbols,
+        debugger: debugger::Debugger::new(),
+        filter: args.filter.clone(),
+        mode: host::RunMode::Stepping,
+        palette: args.palette,
+        capture_scale: args.capture_scale,
+        capture_prefix,
+    };
+    println!("[+] RunMode: {:?}", session.mode);
 
-        if draw_fb {
-            for y in 0..gpu::HEIGHT {
-                for x in 0..gpu::WIDTH {
-                    let pixel = cpu.get_fb()[y * gpu::WIDTH + x] as u32 * 0x00ff0000;
-                    pixel_engine::draw_pixel_scaled(
-                        &mut fb,
-                        x * 4,
-                        y * 4,
-                        pixel,
-                        pixel_engine::PixelScale::X4,
-                    );
-                }
-            }
-            window.update_with_buffer(fb.buffer()).unwrap();
-        }
-    }
+    let result = if let Some(frames) = args.headless {
+        let input = match args.input {
+            Some(ref path) => std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read input script {}: {}", path, e))
+                .and_then(|text| host::ScriptInput::parse(&text, frames)),
+            None => Ok(host::ScriptInput::new(frames)),
+        };
+        input.and_then(|input| {
+            host::run(
+                &mut session,
+                &mut (host::NoVideo, input, host::Silence),
+                false,
+            )
+        })
+    } else if args.tui {
+        tui::TuiFrontend::new().and_then(|mut tui| host::run(&mut session, &mut tui, true))
+    } else {
+        window::WindowFrontend::new(&args)
+            .and_then(|mut window| host::run(&mut session, &mut window, true))
+    };
+    exit_on_err(result);
+
+    exit_on_err(session.tools.finish(&args, &session.cpu));
 }
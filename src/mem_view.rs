@@ -0,0 +1,325 @@
+use super::cpu::{AccessKind, Cpu};
+use super::memory::MEM_SIZE;
+use super::ui;
+
+use minifb::Key;
+use pixel_engine::PixelVec;
+
+const BYTES_PER_ROW: usize = 8;
+// header line + rows up to the bottom of the window
+const ROWS: usize = (ui::WINDOW_HEIGHT - ui::MEM_PANEL_Y) / ui::LINE_H - 1;
+// writes within the last RECENT instructions are highlighted
+const RECENT: u64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Follow {
+    Off,
+    I,
+    PC,
+}
+
+// scrollable hex/ascii view of the memory below the framebuffer
+//
+// keys:
+//   M                cycle follow mode (off, I, PC)
+//   PageUp/PageDown  scroll one page
+//   Up/Down          scroll one row (move cursor while editing)
+//   Enter            start/stop editing (only in Stepping mode)
+//   0-9, A-F         enter a byte at the cursor while editing
+pub struct MemView {
+    // first shown address
+    top: u16,
+    follow: Follow,
+    editing: bool,
+    cursor: u16,
+    // high nibble typed while editing
+    nibble: Option<u8>,
+    // cycle of the last write per address
+    last_write: Vec<Option<u64>>,
+}
+
+fn hex_digit(key: Key) -> Option<u8> {
+    match key {
+        Key::Key0 => Some(0x0),
+        Key::Key1 => Some(0x1),
+        Key::Key2 => Some(0x2),
+        Key::Key3 => Some(0x3),
+        Key::Key4 => Some(0x4),
+        Key::Key5 => Some(0x5),
+        Key::Key6 => Some(0x6),
+        Key::Key7 => Some(0x7),
+        Key::Key8 => Some(0x8),
+        Key::Key9 => Some(0x9),
+        Key::A => Some(0xa),
+        Key::B => Some(0xb),
+        Key::C => Some(0xc),
+        Key::D => Some(0xd),
+        Key::E => Some(0xe),
+        Key::F => Some(0xf),
+        _ => None,
+    }
+}
+
+impl MemView {
+    pub fn new() -> MemView {
+        MemView {
+            top: 0x200,
+            follow: Follow::Off,
+            editing: false,
+            cursor: 0x200,
+            nibble: None,
+            last_write: vec![None; MEM_SIZE],
+        }
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.editing
+    }
+
+    // scroll and cursor keys, and hex digits while editing, repeat when held
+    pub fn repeats(&self, key: Key) -> bool {
+        match key {
+            Key::Up | Key::Down | Key::PageUp | Key::PageDown => true,
+            Key::Left | Key::Right | Key::Backspace => self.editing,
+            _ => self.editing && hex_digit(key).is_some(),
+        }
+    }
+
+    // address of the edit cursor, the target of run-to-cursor, the cursor is
+    // only shown while editing
+    pub fn get_cursor(&self) -> Option<u16> {
+        if self.editing {
+            Some(self.cursor)
+        } else {
+            None
+        }
+    }
+
+    // remember the writes of the last executed instruction
+    pub fn record(&mut self, cpu: &Cpu) {
+        for a in cpu.get_accesses() {
+            if a.kind == AccessKind::Write {
+                self.last_write[a.addr as usize % MEM_SIZE] = Some(cpu.get_cycles());
+            }
+        }
+    }
+
+    // written within the last RECENT instructions, writes undone by stepping
+    // back are newer than the current cycle and not highlighted
+    fn is_recent(&self, addr: u16, cpu: &Cpu) -> bool {
+        match self.last_write[addr as usize % MEM_SIZE] {
+            Some(cycle) => cycle <= cpu.get_cycles() && cpu.get_cycles() - cycle < RECENT,
+            None => false,
+        }
+    }
+
+    // address of the byte shown at the window position
+    pub fn addr_at(&self, x: usize, y: usize) -> Option<u16> {
+        if y < ui::MEM_PANEL_Y + ui::LINE_H || x < 5 * ui::CHAR_W {
+            return None;
+        }
+        let row = (y - ui::MEM_PANEL_Y) / ui::LINE_H - 1;
+        // bytes are drawn in groups of two, see `draw`
+        let c = x / ui::CHAR_W - 5;
+        let col = 2 * (c / 5) + if c % 5 >= 2 { 1 } else { 0 };
+        if row >= ROWS || col >= BYTES_PER_ROW {
+            return None;
+        }
+        let addr = self.top as usize + row * BYTES_PER_ROW + col;
+        if addr < MEM_SIZE {
+            Some(addr as u16)
+        } else {
+            None
+        }
+    }
+
+    fn is_visible(&self, addr: u16) -> bool {
+        addr >= self.top && (addr as usize) < self.top as usize + ROWS * BYTES_PER_ROW
+    }
+
+    // set the first shown row, clamped to the memory size
+    fn scroll_to(&mut self, addr: i32) {
+        let max = (MEM_SIZE - ROWS * BYTES_PER_ROW) as i32;
+        let addr = std::cmp::max(0, std::cmp::min(addr, max));
+        self.top = (addr as usize / BYTES_PER_ROW * BYTES_PER_ROW) as u16;
+    }
+
+    pub fn scroll(&mut self, rows: i32) {
+        self.follow = Follow::Off;
+        self.scroll_to(self.top as i32 + rows * BYTES_PER_ROW as i32);
+    }
+
+    fn move_cursor(&mut self, offset: i32) {
+        let cursor = self.cursor as i32 + offset;
+        if cursor >= 0 && cursor < MEM_SIZE as i32 {
+            self.cursor = cursor as u16;
+            self.nibble = None;
+        }
+        if !self.is_visible(self.cursor) {
+            self.scroll_to(self.cursor as i32 - (ROWS / 2 * BYTES_PER_ROW) as i32);
+        }
+    }
+
+    // returns true if the view changed and must be redrawn
+    pub fn handle_key(&mut self, key: Key, cpu: &mut Cpu, paused: bool) -> bool {
+        if self.editing {
+            if !paused {
+                self.editing = false;
+                return true;
+            }
+            if let Some(digit) = hex_digit(key) {
+                match self.nibble.take() {
+                    Some(high) => {
+                        cpu.write_mem(self.cursor, (high << 4) | digit);
+                        self.last_write[self.cursor as usize] = Some(cpu.get_cycles());
+                        self.move_cursor(1);
+                    }
+                    None => self.nibble = Some(digit),
+                }
+                return true;
+            }
+        }
+
+        match key {
+            Key::M => {
+                self.follow = match self.follow {
+                    Follow::Off => Follow::I,
+                    Follow::I => Follow::PC,
+                    Follow::PC => Follow::Off,
+                };
+            }
+            Key::PageUp => self.scroll(-(ROWS as i32)),
+            Key::PageDown => self.scroll(ROWS as i32),
+            Key::Enter if paused => {
+                self.editing = !self.editing;
+                self.nibble = None;
+                if self.editing {
+                    self.follow = Follow::Off;
+                    if !self.is_visible(self.cursor) {
+                        self.cursor = self.top;
+                    }
+                }
+            }
+            Key::Enter => {
+                println!("[-] switch to Stepping mode to edit memory");
+                return false;
+            }
+            Key::Up if self.editing => self.move_cursor(-(BYTES_PER_ROW as i32)),
+            Key::Down if self.editing => self.move_cursor(BYTES_PER_ROW as i32),
+            Key::Left if self.editing => self.move_cursor(-1),
+            Key::Right if self.editing => self.move_cursor(1),
+            Key::Backspace if self.editing => self.nibble = None,
+            Key::Up => self.scroll(-1),
+            Key::Down => self.scroll(1),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn draw(&mut self, fb: &mut PixelVec, cpu: &Cpu) {
+        let regs = cpu.get_registers();
+        let target = match self.follow {
+            Follow::Off => None,
+            Follow::I => Some(regs.I),
+            Follow::PC => Some(regs.PC),
+        };
+        if let Some(addr) = target {
+            if !self.is_visible(addr) {
+                self.scroll_to(addr as i32 - (2 * BYTES_PER_ROW) as i32);
+            }
+        }
+
+        let y = ui::MEM_PANEL_Y;
+        pixel_engine::draw_rect(fb, 0, y, ui::BLACK, ui::PANEL_X - 2, ui::WINDOW_HEIGHT - y);
+
+        let header = if self.editing {
+            format!("MEM EDIT {:04X}", self.cursor)
+        } else {
+            format!("MEM FOLLOW {:?}", self.follow).to_ascii_uppercase()
+        };
+        pixel_engine::draw_str(fb, 0, y, ui::WHITE, &header);
+
+        let mem = cpu.get_mem();
+        for row in 0..ROWS {
+            let y = y + (row + 1) * ui::LINE_H;
+            let addr = self.top as usize + row * BYTES_PER_ROW;
+            if addr >= MEM_SIZE {
+                break;
+            }
+            pixel_engine::draw_str(fb, 0, y, ui::GREY, &format!("{:04X}", addr));
+
+            let bytes = &mem[addr..addr + BYTES_PER_ROW];
+            for (i, &byte) in bytes.iter().enumerate() {
+                let a = (addr + i) as u16;
+                // two bytes per group: `0011 2233 4455 6677`
+                let x = (5 + 2 * i + i / 2) * ui::CHAR_W;
+
+                let recent = self.is_recent(a, cpu);
+                let color = if a == regs.PC || a == regs.PC + 1 {
+                    ui::CYAN
+                } else if a == regs.I {
+                    ui::GREEN
+                } else if recent {
+                    ui::YELLOW
+                } else {
+                    ui::WHITE
+                };
+
+                let text = match self.nibble {
+                    Some(high) if self.editing && a == self.cursor => format!("{:X}_", high),
+                    _ => format!("{:02X}", byte),
+                };
+                if self.editing && a == self.cursor {
+                    pixel_engine::draw_rect(fb, x, y, color, 2 * ui::CHAR_W, ui::LINE_H);
+                    pixel_engine::draw_str(fb, x, y, ui::BLACK, &text);
+                } else {
+                    pixel_engine::draw_str(fb, x, y, color, &text);
+                }
+            }
+
+            let ascii: String = bytes
+                .iter()
+                .map(|&b| {
+                    if (0x20..0x7f).contains(&b) {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            pixel_engine::draw_str(fb, 25 * ui::CHAR_W, y, ui::GREY, &ascii);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpu::Gpu;
+    use crate::history::{History, CAPACITY};
+    use crate::memory::Memory;
+
+    #[test]
+    fn recent_after_step_back() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        cpu.load_rom(&[
+            0x60, 0x05, // 0200: LD V0, 05
+            0xa3, 0x00, // 0202: LD I, 0300
+            0xf0, 0x55, // 0204: LD [I], V0
+        ]);
+        let mut view = MemView::new();
+        let mut history = History::new(CAPACITY);
+        let mut fb = PixelVec::new(ui::WINDOW_WIDTH, ui::WINDOW_HEIGHT);
+        for _ in 0..3 {
+            let pending = History::begin(&cpu);
+            cpu.execute(vec![]);
+            history.push(pending, &cpu);
+            view.record(&cpu);
+        }
+        assert!(view.is_recent(0x300, &cpu));
+
+        history.step_back(&mut cpu).unwrap();
+        assert!(!view.is_recent(0x300, &cpu));
+        view.draw(&mut fb, &cpu);
+    }
+}
@@ -1,5 +1,14 @@
+pub const MEM_SIZE: usize = 4096;
+
+#[derive(Clone)]
 pub struct Memory {
-    mem: [u8; 4096],
+    mem: [u8; MEM_SIZE],
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
 }
 
 impl Memory {
@@ -19,7 +28,7 @@ impl Memory {
             0b11110000, 0b10000000, 0b10000000,
         ];
 
-        let mut mem = [0u8; 4096];
+        let mut mem = [0u8; MEM_SIZE];
         mem[0..sprites.len()].copy_from_slice(&sprites);
 
         Memory { mem: mem }
@@ -41,6 +50,30 @@ impl Memory {
         self.mem[addr as usize] = data;
     }
 
+    pub fn read_slice(&self, addr: u16, len: usize) -> Result<&[u8], String> {
+        let addr = addr as usize;
+        if addr + len > self.mem.len() {
+            return Err(format!(
+                "read of {} bytes at {:04x} out of range",
+                len, addr
+            ));
+        }
+        Ok(&self.mem[addr..addr + len])
+    }
+
+    pub fn write_slice(&mut self, addr: u16, data: &[u8]) -> Result<(), String> {
+        let addr = addr as usize;
+        if addr + data.len() > self.mem.len() {
+            return Err(format!(
+                "write of {} bytes at {:04x} out of range",
+                data.len(),
+                addr
+            ));
+        }
+        self.mem[addr..addr + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
     pub fn dump_range(&self, addr: usize, size: usize) {
         if addr > self.mem.len() {
             return;
@@ -69,3 +102,31 @@ impl Memory {
         self.dump_range(0x0, self.mem.len());
     }
 }
+
+impl AsRef<[u8]> for Memory {
+    fn as_ref(&self) -> &[u8] {
+        &self.mem
+    }
+}
+
+impl AsMut<[u8]> for Memory {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.mem
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn slices() {
+        let mut mem = Memory::new();
+        mem.write_slice(0x300, &[1, 2, 3]).unwrap();
+        assert_eq!(mem.read_slice(0x2ff, 5).unwrap(), &[0, 1, 2, 3, 0]);
+        assert_eq!(mem.read_slice(0xffc, 4).unwrap().len(), 4);
+        assert!(mem.read_slice(0xffc, 5).is_err());
+        assert!(mem.write_slice(0xfff, &[1, 2]).is_err());
+        assert_eq!(mem.read_byte(0xfff), 0);
+    }
+}
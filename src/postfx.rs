@@ -0,0 +1,274 @@
+use super::display::Image;
+
+// brightness kept by the darkened rows/columns of the effects
+const SCANLINE: f32 = 0.5;
+const GRID: f32 = 0.7;
+const MASK: f32 = 0.6;
+
+// post-processing of the rendered game, all done on the CPU
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PostFx {
+    // upscale with Scale2x/Scale3x (EPX) instead of nearest neighbour
+    pub smooth: bool,
+    pub scanlines: bool,
+    pub grid: bool,
+    // CRT shadow mask, columns alternate between red, green and blue
+    pub shadow_mask: bool,
+}
+
+impl PostFx {
+    pub const NAMES: [&'static str; 4] = ["epx", "scanlines", "grid", "crt"];
+
+    // parse a comma separated list of effect names, `none` enables nothing
+    pub fn parse(list: &str) -> Result<PostFx, String> {
+        let mut fx = PostFx::default();
+        for name in list.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+            match name {
+                "none" => {}
+                "epx" | "scale2x" => fx.smooth = true,
+                "scanlines" => fx.scanlines = true,
+                "grid" => fx.grid = true,
+                "crt" => fx.shadow_mask = true,
+                _ => {
+                    return Err(format!(
+                        "unknown effect '{}', available: {}",
+                        name,
+                        PostFx::NAMES.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(fx)
+    }
+
+    // scale the game rendered at its native resolution by `scale` and apply
+    // the effects, every game pixel becomes a `scale` x `scale` cell
+    pub fn process(&self, native: &Image, scale: usize) -> Image {
+        let mut img = if self.smooth {
+            // smooth the factors 2 and 3 of the scale, the rest is nearest
+            let (mut img, mut rest) = (None, scale);
+            while rest % 2 == 0 || rest % 3 == 0 {
+                let src = img.as_ref().unwrap_or(native);
+                img = Some(if rest % 2 == 0 {
+                    rest /= 2;
+                    scale2x(src)
+                } else {
+                    rest /= 3;
+                    scale3x(src)
+                });
+            }
+            nearest(img.as_ref().unwrap_or(native), rest)
+        } else {
+            nearest(native, scale)
+        };
+
+        let width = img.width;
+        for (y, row) in img.pixels.chunks_mut(width).enumerate() {
+            // scale 1 has no room inside a cell, use every other row instead
+            let scanline = if scale > 1 {
+                y % scale == scale - 1
+            } else {
+                y % 2 == 1
+            };
+            for (x, p) in row.iter_mut().enumerate() {
+                if self.scanlines && scanline {
+                    *p = darken(*p, SCANLINE);
+                }
+                if self.grid && scale > 1 && (x % scale == 0 || y % scale == 0) {
+                    *p = darken(*p, GRID);
+                }
+                if self.shadow_mask {
+                    *p = mask(*p, x % 3);
+                }
+            }
+        }
+        img
+    }
+}
+
+fn channels(color: u32) -> [f32; 3] {
+    [
+        (color >> 16 & 0xff) as f32,
+        (color >> 8 & 0xff) as f32,
+        (color & 0xff) as f32,
+    ]
+}
+
+fn rgb(c: [f32; 3]) -> u32 {
+    (c[0] as u32) << 16 | (c[1] as u32) << 8 | c[2] as u32
+}
+
+fn darken(color: u32, kept: f32) -> u32 {
+    let c = channels(color);
+    rgb([c[0] * kept, c[1] * kept, c[2] * kept])
+}
+
+// keep the channel `phase` (0 red, 1 green, 2 blue), dim the others
+fn mask(color: u32, phase: usize) -> u32 {
+    let mut c = channels(color);
+    for (i, v) in c.iter_mut().enumerate() {
+        if i != phase {
+            *v *= MASK;
+        }
+    }
+    rgb(c)
+}
+
+pub fn nearest(src: &Image, scale: usize) -> Image {
+    let mut img = Image::new(src.width * scale, src.height * scale, 0);
+    for (y, row) in img.pixels.chunks_mut(src.width * scale).enumerate() {
+        for (x, p) in row.iter_mut().enumerate() {
+            *p = src.pixels[y / scale * src.width + x / scale];
+        }
+    }
+    img
+}
+
+// pixel at (x + dx, y + dy), the border pixels repeat outside the image
+fn neighbour(src: &Image, x: usize, y: usize, dx: isize, dy: isize) -> u32 {
+    let clamp = |v: usize, d: isize, max: usize| (v as isize + d).max(0).min(max as isize - 1);
+    let (x, y) = (clamp(x, dx, src.width), clamp(y, dy, src.height));
+    src.pixels[y as usize * src.width + x as usize]
+}
+
+// Scale2x/EPX, doubles the resolution and rounds off diagonal edges
+pub fn scale2x(src: &Image) -> Image {
+    let mut img = Image::new(src.width * 2, src.height * 2, 0);
+    for y in 0..src.height {
+        for x in 0..src.width {
+            let n = |dx, dy| neighbour(src, x, y, dx, dy);
+            let (p, a, b, c, d) = (n(0, 0), n(0, -1), n(1, 0), n(-1, 0), n(0, 1));
+            let out = [
+                if c == a && c != d && a != b { a } else { p },
+                if a == b && a != c && b != d { b } else { p },
+                if d == c && d != b && c != a { c } else { p },
+                if b == d && b != a && d != c { d } else { p },
+            ];
+            for (i, &color) in out.iter().enumerate() {
+                img.pixels[(2 * y + i / 2) * img.width + 2 * x + i % 2] = color;
+            }
+        }
+    }
+    img
+}
+
+// Scale3x (AdvMAME3x), the Scale2x rules for a factor of three
+pub fn scale3x(src: &Image) -> Image {
+    let mut img = Image::new(src.width * 3, src.height * 3, 0);
+    for y in 0..src.height {
+        for x in 0..src.width {
+            let n = |dx, dy| neighbour(src, x, y, dx, dy);
+            // a b c
+            // d e f
+            // g h i
+            let (a, b, c) = (n(-1, -1), n(0, -1), n(1, -1));
+            let (d, e, f) = (n(-1, 0), n(0, 0), n(1, 0));
+            let (g, h, i) = (n(-1, 1), n(0, 1), n(1, 1));
+            let db = d == b && b != f && d != h;
+            let bf = b == f && b != d && f != h;
+            let dh = d == h && d != b && h != f;
+            let hf = h == f && d != h && b != f;
+            let out = [
+                if db { d } else { e },
+                if (db && e != c) || (bf && e != a) {
+                    b
+                } else {
+                    e
+                },
+                if bf { f } else { e },
+                if (db && e != g) || (dh && e != a) {
+                    d
+                } else {
+                    e
+                },
+                e,
+                if (bf && e != i) || (hf && e != c) {
+                    f
+                } else {
+                    e
+                },
+                if dh { d } else { e },
+                if (hf && e != g) || (dh && e != i) {
+                    h
+                } else {
+                    e
+                },
+                if hf { f } else { e },
+            ];
+            for (k, &color) in out.iter().enumerate() {
+                img.pixels[(3 * y + k / 3) * img.width + 3 * x + k % 3] = color;
+            }
+        }
+    }
+    img
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const W: u32 = 0xffffff;
+
+    fn image(width: usize, rows: &[&str]) -> Image {
+        let mut img = Image::new(width, rows.len(), 0);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == '#' {
+                    img.pixels[y * width + x] = W;
+                }
+            }
+        }
+        img
+    }
+
+    #[test]
+    fn smooth_scaling() {
+        // a diagonal line gets its steps filled
+        let src = image(4, &["....", ".#..", "..#.", "...."]);
+        let img = scale2x(&src);
+        let expected = image(
+            8,
+            &[
+                "........", "........", "..##....", "..###...", "...###..", "....##..", "........",
+                "........",
+            ],
+        );
+        assert_eq!(img.pixels, expected.pixels);
+
+        // a single pixel is not changed
+        let dot = image(3, &["...", ".#.", "..."]);
+        assert_eq!(scale2x(&dot).pixels, nearest(&dot, 2).pixels);
+        assert_eq!(scale3x(&dot).pixels, nearest(&dot, 3).pixels);
+
+        let fx = PostFx::parse("epx").unwrap();
+        for &scale in &[1, 2, 3, 4, 5, 6] {
+            let img = fx.process(&src, scale);
+            assert_eq!((img.width, img.height), (4 * scale, 4 * scale));
+        }
+    }
+
+    #[test]
+    fn effects() {
+        let src = image(2, &["##", "##"]);
+        let fx = PostFx::parse("scanlines, grid").unwrap();
+        let img = fx.process(&src, 4);
+        assert_eq!(img.pixels[1], darken(W, GRID));
+        assert_eq!(img.pixels[8 * 3 + 1], darken(W, SCANLINE));
+        assert_eq!(img.pixels[8 * 3 + 4], darken(darken(W, SCANLINE), GRID));
+        assert_eq!(img.pixels[8 * 2 + 2], W);
+
+        // scale 1, every other row
+        let img = fx.process(&src, 1);
+        assert_eq!(
+            img.pixels,
+            vec![W, W, darken(W, SCANLINE), darken(W, SCANLINE)]
+        );
+
+        let img = PostFx::parse("crt").unwrap().process(&src, 1);
+        assert_eq!(img.pixels[0], 0xff9999);
+        assert_eq!(img.pixels[1], 0x99ff99);
+
+        assert_eq!(PostFx::parse("none").unwrap(), PostFx::default());
+        assert!(PostFx::parse("blur").is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use super::decoder::{self, Instruction};
+use super::memory::MEM_SIZE;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// the program entry point is used as name for the outermost frame
+const ROOT: u16 = 0x200;
+// number of entries shown per report section
+const TOP: usize = 20;
+
+#[derive(Default)]
+struct Routine {
+    calls: u64,
+    // cycles spent in the routine including called routines
+    inclusive: u64,
+    // cycles spent in the routine itself
+    exclusive: u64,
+}
+
+pub struct Profiler {
+    total: u64,
+    pc_hits: Vec<u64>,
+    max_hits: u64,
+    instr_hits: HashMap<&'static str, u64>,
+    // routine addresses of the active call frames, starting with ROOT
+    stack: Vec<u16>,
+    routines: HashMap<u16, Routine>,
+    // cycles per unique call stack, used for the folded output
+    stacks: HashMap<Vec<u16>, u64>,
+    // backward jumps (target, jump addr) -> number of iterations
+    loops: HashMap<(u16, u16), u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            total: 0,
+            pc_hits: vec![0; MEM_SIZE],
+            max_hits: 0,
+            instr_hits: HashMap::new(),
+            stack: vec![ROOT],
+            routines: HashMap::new(),
+            stacks: HashMap::new(),
+            loops: HashMap::new(),
+        }
+    }
+
+    // account one executed instruction at `pc`
+    pub fn record(&mut self, pc: u16, instr: &Instruction) {
+        self.total += 1;
+
+        let hits = &mut self.pc_hits[pc as usize % MEM_SIZE];
+        *hits += 1;
+        self.max_hits = std::cmp::max(self.max_hits, *hits);
+
+        *self.instr_hits.entry(instr.name()).or_insert(0) += 1;
+
+        // the instruction belongs to the routine it is executed in, this
+        // includes the Call/Return instructions entering/leaving a routine
+        for (i, &addr) in self.stack.iter().enumerate() {
+            // count recursive routines only once
+            if !self.stack[..i].contains(&addr) {
+                self.routines.entry(addr).or_default().inclusive += 1;
+            }
+        }
+        let top = *self.stack.last().unwrap();
+        self.routines.entry(top).or_default().exclusive += 1;
+
+        match self.stacks.get_mut(self.stack.as_slice()) {
+            Some(cycles) => *cycles += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+
+        match *instr {
+            Instruction::Call(addr) => {
+                self.routines.entry(addr).or_default().calls += 1;
+                self.stack.push(addr);
+            }
+            // never pop the root frame, the program may return from a routine
+            // which was entered before profiling started
+            Instruction::Return if self.stack.len() > 1 => {
+                self.stack.pop();
+            }
+            Instruction::Jump(addr) if addr <= pc => {
+                *self.loops.entry((addr, pc)).or_insert(0) += 1;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn get_hits(&self, addr: u16) -> u64 {
+        self.pc_hits[addr as usize % MEM_SIZE]
+    }
+
+    // relative execution count of `addr` in the range [0.0, 1.0], log scaled
+    // to keep rarely executed code visible next to hot loops
+    pub fn heat(&self, addr: u16) -> f32 {
+        if self.max_hits == 0 {
+            return 0.0;
+        }
+        let hits = self.get_hits(addr) as f32;
+        (1.0 + hits).ln() / (1.0 + self.max_hits as f32).ln()
+    }
+
+    fn percent(&self, cycles: u64) -> f64 {
+        100.0 * cycles as f64 / std::cmp::max(self.total, 1) as f64
+    }
+
+    pub fn write_report<W: Write>(&self, out: &mut W, mem: &[u8]) -> std::io::Result<()> {
+        let opcode = |addr: u16| {
+            let addr = addr as usize;
+            u16::from_be_bytes([mem[addr % MEM_SIZE], mem[(addr + 1) % MEM_SIZE]])
+        };
+
+        writeln!(out, "---- profile: {} instructions ----", self.total)?;
+
+        writeln!(out, "\n---- hottest addresses ----")?;
+        let mut addrs: Vec<(u16, u64)> = (0..MEM_SIZE)
+            .filter(|&a| self.pc_hits[a] > 0)
+            .map(|a| (a as u16, self.pc_hits[a]))
+            .collect();
+        addrs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for &(addr, hits) in addrs.iter().take(TOP) {
+            writeln!(
+                out,
+                "{:04x}: {:>10} {:>6.2}%  {}",
+                addr,
+                hits,
+                self.percent(hits),
+                decoder::disassemble(opcode(addr))
+            )?;
+        }
+
+        writeln!(out, "\n---- instructions ----")?;
+        let mut instrs: Vec<(&str, u64)> = self.instr_hits.iter().map(|(&n, &c)| (n, c)).collect();
+        instrs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        for (name, hits) in instrs {
+            writeln!(
+                out,
+                "{:<24} {:>10} {:>6.2}%",
+                name,
+                hits,
+                self.percent(hits)
+            )?;
+        }
+
+        writeln!(out, "\n---- routines ----")?;
+        writeln!(
+            out,
+            "{:<6} {:>8} {:>10} {:>8} {:>10} {:>8}",
+            "addr", "calls", "inclusive", "%", "exclusive", "%"
+        )?;
+        let mut routines: Vec<(&u16, &Routine)> = self.routines.iter().collect();
+        routines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        for (addr, r) in routines.iter().take(TOP) {
+            writeln!(
+                out,
+                "{:04x}   {:>8} {:>10} {:>7.2}% {:>10} {:>7.2}%",
+                addr,
+                r.calls,
+                r.inclusive,
+                self.percent(r.inclusive),
+                r.exclusive,
+                self.percent(r.exclusive)
+            )?;
+        }
+
+        writeln!(out, "\n---- hot loops ----")?;
+        let mut loops: Vec<(&(u16, u16), &u64)> = self.loops.iter().collect();
+        loops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (&(start, end), iterations) in loops.iter().take(TOP) {
+            let cycles: u64 = (start..=end).map(|a| self.get_hits(a)).sum();
+            writeln!(
+                out,
+                "{:04x}..{:04x}: {:>10} iterations {:>10} instructions {:>6.2}%",
+                start,
+                end,
+                iterations,
+                cycles,
+                self.percent(cycles)
+            )?;
+        }
+        Ok(())
+    }
+
+    // one line per call stack `root;caller;callee cycles` as used by
+    // flamegraph tools
+    pub fn write_folded<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .map(|(stack, &cycles)| {
+                let names: Vec<String> = stack.iter().map(|a| format!("{:04x}", a)).collect();
+                (names.join(";"), cycles)
+            })
+            .collect();
+        stacks.sort();
+        for (stack, cycles) in stacks {
+            writeln!(out, "{} {}", stack, cycles)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_report<P: AsRef<Path>>(&self, path: P, mem: &[u8]) -> Result<(), String> {
+        File::create(&path)
+            .and_then(|f| {
+                let mut out = BufWriter::new(f);
+                self.write_report(&mut out, mem)?;
+                out.flush()
+            })
+            .map_err(|e| format!("Failed to write {}: {}", path.as_ref().display(), e))
+    }
+
+    pub fn save_folded<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        File::create(&path)
+            .and_then(|f| {
+                let mut out = BufWriter::new(f);
+                self.write_folded(&mut out)?;
+                out.flush()
+            })
+            .map_err(|e| format!("Failed to write {}: {}", path.as_ref().display(), e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decoder::decode;
+
+    #[test]
+    fn routines() {
+        let mut p = Profiler::new();
+        // 0200: CALL 0300 -> 0300: LD V0, 01 -> 0302: RET -> 0202: JP 0200
+        p.record(0x200, &decode(0x2300).unwrap());
+        p.record(0x300, &decode(0x6001).unwrap());
+        p.record(0x302, &decode(0x00ee).unwrap());
+        p.record(0x202, &decode(0x1200).unwrap());
+
+        assert_eq!(p.get_hits(0x300), 1);
+        assert_eq!(p.routines[&0x200].inclusive, 4);
+        assert_eq!(p.routines[&0x200].exclusive, 2);
+        assert_eq!(p.routines[&0x300].calls, 1);
+        assert_eq!(p.routines[&0x300].exclusive, 2);
+        assert_eq!(p.loops[&(0x200, 0x202)], 1);
+
+        let mut folded = Vec::new();
+        p.write_folded(&mut folded).unwrap();
+        assert_eq!(String::from_utf8(folded).unwrap(), "0200 2\n0200;0300 2\n");
+    }
+
+    #[test]
+    fn heat() {
+        let mut p = Profiler::new();
+        assert_eq!(p.heat(0x200), 0.0);
+        p.record(0x200, &decode(0x6001).unwrap());
+        p.record(0x200, &decode(0x6001).unwrap());
+        p.record(0x202, &decode(0x6001).unwrap());
+        assert_eq!(p.heat(0x200), 1.0);
+        assert!(p.heat(0x202) > 0.0 && p.heat(0x202) < 1.0);
+        assert_eq!(p.heat(0x204), 0.0);
+    }
+}
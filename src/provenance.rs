@@ -0,0 +1,182 @@
+use super::cpu::{AccessKind, Cpu, Registers};
+use super::decoder::Instruction;
+use super::memory::MEM_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reg {
+    V(usize),
+    I,
+    DT,
+    ST,
+    SP,
+}
+
+impl Reg {
+    pub fn all() -> Vec<Reg> {
+        let mut regs: Vec<Reg> = (0..16).map(Reg::V).collect();
+        regs.extend_from_slice(&[Reg::I, Reg::DT, Reg::ST, Reg::SP]);
+        regs
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Reg::V(i) => format!("V{:X}", i),
+            Reg::I => "I".to_string(),
+            Reg::DT => "DT".to_string(),
+            Reg::ST => "ST".to_string(),
+            Reg::SP => "SP".to_string(),
+        }
+    }
+
+    fn index(&self) -> usize {
+        match *self {
+            Reg::V(i) => i,
+            Reg::I => 16,
+            Reg::DT => 17,
+            Reg::ST => 18,
+            Reg::SP => 19,
+        }
+    }
+
+    fn value(&self, regs: &Registers) -> usize {
+        match *self {
+            Reg::V(i) => regs.V[i] as usize,
+            Reg::I => regs.I as usize,
+            Reg::DT => regs.DT as usize,
+            Reg::ST => regs.ST as usize,
+            Reg::SP => regs.SP,
+        }
+    }
+}
+
+// instruction that last wrote a register or memory byte
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Writer {
+    pub pc: u16,
+    pub cycle: u64,
+}
+
+// last writer of every register and memory byte
+pub struct Provenance {
+    regs: [Option<Writer>; 20],
+    mem: Vec<Option<Writer>>,
+    // registers whose value changed in the last step
+    changed: Vec<Reg>,
+}
+
+// registers written by an instruction, even if the value stays the same
+fn destinations(instr: &Instruction) -> Vec<Reg> {
+    use Instruction::*;
+    match *instr {
+        LoadVxByte(x, _)
+        | AddVxByte(x, _)
+        | LoadVxVy(x, _)
+        | AndVxVy(x, _)
+        | OrVxVy(x, _)
+        | XorVxVy(x, _)
+        | LoadVxDT(x)
+        | LoadVxKey(x)
+        | RandVxAndByte(x, _) => vec![Reg::V(x)],
+        AddVxVy(x, _) | SubVxVy(x, _) | SubnVxVy(x, _) | ShlVxby1(x) | ShrVxby1(x) => {
+            vec![Reg::V(x), Reg::V(0xf)]
+        }
+        DisplaySpriteVxVyNibble(..) => vec![Reg::V(0xf)],
+        LoadRegsVx(x) => (0..=x).map(Reg::V).collect(),
+        LoadIAddr(_) | AddIVx(_) | LoadSpriteAddrVx(_) => vec![Reg::I],
+        LoadDTVx(_) => vec![Reg::DT],
+        LoadSTVx(_) => vec![Reg::ST],
+        Call(_) | Return => vec![Reg::SP],
+        _ => Vec::new(),
+    }
+}
+
+impl Provenance {
+    pub fn new() -> Provenance {
+        Provenance {
+            regs: [None; 20],
+            mem: vec![None; MEM_SIZE],
+            changed: Vec::new(),
+        }
+    }
+
+    // record the writes of the instruction executed from state `before`
+    pub fn record(&mut self, before: &Registers, instr: Option<&Instruction>, cpu: &Cpu) {
+        let after = cpu.get_registers();
+        let writer = Writer {
+            pc: before.PC,
+            cycle: cpu.get_cycles(),
+        };
+
+        self.changed = Reg::all()
+            .into_iter()
+            .filter(|r| r.value(before) != r.value(&after))
+            .collect();
+
+        // a key wait does not write VX until it completes
+        let waiting = after.PC == before.PC;
+        if let Some(instr) = instr.filter(|_| !waiting) {
+            for reg in destinations(instr) {
+                self.regs[reg.index()] = Some(writer);
+            }
+        }
+        // registers changed through quirks, e.g. I incremented by FX55
+        for reg in &self.changed {
+            self.regs[reg.index()] = Some(writer);
+        }
+
+        for a in cpu.get_accesses() {
+            if a.kind == AccessKind::Write {
+                self.mem[a.addr as usize % MEM_SIZE] = Some(writer);
+            }
+        }
+    }
+
+    pub fn get_reg(&self, reg: Reg) -> Option<Writer> {
+        self.regs[reg.index()]
+    }
+
+    pub fn get_mem(&self, addr: u16) -> Option<Writer> {
+        self.mem[addr as usize % MEM_SIZE]
+    }
+
+    pub fn changed(&self) -> &[Reg] {
+        &self.changed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decoder;
+    use crate::gpu::Gpu;
+    use crate::memory::Memory;
+
+    #[test]
+    fn record() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        // 0200: LD V5, 00 | LD I, 0300 | LD [I], V1 | ADD V5, V5
+        cpu.load_rom(&[0x65, 0x00, 0xa3, 0x00, 0xf1, 0x55, 0x85, 0x54]);
+        let mut prov = Provenance::new();
+        for _ in 0..4 {
+            let before = cpu.get_registers();
+            let instr = decoder::decode(cpu.get_next_n_instr(1)[0]);
+            cpu.execute(vec![]);
+            prov.record(&before, instr.as_ref(), &cpu);
+        }
+
+        // same value written is still a write
+        assert_eq!(
+            prov.get_reg(Reg::V(5)),
+            Some(Writer {
+                pc: 0x206,
+                cycle: 4
+            })
+        );
+        assert_eq!(prov.get_reg(Reg::V(0xf)).map(|w| w.pc), Some(0x206));
+        assert_eq!(prov.get_reg(Reg::I).map(|w| w.pc), Some(0x202));
+        assert_eq!(prov.get_reg(Reg::V(0)), None);
+        assert_eq!(prov.get_mem(0x301).map(|w| w.cycle), Some(3));
+        assert_eq!(prov.get_mem(0x302), None);
+        assert!(prov.changed().is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+use super::cpu::Cpu;
+use super::provenance::{Provenance, Reg, Writer};
+use super::symbols::Symbols;
+use super::ui;
+
+use pixel_engine::PixelVec;
+
+const X: usize = ui::PANEL_X;
+// below the 10 disassembly lines
+const Y: usize = 10 * ui::LINE_H;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Selection {
+    Reg(Reg),
+    Mem(u16),
+}
+
+// register area of the debug panel, changed registers are highlighted and a
+// clicked register or memory byte shows the instruction that last wrote it
+pub struct RegView {
+    selected: Option<Selection>,
+}
+
+// (register, line, first column, end column) of every register, from the
+// fixed layout of `CpuState::format`, symbol names appended to the addresses
+// can't shift them
+fn spans(cpu: &Cpu) -> Vec<(Reg, usize, usize, usize)> {
+    Reg::all()
+        .into_iter()
+        .filter_map(|reg| {
+            let (line, start, width) = match reg {
+                // `V0: 00   V1: 00  V2: 00  V3: 00`
+                Reg::V(i) => (1 + i / 4, [0, 9, 17, 25][i % 4], 6),
+                // `DT: 00   ST: 00`
+                Reg::DT => (5, 0, 6),
+                Reg::ST => (5, 9, 6),
+                Reg::I => (6, 0, 8),
+                // only shown while there is a return address
+                Reg::SP if cpu.get_stack().is_empty() => return None,
+                Reg::SP => (8, 0, 8),
+            };
+            Some((reg, line, start, start + width))
+        })
+        .collect()
+}
+
+fn describe(name: &str, writer: Option<Writer>, symbols: &Symbols) -> String {
+    match writer {
+        Some(w) => format!(
+            "{} <- {:04X} {} @{}",
+            name,
+            w.pc,
+            symbols.describe(w.pc).unwrap_or_default(),
+            w.cycle
+        ),
+        None => format!("{} never written", name),
+    }
+}
+
+impl RegView {
+    pub fn new() -> RegView {
+        RegView { selected: None }
+    }
+
+    // select the register at the window position, returns true on a hit
+    pub fn click(&mut self, x: usize, y: usize, cpu: &Cpu) -> bool {
+        if x < X || y < Y {
+            return false;
+        }
+        let (line, col) = ((y - Y) / ui::LINE_H, (x - X) / ui::CHAR_W);
+        match spans(cpu)
+            .into_iter()
+            .find(|&(_, nr, start, end)| nr == line && col >= start && col < end)
+        {
+            Some((reg, ..)) => {
+                self.selected = Some(Selection::Reg(reg));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn select_mem(&mut self, addr: u16) {
+        self.selected = Some(Selection::Mem(addr));
+    }
+
+    pub fn draw(&self, fb: &mut PixelVec, cpu: &Cpu, symbols: &Symbols, prov: &Provenance) {
+        let lines = cpu.dump_to_vec_str(symbols);
+        for (nr, line) in lines.iter().enumerate() {
+            pixel_engine::draw_str(fb, X, Y + nr * ui::LINE_H, ui::WHITE, line);
+        }
+
+        // redraw changed and selected registers in their color
+        for (reg, nr, start, end) in spans(cpu) {
+            let color = if self.selected == Some(Selection::Reg(reg)) {
+                ui::CYAN
+            } else if prov.changed().contains(&reg) {
+                ui::YELLOW
+            } else {
+                continue;
+            };
+            pixel_engine::draw_rect(
+                fb,
+                X + start * ui::CHAR_W,
+                Y + nr * ui::LINE_H,
+                ui::BLACK,
+                (end - start) * ui::CHAR_W,
+                ui::LINE_H,
+            );
+            pixel_engine::draw_str(
+                fb,
+                X + start * ui::CHAR_W,
+                Y + nr * ui::LINE_H,
+                color,
+                &lines[nr][start..end],
+            );
+        }
+
+        let info = match self.selected {
+            Some(Selection::Reg(reg)) => describe(&reg.name(), prov.get_reg(reg), symbols),
+            Some(Selection::Mem(addr)) => {
+                describe(&format!("[{:04X}]", addr), prov.get_mem(addr), symbols)
+            }
+            None => return,
+        };
+        pixel_engine::draw_str(fb, X, Y + (lines.len() + 1) * ui::LINE_H, ui::CYAN, &info);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gpu::Gpu;
+    use crate::memory::Memory;
+
+    #[test]
+    fn register_spans() {
+        let mut cpu = Cpu::new(Memory::new(), Gpu::new());
+        // 0200: CALL 0204 | 0204: RET
+        cpu.load_rom(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xee]);
+        cpu.execute(vec![]);
+        cpu.set_st(5);
+        // register labels in a symbol name on the PC line
+        let symbols = Symbols::parse("0x204 SP:DT:\n").unwrap();
+        let lines = cpu.dump_to_vec_str(&symbols);
+
+        let text = |reg| {
+            let (_, nr, start, end) = spans(&cpu).into_iter().find(|s| s.0 == reg).unwrap();
+            &lines[nr][start..end]
+        };
+        assert_eq!(text(Reg::V(1)), "V1: 00");
+        assert_eq!(text(Reg::V(0xf)), "VF: 00");
+        assert_eq!(text(Reg::ST), "ST: 05");
+        assert_eq!(text(Reg::I), "I : 0000");
+        assert_eq!(text(Reg::SP), "SP: 0202");
+
+        cpu.execute(vec![]);
+        assert!(!spans(&cpu).iter().any(|s| s.0 == Reg::SP));
+    }
+}
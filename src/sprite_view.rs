@@ -0,0 +1,164 @@
+use super::cpu::Cpu;
+use super::decoder::{self, Instruction};
+use super::gpu;
+use super::ui;
+
+use minifb::Key;
+use pixel_engine::PixelVec;
+
+const MAX_LINES: usize = 15;
+// size of a sprite pixel in the panel
+const SCALE: usize = 8;
+const X: usize = ui::PANEL_X + 210;
+
+const PIXEL_ON: u32 = ui::WHITE;
+const PIXEL_OFF: u32 = 0x00303030;
+// overlay colors for pixels turned on/off by the next DRW
+const OVERLAY_ON: u32 = 0x0000a000;
+const OVERLAY_OFF: u32 = 0x00ffff00;
+
+// bitmap view of sprite data in memory, right of the debug panel
+//
+// keys:
+//   I        toggle between following I and a fixed address
+//   , .      decrement/increment the fixed address
+//   [ ]      decrement/increment the number of lines (auto uses the next DRW)
+//   O        toggle the preview of the next DRW on the framebuffer
+pub struct SpriteView {
+    // None follows I
+    addr: Option<u16>,
+    // None takes the line count from the next DRW
+    lines: Option<usize>,
+    overlay: bool,
+}
+
+// (x register, y register, lines) of the DRW instruction at PC
+fn next_draw(cpu: &Cpu) -> Option<(usize, usize, usize)> {
+    match decoder::decode(cpu.get_next_n_instr(1)[0]) {
+        Some(Instruction::DisplaySpriteVxVyNibble(vx, vy, n)) => Some((vx, vy, n as usize)),
+        _ => None,
+    }
+}
+
+impl SpriteView {
+    pub fn new() -> SpriteView {
+        SpriteView {
+            addr: None,
+            lines: None,
+            overlay: true,
+        }
+    }
+
+    // returns true if the view changed and must be redrawn
+    pub fn handle_key(&mut self, key: Key, cpu: &Cpu) -> bool {
+        match key {
+            Key::I => {
+                self.addr = match self.addr {
+                    Some(_) => None,
+                    None => Some(cpu.get_registers().I),
+                }
+            }
+            Key::Comma => self.addr = self.addr.map(|a| a.saturating_sub(1)),
+            Key::Period => self.addr = self.addr.map(|a| std::cmp::min(a + 1, 0xfff)),
+            Key::LeftBracket => {
+                self.lines = match self.lines {
+                    None => Some(MAX_LINES),
+                    Some(1) => None,
+                    Some(n) => Some(n - 1),
+                }
+            }
+            Key::RightBracket => {
+                self.lines = match self.lines {
+                    None => Some(1),
+                    Some(MAX_LINES) => None,
+                    Some(n) => Some(n + 1),
+                }
+            }
+            Key::O => self.overlay = !self.overlay,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn draw(&self, fb: &mut PixelVec, cpu: &Cpu) {
+        let addr = self.addr.unwrap_or(cpu.get_registers().I);
+        let lines = match self.lines {
+            Some(n) => n,
+            None => next_draw(cpu).map(|(_, _, n)| n).unwrap_or(MAX_LINES),
+        };
+
+        pixel_engine::draw_rect(
+            fb,
+            X,
+            0,
+            ui::BLACK,
+            ui::WINDOW_WIDTH - X,
+            (MAX_LINES + 2) * SCALE + 2 * ui::LINE_H,
+        );
+        let source = if self.addr.is_some() { "ADDR" } else { "I" };
+        pixel_engine::draw_str(fb, X, 0, ui::WHITE, &format!("SPRITE {}", source));
+        pixel_engine::draw_str(
+            fb,
+            X,
+            ui::LINE_H,
+            ui::WHITE,
+            &format!("{:04X} N={:X}", addr, lines),
+        );
+
+        let mem = cpu.get_mem();
+        for line in 0..lines {
+            let byte = mem[(addr as usize + line) % mem.len()];
+            for col in 0..8 {
+                let color = if gpu::sprite_pixel(byte, col) {
+                    PIXEL_ON
+                } else {
+                    PIXEL_OFF
+                };
+                pixel_engine::draw_rect(
+                    fb,
+                    X + col * SCALE,
+                    2 * ui::LINE_H + line * SCALE,
+                    color,
+                    SCALE - 1,
+                    SCALE - 1,
+                );
+            }
+        }
+    }
+
+    // preview the sprite of the next DRW at its target position, must be
+    // drawn after the framebuffer, which is at `scale` with its top left
+    // corner at `x0`, `y0`
+    pub fn draw_overlay(&self, fb: &mut PixelVec, cpu: &Cpu, scale: usize, x0: usize, y0: usize) {
+        let (vx, vy, lines) = match next_draw(cpu) {
+            Some(draw) if self.overlay => draw,
+            _ => return,
+        };
+
+        let regs = cpu.get_registers();
+        let (x, y) = (regs.V[vx] as usize, regs.V[vy] as usize);
+        let mem = cpu.get_mem();
+        for line in 0..lines {
+            let byte = mem[(regs.I as usize + line) % mem.len()];
+            for col in (0..8).filter(|&col| gpu::sprite_pixel(byte, col)) {
+                let idx = match cpu.get_gpu().sprite_pixel_index(x, y, line, col) {
+                    Some(idx) => idx,
+                    None => continue,
+                };
+                let color = if cpu.get_gpu().get_pixel(idx % gpu::WIDTH, idx / gpu::WIDTH) {
+                    OVERLAY_OFF
+                } else {
+                    OVERLAY_ON
+                };
+                pixel_engine::draw_rect(
+                    fb,
+                    x0 + (idx % gpu::WIDTH) * scale,
+                    y0 + (idx / gpu::WIDTH) * scale,
+                    color,
+                    scale,
+                    scale,
+                );
+            }
+        }
+    }
+}
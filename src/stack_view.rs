@@ -0,0 +1,50 @@
+use super::callstack;
+use super::cpu::Cpu;
+use super::symbols::Symbols;
+use super::ui;
+
+use pixel_engine::PixelVec;
+
+const X: usize = ui::PANEL_X + 210;
+// below the sprite panel
+const Y: usize = 172;
+const RED: u32 = 0x00ff4040;
+
+// call stack, innermost frame first, as `caller>callee`
+pub fn draw(fb: &mut PixelVec, cpu: &Cpu, symbols: &Symbols) {
+    pixel_engine::draw_rect(
+        fb,
+        X,
+        Y,
+        ui::BLACK,
+        ui::WINDOW_WIDTH - X,
+        ui::WINDOW_HEIGHT - Y,
+    );
+
+    let frames = callstack::frames(cpu.get_stack(), cpu.get_mem());
+    pixel_engine::draw_str(fb, X, Y, ui::WHITE, &format!("CALL STACK {}", frames.len()));
+
+    let mut y = Y + ui::LINE_H;
+    for frame in frames.iter().rev() {
+        if y + ui::LINE_H > ui::WINDOW_HEIGHT {
+            break;
+        }
+        let callee = match frame.callee {
+            Some(addr) => symbols.name_or_addr(addr),
+            None => "?".to_string(),
+        };
+        let color = if frame.warning.is_some() {
+            RED
+        } else {
+            ui::WHITE
+        };
+        let caller = symbols.format_addr(frame.caller);
+        pixel_engine::draw_str(fb, X, y, color, &format!("{}>{}", caller, callee));
+        y += ui::LINE_H;
+
+        if let Some(warning) = frame.warning {
+            pixel_engine::draw_str(fb, X, y, RED, &format!(" !{}", warning));
+            y += ui::LINE_H;
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// addresses further away from the preceding symbol are printed as plain hex
+const MAX_OFFSET: u16 = 0x100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Code,
+    Data,
+}
+
+// symbol map loaded alongside a ROM
+//
+// text format, one symbol per line, `#` and `;` start a comment, the kind
+// defaults to code:
+//   0x200 main
+//   2a4   draw_player
+//   0x300 score data
+//
+// labels exported by Octo are accepted as well, a `:monitor` on a label
+// marks it as data:
+//   :const draw_player 0x2a4
+//   :monitor score 1
+#[derive(Clone)]
+pub struct Symbols {
+    by_addr: BTreeMap<u16, String>,
+    by_name: HashMap<String, u16>,
+    data: BTreeSet<u16>,
+}
+
+fn parse_addr(s: &str) -> Option<u16> {
+    let s = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(s, 16).ok()
+}
+
+impl Default for Symbols {
+    fn default() -> Symbols {
+        Symbols::new()
+    }
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols {
+            by_addr: BTreeMap::new(),
+            by_name: HashMap::new(),
+            data: BTreeSet::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Symbols, String> {
+        let mut text = String::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Failed to read {}: {}", path.as_ref().display(), e))?;
+        Symbols::parse(&text).map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::new();
+        // Octo monitors may come before the label they refer to
+        let mut monitors = Vec::new();
+
+        for (nr, line) in text.lines().enumerate() {
+            let line = match line.find(&['#', ';'][..]) {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let invalid = |addr: &str| format!("line {}: invalid address '{}'", nr + 1, addr);
+
+            match fields.as_slice() {
+                [] => {}
+                [":const", name, addr] => {
+                    let addr = parse_addr(addr).ok_or_else(|| invalid(addr))?;
+                    symbols.insert(addr, name, Kind::Code);
+                }
+                [":monitor", name, ..] => monitors.push(name.to_string()),
+                [addr, name] | [addr, name, "code"] => {
+                    let addr = parse_addr(addr).ok_or_else(|| invalid(addr))?;
+                    symbols.insert(addr, name, Kind::Code);
+                }
+                [addr, name, "data"] => {
+                    let addr = parse_addr(addr).ok_or_else(|| invalid(addr))?;
+                    symbols.insert(addr, name, Kind::Data);
+                }
+                _ => {
+                    return Err(format!(
+                        "line {}: expected '<addr> <name> [code|data]'",
+                        nr + 1
+                    ))
+                }
+            }
+        }
+
+        for name in monitors {
+            if let Some(addr) = symbols.lookup(&name) {
+                symbols.data.insert(addr);
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn insert(&mut self, addr: u16, name: &str, kind: Kind) {
+        self.by_addr.insert(addr, name.to_string());
+        self.by_name.insert(name.to_string(), addr);
+        if kind == Kind::Data {
+            self.data.insert(addr);
+        }
+    }
+
+    pub fn get(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(|n| n.as_str())
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).cloned()
+    }
+
+    pub fn is_data(&self, addr: u16) -> bool {
+        self.data.contains(&addr)
+    }
+
+    // symbol name of `addr` or its hex value
+    pub fn name_or_addr(&self, addr: u16) -> String {
+        match self.get(addr) {
+            Some(name) => name.to_string(),
+            None => format!("{:04x}", addr),
+        }
+    }
+
+    // `name` or `name+off` relative to the preceding symbol
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        match self.by_addr.range(..=addr).next_back() {
+            Some((&a, name)) if a == addr => Some(name.clone()),
+            Some((&a, name)) if addr - a < MAX_OFFSET => Some(format!("{}+{:x}", name, addr - a)),
+            _ => None,
+        }
+    }
+
+    // like `describe` but falls back to the hex value
+    pub fn format_addr(&self, addr: u16) -> String {
+        self.describe(addr)
+            .unwrap_or_else(|| format!("{:04x}", addr))
+    }
+
+    // resolve a location given as `name`, `name+off` or hex address
+    pub fn resolve(&self, loc: &str) -> Result<u16, String> {
+        let (base, off) = match loc.find('+') {
+            Some(plus) => (&loc[..plus], Some(&loc[plus + 1..])),
+            None => (loc, None),
+        };
+        let base = match self.lookup(base) {
+            Some(addr) => addr,
+            None => parse_addr(base).ok_or(format!("unknown symbol or address '{}'", base))?,
+        };
+        let off = match off {
+            Some(off) => parse_addr(off).ok_or(format!("invalid offset '{}'", off))?,
+            None => 0,
+        };
+        Ok(base.wrapping_add(off) & 0x0fff)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let symbols =
+            Symbols::parse("# comment\n0x200 main\n\n2a4 draw_player ; sprite\n").unwrap();
+        assert_eq!(symbols.get(0x200), Some("main"));
+        assert_eq!(symbols.name_or_addr(0x2a4), "draw_player");
+        assert_eq!(symbols.name_or_addr(0x2a6), "02a6");
+
+        assert!(Symbols::parse("zz main").is_err());
+        assert!(Symbols::parse("0x200").is_err());
+        assert!(Symbols::parse("0x200 main stack").is_err());
+    }
+
+    #[test]
+    fn parse_octo() {
+        let symbols =
+            Symbols::parse(":monitor score 1\n:const main 0x200\n:const score 0x3a0\n").unwrap();
+        assert_eq!(symbols.lookup("main"), Some(0x200));
+        assert!(!symbols.is_data(0x200));
+        assert!(symbols.is_data(0x3a0));
+    }
+
+    #[test]
+    fn format_and_resolve() {
+        let symbols = Symbols::parse("0x200 main\n0x300 score data\n").unwrap();
+        assert_eq!(symbols.format_addr(0x200), "main");
+        assert_eq!(symbols.format_addr(0x204), "main+4");
+        assert_eq!(symbols.format_addr(0x1fe), "01fe");
+        assert_eq!(symbols.format_addr(0x400), "0400");
+
+        assert_eq!(symbols.resolve("score"), Ok(0x300));
+        assert_eq!(symbols.resolve("main+a"), Ok(0x20a));
+        assert_eq!(symbols.resolve("0x2a4"), Ok(0x2a4));
+        assert!(symbols.resolve("draw").is_err());
+    }
+}
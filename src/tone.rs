@@ -0,0 +1,15 @@
+// square wave beeper of the recordings and the libretro core
+
+pub const SAMPLE_RATE: u32 = 48000;
+pub const TONE_HZ: u64 = 440;
+pub const AMPLITUDE: i16 = 8000;
+
+// sample `n` of the timeline, the tone while the sound timer is active
+pub fn sample(n: u64, sound: bool) -> i16 {
+    let high = (n * TONE_HZ * 2 / SAMPLE_RATE as u64) & 1 == 0;
+    match (sound, high) {
+        (false, _) => 0,
+        (true, true) => AMPLITUDE,
+        (true, false) => -AMPLITUDE,
+    }
+}
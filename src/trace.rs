@@ -0,0 +1,361 @@
+use super::cpu::Registers;
+use super::decoder;
+use super::symbols::Symbols;
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+// default text line, placeholders are documented in `format_line`
+pub const DEFAULT_FORMAT: &str = "{cycle} {pc}: {opcode}  {disasm:18} {changes}";
+
+// binary trace layout:
+//   header: MAGIC | VERSION
+//   record: cycle(u64) PC(u16) opcode(u16) V0..VF(16 x u8) I(u16) DT(u8) ST(u8) SP(u8)
+// all multi byte values are stored little endian, registers are the state
+// *after* the instruction executed
+pub const MAGIC: &[u8; 4] = b"C8TR";
+pub const VERSION: u8 = 1;
+pub const RECORD_SIZE: usize = 8 + 2 + 2 + 16 + 2 + 1 + 1 + 1;
+
+pub enum Format {
+    Text(String),
+    Binary,
+}
+
+pub struct Tracer {
+    out: BufWriter<File>,
+    format: Format,
+    symbols: Symbols,
+}
+
+impl Tracer {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        format: Format,
+        symbols: Symbols,
+    ) -> Result<Tracer, String> {
+        let file = File::create(&path).map_err(|e| {
+            format!(
+                "Failed to create trace file {}: {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+
+        let mut tracer = Tracer {
+            out: BufWriter::new(file),
+            format,
+            symbols,
+        };
+        if let Format::Binary = tracer.format {
+            tracer
+                .out
+                .write_all(MAGIC)
+                .and_then(|_| tracer.out.write_all(&[VERSION]))
+                .map_err(|e| format!("Failed to write trace header: {}", e))?;
+        }
+        Ok(tracer)
+    }
+
+    // log one executed instruction, `before`/`after` is the register state
+    // around the execution of `opcode`
+    pub fn log(
+        &mut self,
+        cycle: u64,
+        opcode: u16,
+        before: &Registers,
+        after: &Registers,
+    ) -> std::io::Result<()> {
+        match self.format {
+            Format::Text(ref fmt) => {
+                let line = format_line(fmt, cycle, opcode, before, after, &self.symbols);
+                writeln!(self.out, "{}", line)
+            }
+            Format::Binary => {
+                let record = Record {
+                    cycle,
+                    pc: before.PC,
+                    opcode,
+                    regs: *after,
+                };
+                self.out.write_all(&record.encode())
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// one entry of a binary trace
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Record {
+    pub cycle: u64,
+    // address of the executed instruction
+    pub pc: u16,
+    pub opcode: u16,
+    // register state after execution
+    pub regs: Registers,
+}
+
+impl Record {
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut buf = [0u8; RECORD_SIZE];
+        buf[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        buf[8..10].copy_from_slice(&self.pc.to_le_bytes());
+        buf[10..12].copy_from_slice(&self.opcode.to_le_bytes());
+        buf[12..28].copy_from_slice(&self.regs.V);
+        buf[28..30].copy_from_slice(&self.regs.I.to_le_bytes());
+        buf[30] = self.regs.DT;
+        buf[31] = self.regs.ST;
+        buf[32] = self.regs.SP as u8;
+        buf
+    }
+
+    pub fn decode(buf: &[u8; RECORD_SIZE], pc_after: u16) -> Record {
+        let mut v = [0u8; 16];
+        v.copy_from_slice(&buf[12..28]);
+        Record {
+            cycle: u64::from_le_bytes([
+                buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7],
+            ]),
+            pc: u16::from_le_bytes([buf[8], buf[9]]),
+            opcode: u16::from_le_bytes([buf[10], buf[11]]),
+            regs: Registers {
+                V: v,
+                I: u16::from_le_bytes([buf[28], buf[29]]),
+                DT: buf[30],
+                ST: buf[31],
+                PC: pc_after,
+                SP: buf[32] as usize,
+            },
+        }
+    }
+}
+
+// check for the binary trace header
+pub fn is_binary<P: AsRef<Path>>(path: P) -> bool {
+    let mut magic = [0u8; 4];
+    match File::open(path).and_then(|mut f| f.read_exact(&mut magic)) {
+        Ok(_) => &magic == MAGIC,
+        Err(_) => false,
+    }
+}
+
+// read all records of a binary trace file
+//
+// the PC after an instruction is not stored explicitly, it is the PC of the
+// following record (the last record keeps its own PC)
+pub fn read_binary<P: AsRef<Path>>(path: P) -> Result<Vec<Record>, String> {
+    let mut data = Vec::new();
+    File::open(&path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| format!("Failed to read {}: {}", path.as_ref().display(), e))?;
+
+    if data.len() < MAGIC.len() + 1 || &data[0..4] != MAGIC {
+        return Err(format!("{} is no binary trace", path.as_ref().display()));
+    }
+    if data[4] != VERSION {
+        return Err(format!("Unsupported trace version {}", data[4]));
+    }
+
+    let chunks: Vec<&[u8]> = data[5..].chunks_exact(RECORD_SIZE).collect();
+    let mut records = Vec::with_capacity(chunks.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut buf = [0u8; RECORD_SIZE];
+        buf.copy_from_slice(chunk);
+        let pc_after = match chunks.get(i + 1) {
+            Some(next) => u16::from_le_bytes([next[8], next[9]]),
+            None => u16::from_le_bytes([buf[8], buf[9]]),
+        };
+        records.push(Record::decode(&buf, pc_after));
+    }
+    Ok(records)
+}
+
+// list of registers which differ between `before` and `after`, formatted as
+// `NAME=value` separated by spaces
+pub fn format_changes(before: &Registers, after: &Registers) -> String {
+    let mut changes = Vec::new();
+    for i in 0..16 {
+        if before.V[i] != after.V[i] {
+            changes.push(format!("V{:X}={:02x}", i, after.V[i]));
+        }
+    }
+    if before.I != after.I {
+        changes.push(format!("I={:04x}", after.I));
+    }
+    if before.DT != after.DT {
+        changes.push(format!("DT={:02x}", after.DT));
+    }
+    if before.ST != after.ST {
+        changes.push(format!("ST={:02x}", after.ST));
+    }
+    if before.SP != after.SP {
+        changes.push(format!("SP={}", after.SP));
+    }
+    changes.join(" ")
+}
+
+// expand the placeholders in `fmt` for one executed instruction
+//
+// supported placeholders, an optional `:N` pads the value to N characters:
+//   {cycle}     number of the executed instruction
+//   {pc}        address of the instruction
+//   {sym}       address of the instruction as `name+off`
+//   {opcode}    raw 16bit opcode
+//   {disasm}    disassembly of the opcode, using symbol names
+//   {changes}   registers changed by the instruction
+//   {V0}..{VF}  {I} {DT} {ST} {SP}  register values after execution
+pub fn format_line(
+    fmt: &str,
+    cycle: u64,
+    opcode: u16,
+    before: &Registers,
+    after: &Registers,
+    symbols: &Symbols,
+) -> String {
+    let mut line = String::new();
+    let mut rest = fmt;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        line.push_str(&rest[..start]);
+
+        let spec = &rest[start + 1..end];
+        let (name, width) = match spec.find(':') {
+            Some(colon) => (&spec[..colon], spec[colon + 1..].parse::<usize>().ok()),
+            None => (spec, None),
+        };
+
+        let value = match name {
+            "cycle" => Some(format!("{}", cycle)),
+            "pc" => Some(format!("{:04x}", before.PC)),
+            "sym" => Some(symbols.format_addr(before.PC)),
+            "opcode" => Some(format!("{:04x}", opcode)),
+            "disasm" => Some(decoder::disassemble_with(opcode, |a| {
+                symbols.format_addr(a)
+            })),
+            "changes" => Some(format_changes(before, after)),
+            "I" => Some(format!("{:04x}", after.I)),
+            "DT" => Some(format!("{:02x}", after.DT)),
+            "ST" => Some(format!("{:02x}", after.ST)),
+            "SP" => Some(format!("{}", after.SP)),
+            _ => register_index(name).map(|i| format!("{:02x}", after.V[i])),
+        };
+
+        match value {
+            Some(value) => line.push_str(&format!("{:<1$}", value, width.unwrap_or(0))),
+            // keep unknown placeholders verbatim
+            None => line.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    line.push_str(rest);
+    line
+}
+
+// `V0` .. `VF` -> register index
+fn register_index(name: &str) -> Option<usize> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(c), None) | (Some('v'), Some(c), None) => {
+            c.to_digit(16).map(|i| i as usize)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn regs() -> Registers {
+        Registers {
+            V: [0; 16],
+            I: 0,
+            DT: 0,
+            ST: 0,
+            PC: 0x200,
+            SP: 0,
+        }
+    }
+
+    #[test]
+    fn text_line() {
+        let before = regs();
+        let mut after = regs();
+        after.V[0xa] = 0x02;
+        after.PC = 0x202;
+
+        assert_eq!(
+            format_line(DEFAULT_FORMAT, 7, 0x6a02, &before, &after, &Symbols::new()),
+            "7 0200: 6a02  LD Va, 02          VA=02"
+        );
+        assert_eq!(
+            format_line(
+                "PC={pc} VA={VA} I={I} {unknown}",
+                7,
+                0x6a02,
+                &before,
+                &after,
+                &Symbols::new()
+            ),
+            "PC=0200 VA=02 I=0000 {unknown}"
+        );
+        // an unclosed placeholder is kept verbatim
+        assert_eq!(
+            format_line(
+                "PC={pc} VA={VA",
+                7,
+                0x6a02,
+                &before,
+                &after,
+                &Symbols::new()
+            ),
+            "PC=0200 VA={VA"
+        );
+
+        let symbols = Symbols::parse("0x1fc main\n0x2a4 draw\n").unwrap();
+        assert_eq!(
+            format_line("{sym} {disasm}", 7, 0x22a4, &before, &after, &symbols),
+            "main+4 CALL draw"
+        );
+    }
+
+    #[test]
+    fn changes() {
+        let before = regs();
+        let mut after = regs();
+        assert_eq!(format_changes(&before, &after), "");
+
+        after.V[0xf] = 1;
+        after.I = 0x123;
+        after.SP = 1;
+        assert_eq!(format_changes(&before, &after), "VF=01 I=0123 SP=1");
+    }
+
+    #[test]
+    fn record_roundtrip() {
+        let mut after = regs();
+        after.V[3] = 0x33;
+        after.I = 0xabc;
+        after.DT = 4;
+        after.ST = 5;
+        after.SP = 2;
+        after.PC = 0x204;
+
+        let record = Record {
+            cycle: 0x1_0000_0001,
+            pc: 0x202,
+            opcode: 0x2204,
+            regs: after,
+        };
+        assert_eq!(Record::decode(&record.encode(), 0x204), record);
+    }
+}
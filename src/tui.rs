@@ -0,0 +1,334 @@
+use super::disasm_line;
+use super::display::Palette;
+use super::gpu;
+use super::host::{AudioSink, Event, InputSource, Session, VideoSink};
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+// terminals report no key releases, a key is held for this long after each
+// press or auto repeat
+const HOLD: Duration = Duration::from_millis(200);
+const DISASM_LINES: usize = 10;
+const DISASM_WIDTH: usize = 28;
+
+// raw terminal mode while alive, the previous settings are restored on drop
+// (also when unwinding from a panic)
+struct RawTerminal {
+    saved: String,
+}
+
+impl RawTerminal {
+    fn enter() -> Result<RawTerminal, String> {
+        let out = Command::new("stty")
+            .arg("-g")
+            .stdin(Stdio::inherit())
+            .output()
+            .map_err(|e| format!("Failed to run stty: {}", e))?;
+        if !out.status.success() {
+            return Err("stdin is no terminal".to_string());
+        }
+        let saved = String::from_utf8_lossy(&out.stdout).trim().to_string();
+        stty(&["raw", "-echo"])?;
+        // alternate screen, hide the cursor
+        print!("\x1b[?1049h\x1b[?25l");
+        Ok(RawTerminal { saved })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = std::io::stdout().flush();
+        let _ = stty(&[self.saved.as_str()]);
+    }
+}
+
+fn stty(args: &[&str]) -> Result<(), String> {
+    match Command::new("stty").args(args).status() {
+        Ok(status) if status.success() => Ok(()),
+        _ => Err(format!("Failed to run stty {}", args.join(" "))),
+    }
+}
+
+// chip8 key of a terminal character, same layout as the window frontend
+fn keypad(c: u8) -> Option<u8> {
+    let key = match c.to_ascii_lowercase() {
+        b'x' => 0x0,
+        b'1' => 0x1,
+        b'2' => 0x2,
+        b'3' => 0x3,
+        b'q' => 0x4,
+        b'w' => 0x5,
+        b'e' => 0x6,
+        b'a' => 0x7,
+        b's' => 0x8,
+        b'd' => 0x9,
+        b'z' => 0xA,
+        b'c' => 0xB,
+        b'4' => 0xC,
+        b'r' => 0xD,
+        b'f' => 0xE,
+        b'v' => 0xF,
+        _ => return None,
+    };
+    Some(key)
+}
+
+#[derive(Debug, PartialEq)]
+enum Input {
+    Key(u8),
+    Run,
+    Break,
+    Step,
+    Quit,
+}
+
+// decode one read from the terminal, escape sequences (arrows, function
+// keys) are skipped, a lone escape quits
+fn decode(bytes: &[u8]) -> Vec<Input> {
+    if bytes == [0x1b] {
+        return vec![Input::Quit];
+    }
+    let mut input = Vec::new();
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        match b {
+            0x1b => {
+                if iter.next() == Some(&b'[') {
+                    // parameters up to the final byte
+                    for &c in iter.by_ref() {
+                        if (0x40..=0x7e).contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            // ctrl-c
+            0x03 => input.push(Input::Quit),
+            b'g' | b'G' => input.push(Input::Run),
+            b'b' | b'B' => input.push(Input::Break),
+            b' ' => input.push(Input::Step),
+            _ => {
+                if let Some(key) = keypad(b) {
+                    input.push(Input::Key(key));
+                }
+            }
+        }
+    }
+    input
+}
+
+fn set_color(out: &mut String, layer: u8, color: u32) {
+    out.push_str(&format!(
+        "\x1b[{};2;{};{};{}m",
+        layer,
+        color >> 16 & 0xff,
+        color >> 8 & 0xff,
+        color & 0xff
+    ));
+}
+
+// framebuffer as rows of upper half blocks, the foreground color is the top
+// pixel and the background color the bottom pixel of a cell
+fn render_fb(out: &mut String, levels: &[f32], palette: Palette) {
+    for row in 0..gpu::HEIGHT / 2 {
+        let mut colors = None;
+        for x in 0..gpu::WIDTH {
+            let top = palette.mix(levels[2 * row * gpu::WIDTH + x]);
+            let bottom = palette.mix(levels[(2 * row + 1) * gpu::WIDTH + x]);
+            if colors != Some((top, bottom)) {
+                set_color(out, 38, top);
+                set_color(out, 48, bottom);
+                colors = Some((top, bottom));
+            }
+            out.push('\u{2580}');
+        }
+        out.push_str("\x1b[0m\x1b[K\r\n");
+    }
+}
+
+fn render(session: &Session, status: &str) -> String {
+    let (cpu, symbols) = (&session.cpu, &session.symbols);
+    // home, the screen is overwritten in place
+    let mut out = String::from("\x1b[H");
+    render_fb(
+        &mut out,
+        &session.filter.apply(session.frame()),
+        session.palette,
+    );
+    out.push_str(&format!(
+        "\x1b[K\r\n{:?}: 'g' run, 'b' break, 'space' step, 'esc' quit\x1b[K\r\n{}\x1b[K\r\n",
+        session.mode, status
+    ));
+
+    let pc = cpu.get_registers().PC;
+    let disasm: Vec<String> = cpu
+        .get_next_n_instr(DISASM_LINES)
+        .iter()
+        .enumerate()
+        .map(|(c, &instr)| {
+            let addr = pc.wrapping_add(2 * c as u16);
+            format!(
+                "{:04X} {}",
+                addr,
+                disasm_line(addr, instr, &session.tools, symbols)
+            )
+        })
+        .collect();
+    let state = cpu.get_state().format(symbols);
+    for i in 0..std::cmp::max(disasm.len(), state.len()) {
+        let left = disasm.get(i).map(|s| s.as_str()).unwrap_or("");
+        let right = state.get(i).map(|s| s.as_str()).unwrap_or("");
+        out.push_str(&format!(
+            "{:width$.width$} {}\x1b[K\r\n",
+            left,
+            right,
+            width = DISASM_WIDTH
+        ));
+    }
+    // clear the rest of a previously longer stack
+    out.push_str("\x1b[J");
+    out
+}
+
+// frontend in the terminal, the framebuffer is drawn with unicode half blocks
+// and the sound timer rings the terminal bell
+pub struct TuiFrontend {
+    // restores the terminal when the frontend is dropped
+    _terminal: RawTerminal,
+    input: Receiver<Vec<u8>>,
+    // last press of every chip8 key
+    pressed: [Option<Instant>; 16],
+    screen: String,
+    // last message, shown below the framebuffer
+    status: String,
+    sound: bool,
+}
+
+impl TuiFrontend {
+    pub fn new() -> Result<TuiFrontend, String> {
+        let terminal = RawTerminal::enter()?;
+        let (tx, input) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let stdin = std::io::stdin();
+            while let Ok(n) = stdin.lock().read(&mut buf) {
+                if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(TuiFrontend {
+            _terminal: terminal,
+            input,
+            pressed: [None; 16],
+            screen: String::new(),
+            status: String::new(),
+            sound: false,
+        })
+    }
+}
+
+impl VideoSink for TuiFrontend {
+    fn present(&mut self, session: &Session, changed: bool) -> Result<(), String> {
+        if !changed && !self.screen.is_empty() {
+            return Ok(());
+        }
+        let next = render(session, &self.status);
+        if next != self.screen {
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            out.write_all(next.as_bytes())
+                .and_then(|_| out.flush())
+                .map_err(|e| format!("Failed to write to the terminal: {}", e))?;
+            self.screen = next;
+        }
+        Ok(())
+    }
+
+    fn message(&mut self, msg: &str) {
+        self.status = msg.to_string();
+        // redraw with the new status line
+        self.screen.clear();
+    }
+}
+
+impl InputSource for TuiFrontend {
+    fn poll(&mut self, _: &mut Session) -> Vec<Event> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+        while let Ok(bytes) = self.input.try_recv() {
+            for input in decode(&bytes) {
+                match input {
+                    Input::Key(key) => self.pressed[key as usize] = Some(now),
+                    Input::Run => events.push(Event::Run),
+                    Input::Break => events.push(Event::Break),
+                    Input::Step => events.push(Event::Step),
+                    Input::Quit => events.push(Event::Quit),
+                }
+            }
+        }
+        events
+    }
+
+    fn keys(&mut self) -> Vec<u8> {
+        let now = Instant::now();
+        let pressed = &self.pressed;
+        (0..16u8)
+            .filter(|&k| matches!(pressed[k as usize], Some(t) if now - t < HOLD))
+            .collect()
+    }
+}
+
+impl AudioSink for TuiFrontend {
+    fn frame(&mut self, sound: bool) {
+        if sound && !self.sound {
+            print!("\x07");
+        }
+        self.sound = sound;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_input() {
+        assert_eq!(decode(b"\x1b"), vec![Input::Quit]);
+        assert_eq!(decode(b"\x03"), vec![Input::Quit]);
+        // an arrow key between keypad keys
+        assert_eq!(
+            decode(b"1\x1b[Av G"),
+            vec![Input::Key(1), Input::Key(0xF), Input::Step, Input::Run]
+        );
+        assert_eq!(decode(b"\x1b[15~b"), vec![Input::Break]);
+        assert_eq!(decode(b"hX"), vec![Input::Key(0)]);
+    }
+
+    #[test]
+    fn half_blocks() {
+        let mut levels = vec![0.0; gpu::WIDTH * gpu::HEIGHT];
+        // top pixel of the first cell, bottom pixel of the second cell
+        levels[0] = 1.0;
+        levels[gpu::WIDTH + 1] = 1.0;
+        let palette = Palette {
+            fg: 0xff0000,
+            bg: 0,
+        };
+        let mut out = String::new();
+        render_fb(&mut out, &levels, palette);
+
+        let rows: Vec<&str> = out.split("\r\n").collect();
+        assert_eq!(rows.len(), gpu::HEIGHT / 2 + 1);
+        assert!(rows[0].starts_with(
+            "\x1b[38;2;255;0;0m\x1b[48;2;0;0;0m\u{2580}\x1b[38;2;0;0;0m\x1b[48;2;255;0;0m\u{2580}"
+        ));
+        assert_eq!(rows[1].matches('\u{2580}').count(), gpu::WIDTH);
+        // a row of equal cells sets its colors once
+        assert_eq!(rows[1].matches("\x1b[38").count(), 1);
+    }
+}
@@ -0,0 +1,20 @@
+use super::gpu;
+
+// window layout, the framebuffer is drawn in the top left corner, the debug
+// panel right of it and the memory panel below it
+pub const WINDOW_WIDTH: usize = 640;
+pub const WINDOW_HEIGHT: usize = 480;
+pub const FB_SCALE: usize = 4;
+pub const PANEL_X: usize = FB_SCALE * gpu::WIDTH + 22;
+pub const MEM_PANEL_Y: usize = FB_SCALE * gpu::HEIGHT + 12;
+
+// glyph advance and line height of the pixel_engine font
+pub const CHAR_W: usize = 6;
+pub const LINE_H: usize = 12;
+
+pub const BLACK: u32 = 0x00000000;
+pub const WHITE: u32 = 0x00ffffff;
+pub const GREY: u32 = 0x00a0a0a0;
+pub const YELLOW: u32 = 0x00ffff00;
+pub const GREEN: u32 = 0x0000ff00;
+pub const CYAN: u32 = 0x0000ffff;
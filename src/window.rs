@@ -0,0 +1,333 @@
+use super::args::Args;
+use super::cpu::Cpu;
+use super::debugger;
+use super::disasm_line;
+use super::display;
+use super::gpu;
+use super::host::{AudioSink, Event, InputSource, RunMode, Session, VideoSink};
+use super::mem_view::MemView;
+use super::postfx::PostFx;
+use super::reg_view::RegView;
+use super::sprite_view::SpriteView;
+use super::stack_view;
+use super::ui;
+
+use minifb::{Key, Window, WindowOptions};
+use pixel_engine::{PixelBuffer, PixelVec};
+
+use std::sync::mpsc::Receiver;
+
+fn remap_keys(keys: Vec<Key>) -> Vec<u8> {
+    keys.iter()
+        .filter_map(|key| match key {
+            Key::X => Some(0x0),
+            Key::Key1 => Some(0x1),
+            Key::Key2 => Some(0x2),
+            Key::Key3 => Some(0x3),
+            Key::Q => Some(0x4),
+            Key::W => Some(0x5),
+            Key::E => Some(0x6),
+            Key::A => Some(0x7),
+            Key::S => Some(0x8),
+            Key::D => Some(0x9),
+            Key::Z => Some(0xA),
+            Key::C => Some(0xB),
+            Key::Key4 => Some(0xC),
+            Key::R => Some(0xD),
+            Key::F => Some(0xE),
+            Key::V => Some(0xF),
+            _ => None,
+        })
+        .collect::<Vec<u8>>()
+}
+
+// keys which step the program or move a view repeat while held
+fn repeats(key: Key) -> bool {
+    matches!(
+        key,
+        Key::Space
+            | Key::Backspace
+            | Key::F6
+            | Key::F7
+            | Key::F10
+            | Key::F11
+            | Key::Comma
+            | Key::Period
+    )
+}
+
+// white for cold, red for hot instructions
+fn heat_color(heat: f32) -> u32 {
+    let cold = (255.0 * (1.0 - heat)) as u32;
+    0x00ff0000 | (cold << 8) | cold
+}
+
+// minifb window with the game and the debug panel, commands are read from
+// the terminal
+pub struct WindowFrontend {
+    window: Window,
+    fb: PixelVec,
+    mem_view: MemView,
+    sprite_view: SpriteView,
+    reg_view: RegView,
+    show_panel: bool,
+    mouse_down: bool,
+    window_size: (usize, usize),
+    console: Receiver<String>,
+    postfx: PostFx,
+    draw_dbg: bool,
+    draw_fb: bool,
+}
+
+impl WindowFrontend {
+    pub fn new(args: &Args) -> Result<WindowFrontend, String> {
+        let (width, height) = match args.window {
+            Some(size) => size,
+            None if args.panel => (ui::WINDOW_WIDTH, ui::WINDOW_HEIGHT),
+            None => (gpu::WIDTH * args.scale, gpu::HEIGHT * args.scale),
+        };
+        let window = Window::new(
+            "CHIP-8 - ESC to exit",
+            width,
+            height,
+            WindowOptions {
+                borderless: false,
+                title: true,
+                resize: true,
+                scale: minifb::Scale::X1,
+            },
+        )
+        .map_err(|e| format!("Failed to open window: {}", e))?;
+
+        println!("[+] Change RunMode with 'G' | 'B'");
+        println!("    'G': FreeRunning");
+        println!("    'B': Stepping");
+        println!("[+] In Stepping mode use 'SPACE' to step one instruction");
+        println!("[+] Memory panel: 'M' follow I/PC, 'PageUp'/'PageDown' scroll");
+        println!("    'ENTER' edit memory in Stepping mode");
+        println!("[+] Sprite panel: 'I' follow I/fixed address, ',' '.' move address");
+        println!("    '[' ']' number of lines, 'O' preview next DRW");
+        println!("[+] Click a register or memory byte to show its last writer");
+        println!("[+] 'F10' step over, 'F11' step out, 'F4' run to memory editor cursor");
+        println!("    'F6' run one frame, 'F7' run to next DRW");
+        println!("    'BACKSPACE' step back one instruction in Stepping mode");
+        println!("[+] 'TAB' show/hide the debug panel");
+        println!("[+] 'F8' switch the display filter, '-' '=' change its strength");
+        println!("[+] 'F12' save a screenshot, 'F9' start/stop recording a GIF");
+        println!("[+] Type 'help' for debugger commands");
+
+        let window_size = window.get_size();
+        Ok(WindowFrontend {
+            window,
+            fb: PixelVec::new(ui::WINDOW_WIDTH, ui::WINDOW_HEIGHT),
+            mem_view: MemView::new(),
+            sprite_view: SpriteView::new(),
+            reg_view: RegView::new(),
+            show_panel: args.panel,
+            mouse_down: false,
+            window_size,
+            console: debugger::spawn_console(),
+            postfx: args.postfx,
+            draw_dbg: true,
+            draw_fb: true,
+        })
+    }
+
+    fn draw_panel(&mut self, session: &Session) {
+        let (cpu, tools, symbols) = (&session.cpu, &session.tools, &session.symbols);
+        let fb = &mut self.fb;
+        // clear screen
+        pixel_engine::draw_rect(fb, ui::PANEL_X, 0, ui::BLACK, 200, ui::WINDOW_HEIGHT);
+
+        let pc = cpu.get_registers().PC;
+        for (c, &instr) in cpu.get_next_n_instr(10).iter().enumerate() {
+            let addr = pc + 2 * c as u16;
+            let disasm = disasm_line(addr, instr, tools, symbols);
+            let color = match tools.profiler {
+                Some(ref p) => heat_color(p.heat(addr)),
+                None => ui::WHITE,
+            };
+            pixel_engine::draw_str(fb, ui::PANEL_X, c * ui::LINE_H, color, disasm.as_str());
+        }
+
+        self.reg_view.draw(fb, cpu, symbols, &tools.provenance);
+        self.mem_view.draw(fb, cpu);
+        self.sprite_view.draw(fb, cpu);
+        stack_view::draw(fb, cpu, symbols);
+    }
+
+    fn draw_game(&mut self, session: &Session) -> Result<(), String> {
+        let (width, height) = self.window_size;
+        let levels = session.filter.apply(session.frame());
+        let native = display::render(&levels, session.palette, 1);
+        let out = if self.show_panel {
+            // the panels leave room for the game at `ui::FB_SCALE`
+            let game = self.postfx.process(&native, ui::FB_SCALE);
+            for (idx, &pixel) in game.pixels.iter().enumerate() {
+                pixel_engine::draw_pixel_scaled(
+                    &mut self.fb,
+                    idx % game.width,
+                    idx / game.width,
+                    pixel,
+                    pixel_engine::PixelScale::X1,
+                );
+            }
+            if session.mode == RunMode::Stepping {
+                self.sprite_view
+                    .draw_overlay(&mut self.fb, &session.cpu, ui::FB_SCALE, 0, 0);
+            }
+            // the debug layout has a fixed size, a larger window is padded
+            let mut out = display::Image::new(width, height, ui::BLACK);
+            out.blit(self.fb.buffer(), ui::WINDOW_WIDTH, 0, 0);
+            out
+        } else {
+            let (scale, x, y) = display::letterbox(width, height);
+            let game = self.postfx.process(&native, scale);
+            let mut out = display::Image::new(width, height, session.palette.bg);
+            out.blit(&game.pixels, game.width, x, y);
+            out
+        };
+        self.window
+            .update_with_buffer(&out.pixels)
+            .map_err(|e| format!("Failed to update window: {}", e))
+    }
+}
+
+impl VideoSink for WindowFrontend {
+    fn present(&mut self, session: &Session, changed: bool) -> Result<(), String> {
+        if changed {
+            self.draw_dbg = true;
+            self.draw_fb = true;
+        }
+        if self.draw_dbg && self.show_panel {
+            self.draw_panel(session);
+        }
+        if self.draw_fb {
+            self.draw_game(session)?;
+        } else {
+            // keep the window responsive
+            self.window.update();
+        }
+        self.draw_dbg = false;
+        self.draw_fb = false;
+        Ok(())
+    }
+
+    fn step(&mut self, cpu: &Cpu) {
+        self.mem_view.record(cpu);
+    }
+
+    fn message(&mut self, msg: &str) {
+        if msg.starts_with("[-]") {
+            eprintln!("{}", msg);
+        } else {
+            println!("{}", msg);
+        }
+    }
+}
+
+impl InputSource for WindowFrontend {
+    fn poll(&mut self, session: &mut Session) -> Vec<Event> {
+        if !self.window.is_open() || self.window.is_key_down(Key::Escape) {
+            return vec![Event::Quit];
+        }
+
+        let mut events = Vec::new();
+        let stepping = session.mode == RunMode::Stepping;
+        // mode toggles and one-shot actions fire once per key press, only
+        // stepping and the editor and scroll keys repeat while held
+        let pressed = self
+            .window
+            .get_keys_pressed(minifb::KeyRepeat::No)
+            .unwrap_or_default();
+        let keys: Vec<Key> = self
+            .window
+            .get_keys_pressed(minifb::KeyRepeat::Yes)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&k| pressed.contains(&k) || repeats(k) || self.mem_view.repeats(k))
+            .collect();
+        for k in keys {
+            let editing = self.mem_view.is_editing();
+            match k {
+                Key::B if !editing => events.push(Event::Break),
+                Key::G => events.push(Event::Run),
+                Key::Space => events.push(Event::Step),
+                Key::F10 => events.push(Event::StepOver),
+                Key::F11 => events.push(Event::StepOut),
+                Key::F4 => match self.mem_view.get_cursor() {
+                    Some(addr) => events.push(Event::RunTo(addr)),
+                    None => eprintln!(
+                        "[-] open the memory editor with Enter to pick the run-to address"
+                    ),
+                },
+                Key::F6 => events.push(Event::RunFrame),
+                Key::F7 => events.push(Event::RunToDraw),
+                Key::Backspace if stepping && !editing => events.push(Event::StepBack),
+                Key::Tab if !editing => {
+                    self.show_panel = !self.show_panel;
+                    self.draw_dbg = true;
+                    self.draw_fb = true;
+                }
+                Key::F8 => events.push(Event::NextFilter),
+                Key::Minus => events.push(Event::FilterStrength(false)),
+                Key::Equal => events.push(Event::FilterStrength(true)),
+                Key::F12 => events.push(Event::Screenshot),
+                Key::F9 => events.push(Event::ToggleRecording),
+                _ => {}
+            }
+            let mut changed = self.mem_view.handle_key(k, &mut session.cpu, stepping);
+            if !self.mem_view.is_editing() {
+                changed |= self.sprite_view.handle_key(k, &session.cpu);
+            }
+            if changed {
+                self.draw_dbg = true;
+                self.draw_fb = true;
+            }
+        }
+
+        while let Ok(line) = self.console.try_recv() {
+            events.push(Event::Command(line));
+        }
+
+        // click a register or memory byte to show its last writer
+        let down = self.window.get_mouse_down(minifb::MouseButton::Left);
+        if down && !self.mouse_down && self.show_panel {
+            if let Some((x, y)) = self.window.get_mouse_pos(minifb::MouseMode::Discard) {
+                let (x, y) = (x as usize, y as usize);
+                if self.reg_view.click(x, y, &session.cpu) {
+                    self.draw_dbg = true;
+                } else if let Some(addr) = self.mem_view.addr_at(x, y) {
+                    self.reg_view.select_mem(addr);
+                    self.draw_dbg = true;
+                }
+            }
+        }
+        self.mouse_down = down;
+
+        if let Some((_, dy)) = self.window.get_scroll_wheel() {
+            if dy != 0.0 {
+                self.mem_view.scroll(if dy > 0.0 { -2 } else { 2 });
+                self.draw_dbg = true;
+                self.draw_fb = true;
+            }
+        }
+
+        let size = self.window.get_size();
+        if size != self.window_size {
+            self.window_size = size;
+            self.draw_dbg = true;
+            self.draw_fb = true;
+        }
+        events
+    }
+
+    fn keys(&mut self) -> Vec<u8> {
+        remap_keys(self.window.get_keys().unwrap_or_default())
+    }
+}
+
+// minifb has no audio output
+impl AudioSink for WindowFrontend {
+    fn frame(&mut self, _: bool) {}
+}
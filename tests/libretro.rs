@@ -0,0 +1,287 @@
+// drives the libretro core like a frontend: dlopen the cdylib built next to
+// this test and run a few frames
+#![cfg(target_os = "linux")]
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+#[link(name = "dl")]
+extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *const c_char;
+}
+const RTLD_NOW: c_int = 2;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+const FG: u32 = 0x00ff0000;
+
+#[repr(C)]
+struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+struct SystemAvInfo {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+struct InputDescriptor {
+    port: c_uint,
+    device: c_uint,
+    index: c_uint,
+    id: c_uint,
+    description: *const c_char,
+}
+
+// clear, wait for a key, draw its digit, beep and wait for keys forever
+const ROM: [u8; 18] = [
+    0x00, 0xE0, // CLS
+    0xF0, 0x0A, // LD V0, K
+    0xF0, 0x29, // LD F, V0
+    0x61, 0x00, // LD V1, 0
+    0xD1, 0x15, // DRW V1, V1, 5
+    0x62, 0x03, // LD V2, 3
+    0xF2, 0x18, // LD ST, V2
+    0xF3, 0x0A, // LD V3, K
+    0x12, 0x0E, // JP 0x20E
+];
+
+// frontend state, the core calls back without a context pointer
+static PIXEL_FORMAT: AtomicU32 = AtomicU32::new(u32::MAX);
+static DESCRIPTORS: Mutex<Vec<(c_uint, String)>> = Mutex::new(Vec::new());
+static FRAME: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static AUDIO: Mutex<Vec<i16>> = Mutex::new(Vec::new());
+// held joypad button and keyboard key, u32::MAX for none
+static BUTTON: AtomicU32 = AtomicU32::new(u32::MAX);
+static KEY: AtomicU32 = AtomicU32::new(u32::MAX);
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        // SET_PIXEL_FORMAT
+        10 => {
+            PIXEL_FORMAT.store(*(data as *const c_uint), Ordering::SeqCst);
+            true
+        }
+        // SET_INPUT_DESCRIPTORS
+        11 => {
+            let mut descriptors = DESCRIPTORS.lock().unwrap();
+            let mut d = data as *const InputDescriptor;
+            while !(*d).description.is_null() {
+                let name = CStr::from_ptr((*d).description).to_string_lossy();
+                descriptors.push(((*d).id, name.into_owned()));
+                d = d.add(1);
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn video_refresh(
+    data: *const c_void,
+    width: c_uint,
+    height: c_uint,
+    pitch: usize,
+) {
+    assert_eq!((width as usize, height as usize), (WIDTH, HEIGHT));
+    let mut frame = FRAME.lock().unwrap();
+    frame.clear();
+    for y in 0..HEIGHT {
+        let row = (data as *const u8).add(y * pitch) as *const u32;
+        frame.extend_from_slice(std::slice::from_raw_parts(row, WIDTH));
+    }
+}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    AUDIO
+        .lock()
+        .unwrap()
+        .extend_from_slice(std::slice::from_raw_parts(data, 2 * frames));
+    frames
+}
+
+unsafe extern "C" fn audio_sample(_: i16, _: i16) {}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _: c_uint, id: c_uint) -> i16 {
+    let held = match device {
+        1 => BUTTON.load(Ordering::SeqCst),
+        3 => KEY.load(Ordering::SeqCst),
+        _ => u32::MAX,
+    };
+    (port == 0 && id == held) as i16
+}
+
+// the cdylib is next to the test binary in target/<profile>/deps, cargo also
+// copies it to target/<profile>
+fn core_path() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+    [deps, deps.parent().unwrap()]
+        .iter()
+        .map(|dir| dir.join("libchip8_remu.so"))
+        .find(|path| path.exists())
+        .expect("libchip8_remu.so not built")
+}
+
+struct Core {
+    lib: *mut c_void,
+}
+
+impl Core {
+    fn open() -> Core {
+        let path = CString::new(core_path().to_str().unwrap()).unwrap();
+        let lib = unsafe { dlopen(path.as_ptr(), RTLD_NOW) };
+        if lib.is_null() {
+            let err = unsafe { CStr::from_ptr(dlerror()) };
+            panic!("dlopen failed: {}", err.to_string_lossy());
+        }
+        Core { lib }
+    }
+
+    // function pointer of type `T` of the exported symbol `name`
+    unsafe fn sym<T: Copy>(&self, name: &str) -> T {
+        let cname = CString::new(name).unwrap();
+        let ptr = dlsym(self.lib, cname.as_ptr());
+        assert!(!ptr.is_null(), "missing symbol {}", name);
+        std::mem::transmute_copy(&ptr)
+    }
+
+    fn run(&self, frames: usize) {
+        let run: extern "C" fn() = unsafe { self.sym("retro_run") };
+        for _ in 0..frames {
+            run();
+        }
+    }
+}
+
+fn lit(frame: &[u32], x: usize, y: usize) -> bool {
+    frame[y * WIDTH + x] == FG
+}
+
+#[test]
+fn drive_core() {
+    let core = Core::open();
+    unsafe {
+        let api_version: extern "C" fn() -> c_uint = core.sym("retro_api_version");
+        assert_eq!(api_version(), 1);
+
+        let mut info: SystemInfo = std::mem::zeroed();
+        let get_info: extern "C" fn(*mut SystemInfo) = core.sym("retro_get_system_info");
+        get_info(&mut info);
+        assert_eq!(CStr::from_ptr(info.library_name).to_str(), Ok("chip8-remu"));
+        assert!(!info.need_fullpath);
+
+        core.sym::<extern "C" fn(unsafe extern "C" fn(c_uint, *mut c_void) -> bool)>(
+            "retro_set_environment",
+        )(environment);
+        core.sym::<extern "C" fn(unsafe extern "C" fn(*const c_void, c_uint, c_uint, usize))>(
+            "retro_set_video_refresh",
+        )(video_refresh);
+        core.sym::<extern "C" fn(unsafe extern "C" fn(*const i16, usize) -> usize)>(
+            "retro_set_audio_sample_batch",
+        )(audio_sample_batch);
+        core.sym::<extern "C" fn(unsafe extern "C" fn(i16, i16))>("retro_set_audio_sample")(
+            audio_sample,
+        );
+        core.sym::<extern "C" fn(unsafe extern "C" fn())>("retro_set_input_poll")(input_poll);
+        core.sym::<extern "C" fn(unsafe extern "C" fn(c_uint, c_uint, c_uint, c_uint) -> i16)>(
+            "retro_set_input_state",
+        )(input_state);
+        core.sym::<extern "C" fn()>("retro_init")();
+
+        let game = GameInfo {
+            path: std::ptr::null(),
+            data: ROM.as_ptr() as *const c_void,
+            size: ROM.len(),
+            meta: std::ptr::null(),
+        };
+        let load: extern "C" fn(*const GameInfo) -> bool = core.sym("retro_load_game");
+        assert!(load(&game));
+        // XRGB8888
+        assert_eq!(PIXEL_FORMAT.load(Ordering::SeqCst), 1);
+        let descriptors = DESCRIPTORS.lock().unwrap().clone();
+        assert_eq!(descriptors.len(), 16);
+        assert!(descriptors.contains(&(0, "Key 5".to_string())));
+
+        let mut av: SystemAvInfo = std::mem::zeroed();
+        core.sym::<extern "C" fn(*mut SystemAvInfo)>("retro_get_system_av_info")(&mut av);
+        assert_eq!((av.base_width, av.base_height), (64, 32));
+        assert_eq!(av.fps, 60.0);
+
+        let memory: extern "C" fn(c_uint) -> *mut c_void = core.sym("retro_get_memory_data");
+        let memory_size: extern "C" fn(c_uint) -> usize = core.sym("retro_get_memory_size");
+        assert_eq!(memory_size(2), 4096);
+        let ram = std::slice::from_raw_parts(memory(2) as *const u8, 4096);
+        assert_eq!(&ram[0x200..0x204], &ROM[..4]);
+
+        // no key, the game waits and is silent
+        core.run(2);
+        assert!(FRAME.lock().unwrap().iter().all(|&p| p != FG));
+        assert!(AUDIO.lock().unwrap().iter().all(|&s| s == 0));
+
+        let size: extern "C" fn() -> usize = core.sym("retro_serialize_size");
+        let serialize: extern "C" fn(*mut c_void, usize) -> bool = core.sym("retro_serialize");
+        let unserialize: extern "C" fn(*const c_void, usize) -> bool =
+            core.sym("retro_unserialize");
+        let mut state = vec![0u8; size()];
+        assert!(serialize(state.as_mut_ptr() as *mut c_void, state.len()));
+
+        // joypad B is key 5, its digit has a top bar and the left pixel of
+        // the second row
+        BUTTON.store(0, Ordering::SeqCst);
+        AUDIO.lock().unwrap().clear();
+        core.run(2);
+        {
+            let frame = FRAME.lock().unwrap();
+            assert!((0..4).all(|x| lit(&frame, x, 0)));
+            assert!(lit(&frame, 0, 1) && !lit(&frame, 3, 1));
+        }
+        let audio = AUDIO.lock().unwrap().clone();
+        assert_eq!(audio.len(), 2 * 2 * 800);
+        assert!(audio.iter().any(|&s| s != 0));
+
+        // back to waiting for a key, then the keyboard 'x' is key 0
+        BUTTON.store(u32::MAX, Ordering::SeqCst);
+        assert!(unserialize(state.as_ptr() as *const c_void, state.len()));
+        core.run(1);
+        assert!(FRAME.lock().unwrap().iter().all(|&p| p != FG));
+        KEY.store(b'x' as u32, Ordering::SeqCst);
+        core.run(1);
+        {
+            let frame = FRAME.lock().unwrap();
+            assert!(lit(&frame, 0, 1) && lit(&frame, 3, 1));
+        }
+
+        // a truncated state is rejected
+        assert!(!unserialize(state.as_ptr() as *const c_void, 10));
+
+        core.sym::<extern "C" fn()>("retro_unload_game")();
+        core.sym::<extern "C" fn()>("retro_deinit")();
+    }
+}